@@ -0,0 +1,285 @@
+// Arbitrary-precision natural numbers, for the `#big_*` primitives. Digits are in base 2^32,
+// from the least significant, and never end with a zero, so that zero has no digits
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BigNum(Vec<u32>);
+
+impl BigNum {
+    pub fn from_usize(n: usize) -> Self {
+        let mut n = n as u64;
+        let mut digits = vec![];
+        while n > 0 {
+            digits.push(n as u32);
+            n >>= 32;
+        }
+        Self(digits)
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        if self.0.len() > 2 {
+            return None;
+        }
+        let n = self.0.iter().rev().fold(0, |n, d| n << 32 | *d as u64);
+        usize::try_from(n).ok()
+    }
+
+    fn trim(mut self) -> Self {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn digit(&self, i: usize) -> u64 {
+        self.0.get(i).copied().unwrap_or(0) as u64
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.0.last() {
+            Some(last) => self.0.len() * 32 - last.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let len = self.0.len().max(other.0.len());
+        let mut digits = Vec::with_capacity(len + 1);
+        let mut carry = 0;
+        for i in 0..len {
+            let sum = self.digit(i) + other.digit(i) + carry;
+            digits.push(sum as u32);
+            carry = sum >> 32;
+        }
+        digits.push(carry as u32);
+        Self(digits).trim()
+    }
+
+    // `None` if `other` is greater, since the result would be negative
+    pub fn sub(&self, other: &Self) -> Option<Self> {
+        if self < other {
+            return None;
+        }
+        let mut digits = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for i in 0..self.0.len() {
+            let diff = self.digit(i) as i64 - other.digit(i) as i64 - borrow;
+            borrow = (diff < 0) as i64;
+            digits.push((diff + (borrow << 32)) as u32);
+        }
+        Some(Self(digits).trim())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        let mut digits = vec![0; self.0.len() + other.0.len()];
+        for (i, x) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, y) in other.0.iter().enumerate() {
+                // At most (2^32 - 1)^2 + 2 * (2^32 - 1), which fits in 64 bits
+                let cur = digits[i + j] as u64 + *x as u64 * *y as u64 + carry;
+                digits[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            digits[i + other.0.len()] = carry as u32;
+        }
+        Self(digits).trim()
+    }
+
+    // Quotient and remainder, or `None` when dividing by zero. A divisor of a single digit is
+    // handled digit by digit. Otherwise this is Knuth's algorithm D, which estimates each digit
+    // of the quotient from the two leading digits of the remainder and of the divisor, once
+    // both are shifted so that the leading digit of the divisor has its top bit set
+    pub fn div_rem(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        if let [d] = other.0[..] {
+            let mut quotient = vec![0; self.0.len()];
+            let mut rem = 0;
+            for i in (0..self.0.len()).rev() {
+                let cur = rem << 32 | self.digit(i);
+                quotient[i] = (cur / d as u64) as u32;
+                rem = cur % d as u64;
+            }
+            return Some((Self(quotient).trim(), Self::from_usize(rem as usize)));
+        }
+        if self < other {
+            return Some((Self::default(), self.clone()));
+        }
+        let shift = other.0.last().unwrap().leading_zeros() as usize;
+        let v = other.shl(shift).0;
+        let mut u = self.shl(shift).0;
+        let (n, m) = (v.len(), self.0.len() - v.len());
+        u.resize(m + n + 1, 0);
+        let mut quotient = vec![0; m + 1];
+        for j in (0..=m).rev() {
+            // The estimate is at most 2 more than the digit of the quotient, and checking it
+            // against the next digit of the remainder corrects it in almost every case
+            let top = (u[j + n] as u64) << 32 | u[j + n - 1] as u64;
+            let (mut q, mut r) = (top / v[n - 1] as u64, top % v[n - 1] as u64);
+            while q >> 32 != 0 || q * v[n - 2] as u64 > (r << 32 | u[j + n - 2] as u64) {
+                q -= 1;
+                r += v[n - 1] as u64;
+                if r >> 32 != 0 {
+                    break;
+                }
+            }
+            // Subtracts `q` times the divisor from the remainder, adding it back once when `q`
+            // was still 1 too large
+            let (mut carry, mut borrow) = (0, 0);
+            for i in 0..=n {
+                let prod = q * v.get(i).copied().unwrap_or(0) as u64 + carry;
+                carry = prod >> 32;
+                let diff = u[i + j] as i64 - (prod as u32) as i64 - borrow;
+                borrow = (diff < 0) as i64;
+                u[i + j] = (diff + (borrow << 32)) as u32;
+            }
+            if borrow != 0 {
+                q -= 1;
+                let mut carry = 0;
+                for i in 0..=n {
+                    let sum = u[i + j] as u64 + v.get(i).copied().unwrap_or(0) as u64 + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+            }
+            quotient[j] = q as u32;
+        }
+        u.truncate(n);
+        Some((Self(quotient).trim(), Self(u).trim().shr(shift)))
+    }
+
+    pub fn shl(&self, n: usize) -> Self {
+        if self.is_zero() {
+            return Self::default();
+        }
+        let (zeros, bits) = (n / 32, n % 32);
+        let mut digits = vec![0; zeros];
+        let mut carry = 0;
+        for d in &self.0 {
+            let shifted = (*d as u64) << bits | carry;
+            digits.push(shifted as u32);
+            carry = shifted >> 32;
+        }
+        digits.push(carry as u32);
+        Self(digits).trim()
+    }
+
+    pub fn shr(&self, n: usize) -> Self {
+        let (dropped, bits) = (n / 32, n % 32);
+        let digits = (dropped..self.0.len())
+            .map(|i| ((self.digit(i + 1) << 32 | self.digit(i)) >> bits) as u32)
+            .collect();
+        Self(digits).trim()
+    }
+}
+
+impl Ord for BigNum {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().cmp(&other.0.len());
+        len.then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigNum {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Decimal, computed by groups of 9 digits
+impl fmt::Display for BigNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let base = Self::from_usize(1_000_000_000);
+        let mut groups = vec![];
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (quotient, rem) = n.div_rem(&base).unwrap();
+            groups.push(rem.to_usize().unwrap());
+            n = quotient;
+        }
+        match groups.pop() {
+            Some(first) => write!(f, "{first}")?,
+            None => write!(f, "0")?,
+        }
+        groups
+            .iter()
+            .rev()
+            .try_for_each(|group| write!(f, "{group:09}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(n: u128) -> BigNum {
+        BigNum::from_usize(n as usize).add(&BigNum::from_usize((n >> 64) as usize).shl(64))
+    }
+
+    #[test]
+    fn operations_agree_with_u128() {
+        let values = [
+            0,
+            1,
+            7,
+            1 << 32,
+            u32::MAX as u128,
+            u64::MAX as u128,
+            1 << 100,
+            3 << 90,
+        ];
+        for x in values {
+            for y in values {
+                assert_eq!(big(x).cmp(&big(y)), x.cmp(&y), "{x} cmp {y}");
+                if let Some(sum) = x.checked_add(y) {
+                    assert_eq!(big(x).add(&big(y)), big(sum), "{x} + {y}");
+                }
+                assert_eq!(big(x).sub(&big(y)), x.checked_sub(y).map(big), "{x} - {y}");
+                if let Some(product) = x.checked_mul(y) {
+                    assert_eq!(big(x).mul(&big(y)), big(product), "{x} * {y}");
+                }
+                let div_rem = x.checked_div(y).map(|q| (big(q), big(x % y)));
+                assert_eq!(big(x).div_rem(&big(y)), div_rem, "{x} / {y}");
+            }
+            for n in [0, 1, 31, 32, 33, 64] {
+                assert_eq!(big(x).shr(n), big(x >> n), "{x} >> {n}");
+                if x.leading_zeros() as usize >= n {
+                    assert_eq!(big(x).shl(n), big(x << n), "{x} << {n}");
+                }
+            }
+        }
+    }
+
+    // Divisors of two to four digits, made of the digits where the estimates of algorithm D
+    // are most often wrong
+    #[test]
+    fn division_agrees_with_u128() {
+        let digits = [0, 1, 0x7fff_ffff, 0x8000_0000, u32::MAX];
+        let values = (0..digits.len().pow(4)).map(|i| {
+            (0..4).fold(0, |n, k| {
+                n << 32 | digits[i / digits.len().pow(k) % digits.len()] as u128
+            })
+        });
+        let values = values.collect::<Vec<_>>();
+        for x in &values {
+            for y in values.iter().filter(|y| **y > u32::MAX as u128) {
+                let div_rem = Some((big(x / y), big(x % y)));
+                assert_eq!(big(*x).div_rem(&big(*y)), div_rem, "{x} / {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn decimal() {
+        assert_eq!(BigNum::default().to_string(), "0");
+        let n = BigNum::from_usize(1).shl(100);
+        assert_eq!(n.to_string(), "1267650600228229401496703205376");
+        assert_eq!(n.to_usize(), None);
+    }
+}
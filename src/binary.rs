@@ -0,0 +1,737 @@
+use crate::expr::{Atom, Declaration, Definition, Expression, Type, OPERATIONS, PRIMITIVES};
+use crate::lexer::Span;
+use crate::module::{self, CheckError, Module};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Binary encoding of a module, for frontends to hand over their output without printing it.
+// A file starts with `MAGIC` and the version of the encoding, followed by a table of all the
+// names and comments, and by the definitions and extern declarations, where strings are
+// indices into the table.
+// Integers are encoded in unsigned LEB128, and variants by a tag byte. Spans are not encoded
+static MAGIC: &[u8; 4] = b"\0RIR";
+static VERSION: u64 = 2;
+
+// Expressions and types nested deeper are rejected, since they are decoded recursively. The
+// bodies of `let` expressions do not count, so that long functions can be decoded
+static MAX_DEPTH: usize = 256;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion(u64),
+    UnexpectedEnd,
+    Overflow,
+    InvalidUtf8,
+    // Kind of value, and its unknown tag
+    BadTag(&'static str, u8),
+    BadString(u64),
+    Duplicate(String),
+    TrailingBytes,
+    TooDeep,
+    NotFinite(f64),
+    Check(CheckError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "Not an encoded module"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "Unsupported encoding version {version}, expected {VERSION}"
+                )
+            }
+            DecodeError::UnexpectedEnd => write!(f, "Unexpected end of encoded module"),
+            DecodeError::Overflow => write!(f, "Encoded integer is too large"),
+            DecodeError::InvalidUtf8 => write!(f, "Encoded string is not valid UTF-8"),
+            DecodeError::BadTag(kind, tag) => write!(f, "Unknown {kind} tag {tag}"),
+            DecodeError::BadString(index) => write!(f, "String {index} is not in the table"),
+            DecodeError::Duplicate(name) => write!(f, "Function `{name}` is encoded twice"),
+            DecodeError::TrailingBytes => write!(f, "Unexpected bytes after encoded module"),
+            DecodeError::TooDeep => write!(f, "Encoded expression is nested too deeply"),
+            DecodeError::NotFinite(x) => write!(f, "Encoded float {x:?} is not finite"),
+            DecodeError::Check(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+// Definitions and externs are sorted by name, so that a module always has the same encoding
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut defs = module.toplevel().values().collect::<Vec<_>>();
+    defs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut externs = module.externs().values().collect::<Vec<_>>();
+    externs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut encoder = Encoder {
+        strings: HashMap::new(),
+        table: vec![],
+        out: vec![],
+    };
+    encoder.uint(defs.len() as u64);
+    defs.iter().for_each(|def| encoder.definition(def));
+    encoder.uint(externs.len() as u64);
+    externs.iter().for_each(|decl| encoder.declaration(decl));
+
+    let mut out = MAGIC.to_vec();
+    write_uint(&mut out, VERSION);
+    write_uint(&mut out, encoder.table.len() as u64);
+    for string in encoder.table {
+        write_uint(&mut out, string.len() as u64);
+        out.extend_from_slice(string.as_bytes());
+    }
+    out.extend(encoder.out);
+    out
+}
+
+fn write_uint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8)
+}
+
+struct Encoder<'a> {
+    strings: HashMap<&'a str, u64>,
+    // Strings in the order of their first occurrence
+    table: Vec<&'a str>,
+    out: Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    fn uint(&mut self, n: u64) {
+        write_uint(&mut self.out, n)
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.out.push(tag)
+    }
+
+    fn string(&mut self, string: &'a str) {
+        let index = *self.strings.entry(string).or_insert_with(|| {
+            self.table.push(string);
+            self.table.len() as u64 - 1
+        });
+        self.uint(index)
+    }
+
+    fn strings(&mut self, strings: &'a [String]) {
+        self.uint(strings.len() as u64);
+        strings.iter().for_each(|string| self.string(string));
+    }
+
+    fn signature(
+        &mut self,
+        name: &'a str,
+        params: &'a [String],
+        param_types: &'a [Option<Type>],
+        ret_type: &'a Option<Type>,
+    ) {
+        self.string(name);
+        self.strings(params);
+        param_types.iter().for_each(|typ| self.maybe_type(typ));
+        self.maybe_type(ret_type);
+    }
+
+    fn definition(&mut self, def: &'a Definition) {
+        self.signature(&def.name, &def.params, &def.param_types, &def.ret_type);
+        self.strings(&def.comments);
+        self.expr(&def.body);
+    }
+
+    fn declaration(&mut self, decl: &'a Declaration) {
+        self.signature(&decl.name, &decl.params, &decl.param_types, &decl.ret_type);
+    }
+
+    fn maybe_type(&mut self, typ: &'a Option<Type>) {
+        match typ {
+            None => self.tag(0),
+            Some(typ) => {
+                self.tag(1);
+                self.typ(typ)
+            }
+        }
+    }
+
+    fn types(&mut self, types: &'a [Type]) {
+        self.uint(types.len() as u64);
+        types.iter().for_each(|typ| self.typ(typ));
+    }
+
+    fn typ(&mut self, typ: &'a Type) {
+        match typ {
+            Type::Int => self.tag(0),
+            Type::Fn(params, ret) => {
+                self.tag(1);
+                self.types(params);
+                self.typ(ret);
+            }
+            Type::Con(name, args) => {
+                self.tag(2);
+                self.string(name);
+                self.types(args);
+            }
+            Type::Var(name) => {
+                self.tag(3);
+                self.string(name);
+            }
+            Type::Rec(name, body) => {
+                self.tag(4);
+                self.string(name);
+                self.typ(body);
+            }
+        }
+    }
+
+    fn atom(&mut self, atom: &'a Atom) {
+        match atom {
+            Atom::Var(x) => {
+                self.tag(0);
+                self.string(x);
+            }
+            Atom::Lit(n) => {
+                self.tag(1);
+                self.uint(*n as u64);
+            }
+            Atom::Float(x) => {
+                self.tag(2);
+                self.out.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+    }
+
+    fn atoms(&mut self, atoms: &'a [Atom]) {
+        self.uint(atoms.len() as u64);
+        atoms.iter().for_each(|atom| self.atom(atom));
+    }
+
+    fn expr(&mut self, expr: &'a Expression) {
+        match expr {
+            Expression::Unit(atom) => {
+                self.tag(0);
+                self.atom(atom);
+            }
+            Expression::Let(x, val, body) => {
+                self.tag(1);
+                self.string(x);
+                self.expr(val);
+                self.expr(body);
+            }
+            Expression::Apply(f, args) => {
+                self.tag(2);
+                self.string(f);
+                self.atoms(args);
+            }
+            Expression::Call(f, args) => {
+                self.tag(3);
+                self.string(f);
+                self.atoms(args);
+            }
+            Expression::Papp(f, args) => {
+                self.tag(4);
+                self.string(f);
+                self.atoms(args);
+            }
+            Expression::Match(atom, cases, default) => {
+                self.tag(5);
+                self.atom(atom);
+                self.uint(cases.len() as u64);
+                for (n, expr) in cases {
+                    self.uint(*n as u64);
+                    self.expr(expr);
+                }
+                match default {
+                    None => self.tag(0),
+                    Some(expr) => {
+                        self.tag(1);
+                        self.expr(expr);
+                    }
+                }
+            }
+            Expression::Operate(op, x, y) => {
+                self.tag(6);
+                let index = OPERATIONS.iter().position(|o| o == op).unwrap();
+                self.tag(index as u8);
+                self.atom(x);
+                self.atom(y);
+            }
+            Expression::Prim(prim, args) => {
+                self.tag(7);
+                let index = PRIMITIVES.iter().position(|p| p == prim).unwrap();
+                self.tag(index as u8);
+                self.atoms(args);
+            }
+            // Byte strings may not be UTF-8, so they are not in the string table
+            Expression::Str(bytes) => {
+                self.tag(8);
+                self.uint(bytes.len() as u64);
+                self.out.extend_from_slice(bytes);
+            }
+            Expression::Store(x) => {
+                self.tag(9);
+                self.atom(x);
+            }
+            Expression::Fetch(p) => {
+                self.tag(10);
+                self.string(p);
+            }
+            Expression::Update(p, x) => {
+                self.tag(11);
+                self.string(p);
+                self.atom(x);
+            }
+            Expression::Thunk(f, args) => {
+                self.tag(12);
+                self.string(f);
+                self.atoms(args);
+            }
+            Expression::Force(t) => {
+                self.tag(13);
+                self.string(t);
+            }
+            Expression::Throw(x) => {
+                self.tag(14);
+                self.atom(x);
+            }
+            Expression::Catch(body, x, handler) => {
+                self.tag(15);
+                self.expr(body);
+                self.string(x);
+                self.expr(handler);
+            }
+            Expression::Perform(x) => {
+                self.tag(16);
+                self.atom(x);
+            }
+            Expression::Handle(body, x, k, handler) => {
+                self.tag(17);
+                self.expr(body);
+                self.string(x);
+                self.string(k);
+                self.expr(handler);
+            }
+            Expression::Resume(k, x) => {
+                self.tag(18);
+                self.string(k);
+                self.atom(x);
+            }
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Module, DecodeError> {
+    let mut decoder = Decoder {
+        bytes,
+        table: vec![],
+        depth: 0,
+    };
+    if decoder.take(MAGIC.len())? != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = decoder.uint()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    for _ in 0..decoder.uint()? {
+        let len = decoder.usize()?;
+        let string = std::str::from_utf8(decoder.take(len)?);
+        let string = string.map_err(|_| DecodeError::InvalidUtf8)?;
+        decoder.table.push(string.to_owned());
+    }
+    let defs = decoder.list(Decoder::definition)?;
+    let externs = decoder.list(Decoder::declaration)?;
+    if !decoder.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    let mut names = HashSet::new();
+    let defined = defs.iter().map(|def| &def.name);
+    if let Some(name) = defined
+        .chain(externs.iter().map(|decl| &decl.name))
+        .find(|name| !names.insert(*name))
+    {
+        return Err(DecodeError::Duplicate(name.clone()));
+    }
+    let module = Module::new_unchecked(defs.into_iter()).with_externs(externs.into_iter());
+    module::check(&module).map_err(DecodeError::Check)?;
+    Ok(module)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    table: Vec<String>,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn tag(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, DecodeError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.tag()?;
+            let bits = (byte & 0x7f) as u64;
+            if bits << shift >> shift != bits {
+                return Err(DecodeError::Overflow);
+            }
+            n |= bits << shift;
+            if byte < 0x80 {
+                return Ok(n);
+            }
+        }
+        Err(DecodeError::Overflow)
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        self.uint()?.try_into().map_err(|_| DecodeError::Overflow)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let index = self.uint()?;
+        let string = self.table.get(index as usize);
+        string.cloned().ok_or(DecodeError::BadString(index))
+    }
+
+    // Lengths are not trusted to preallocate, as they could be arbitrarily large
+    fn list<T>(
+        &mut self,
+        item: fn(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        let len = self.uint()?;
+        let mut items = vec![];
+        for _ in 0..len {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn declaration(&mut self) -> Result<Declaration, DecodeError> {
+        let name = self.string()?;
+        let params = self.list(Self::string)?;
+        let param_types = params
+            .iter()
+            .map(|_| self.maybe_type())
+            .collect::<Result<_, _>>()?;
+        let ret_type = self.maybe_type()?;
+        Ok(Declaration {
+            name,
+            params,
+            param_types,
+            ret_type,
+        })
+    }
+
+    fn definition(&mut self) -> Result<Definition, DecodeError> {
+        let decl = self.declaration()?;
+        let comments = self.list(Self::string)?;
+        let body = self.expr()?;
+        Ok(Definition {
+            name: decl.name,
+            params: decl.params,
+            param_types: decl.param_types,
+            ret_type: decl.ret_type,
+            body,
+            span: Span::default(),
+            spans: vec![],
+            comments,
+        })
+    }
+
+    fn maybe_type(&mut self) -> Result<Option<Type>, DecodeError> {
+        match self.tag()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.typ()?)),
+            tag => Err(DecodeError::BadTag("optional type", tag)),
+        }
+    }
+
+    fn nested<T>(
+        &mut self,
+        item: fn(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<T, DecodeError> {
+        if self.depth == MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        self.depth += 1;
+        let item = item(self);
+        self.depth -= 1;
+        item
+    }
+
+    fn typ(&mut self) -> Result<Type, DecodeError> {
+        self.nested(Self::type_variant)
+    }
+
+    fn type_variant(&mut self) -> Result<Type, DecodeError> {
+        match self.tag()? {
+            0 => Ok(Type::Int),
+            1 => Ok(Type::Fn(self.list(Self::typ)?, self.typ()?.into())),
+            2 => Ok(Type::Con(self.string()?, self.list(Self::typ)?)),
+            3 => Ok(Type::Var(self.string()?)),
+            4 => Ok(Type::Rec(self.string()?, self.typ()?.into())),
+            tag => Err(DecodeError::BadTag("type", tag)),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Atom, DecodeError> {
+        match self.tag()? {
+            0 => Ok(Atom::Var(self.string()?)),
+            1 => Ok(Atom::Lit(self.usize()?)),
+            2 => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                match f64::from_le_bytes(bytes) {
+                    x if x.is_finite() => Ok(Atom::Float(x)),
+                    x => Err(DecodeError::NotFinite(x)),
+                }
+            }
+            tag => Err(DecodeError::BadTag("atom", tag)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expression, DecodeError> {
+        self.nested(Self::let_chain)
+    }
+
+    fn let_chain(&mut self) -> Result<Expression, DecodeError> {
+        let mut bindings = vec![];
+        let mut tag = self.tag()?;
+        while tag == 1 {
+            bindings.push((self.string()?, self.expr()?));
+            tag = self.tag()?;
+        }
+        let body = match tag {
+            5 => self.matching()?,
+            15 => self.catch()?,
+            17 => self.handle()?,
+            tag => self.variant(tag)?,
+        };
+        let bindings = bindings.into_iter().rev();
+        Ok(bindings.fold(body, |body, (x, val)| {
+            Expression::Let(x, val.into(), body.into())
+        }))
+    }
+
+    // Expressions that hold others are decoded by small functions, to keep deep nesting within
+    // the stack
+    fn matching(&mut self) -> Result<Expression, DecodeError> {
+        let atom = self.atom()?;
+        let cases = self.list(|decoder| Ok((decoder.usize()?, decoder.expr()?)))?;
+        let default = match self.tag()? {
+            0 => None,
+            1 => Some(self.expr()?.into()),
+            tag => return Err(DecodeError::BadTag("default branch", tag)),
+        };
+        Ok(Expression::Match(atom, cases, default))
+    }
+
+    fn catch(&mut self) -> Result<Expression, DecodeError> {
+        Ok(Expression::Catch(
+            self.expr()?.into(),
+            self.string()?,
+            self.expr()?.into(),
+        ))
+    }
+
+    fn handle(&mut self) -> Result<Expression, DecodeError> {
+        Ok(Expression::Handle(
+            self.expr()?.into(),
+            self.string()?,
+            self.string()?,
+            self.expr()?.into(),
+        ))
+    }
+
+    // Expressions that hold no other expression, whose tag has already been read
+    fn variant(&mut self, tag: u8) -> Result<Expression, DecodeError> {
+        match tag {
+            0 => Ok(Expression::Unit(self.atom()?)),
+            2 => Ok(Expression::Apply(self.string()?, self.list(Self::atom)?)),
+            3 => Ok(Expression::Call(self.string()?, self.list(Self::atom)?)),
+            4 => Ok(Expression::Papp(self.string()?, self.list(Self::atom)?)),
+            6 => {
+                let tag = self.tag()?;
+                let op = OPERATIONS.get(tag as usize);
+                let op = *op.ok_or(DecodeError::BadTag("operation", tag))?;
+                Ok(Expression::Operate(op, self.atom()?, self.atom()?))
+            }
+            7 => {
+                let tag = self.tag()?;
+                let prim = PRIMITIVES.get(tag as usize);
+                let prim = *prim.ok_or(DecodeError::BadTag("primitive", tag))?;
+                Ok(Expression::Prim(prim, self.list(Self::atom)?))
+            }
+            8 => {
+                let len = self.usize()?;
+                Ok(Expression::Str(self.take(len)?.to_vec()))
+            }
+            9 => Ok(Expression::Store(self.atom()?)),
+            10 => Ok(Expression::Fetch(self.string()?)),
+            11 => Ok(Expression::Update(self.string()?, self.atom()?)),
+            12 => Ok(Expression::Thunk(self.string()?, self.list(Self::atom)?)),
+            13 => Ok(Expression::Force(self.string()?)),
+            14 => Ok(Expression::Throw(self.atom()?)),
+            16 => Ok(Expression::Perform(self.atom()?)),
+            18 => Ok(Expression::Resume(self.string()?, self.atom()?)),
+            tag => Err(DecodeError::BadTag("expression", tag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{self, Rng};
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    fn parse(source: &str) -> Module {
+        let tokens = LexerScanner::new(source).collect::<Result<Vec<_>, _>>();
+        let mut parser = ParserScanner::new(tokens.unwrap().into_iter());
+        let header = parser.parse_header().unwrap();
+        let defs = parser.map(Result::unwrap).collect::<Vec<_>>();
+        let module = Module::new_unchecked(defs.into_iter());
+        let module = module.with_externs(header.externs.into_iter());
+        module::check(&module).unwrap();
+        module
+    }
+
+    #[test]
+    fn modules_round_trip() {
+        for seed in 0..200 {
+            let module = Module::new(generate::module(&mut Rng::new(seed)).into_iter());
+            let bytes = encode(&module);
+            let decoded = decode(&bytes).unwrap();
+            assert_eq!(decoded.toplevel(), module.toplevel(), "seed {seed}");
+            assert_eq!(encode(&decoded), bytes, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn annotations_and_comments_are_kept() {
+        let module = parse(
+            "extern fn (print x: Int) -> Int;
+
+            // the identity
+            fn (id x: a) -> a {
+              x
+            }
+
+            fn (main) -> Rec(l, List(Fn(Int, l) -> Int)) {
+              let f = (papp id);
+              match 3 {
+                0 => { (apply f 1) }
+                _ => { (<< 1 2) }
+              }
+            }",
+        );
+        let decoded = decode(&encode(&module)).unwrap();
+        assert_eq!(decoded.toplevel(), module.toplevel());
+        assert_eq!(decoded.externs(), module.externs());
+    }
+
+    #[test]
+    fn runtime_expressions_are_kept() {
+        let sources = [
+            include_str!("../corpus/io.ir"),
+            include_str!("../corpus/strings.ir"),
+            include_str!("../corpus/arrays.ir"),
+            include_str!("../corpus/lazy.ir"),
+            include_str!("../corpus/thunks.ir"),
+            include_str!("../corpus/floats.ir"),
+            include_str!("../corpus/bignums.ir"),
+            include_str!("../corpus/exceptions.ir"),
+            include_str!("../corpus/generators.ir"),
+            include_str!("../corpus/fibers.ir"),
+            include_str!("../corpus/channels.ir"),
+        ];
+        for source in sources {
+            let module = parse(source);
+            let decoded = decode(&encode(&module)).unwrap();
+            assert_eq!(decoded.toplevel(), module.toplevel());
+        }
+    }
+
+    #[test]
+    fn headers_are_checked() {
+        let mut bytes = encode(&parse("fn (main) { 0 }"));
+        assert!(matches!(decode(&bytes[1..]), Err(DecodeError::BadMagic)));
+        bytes[MAGIC.len()] = 3;
+        assert!(matches!(
+            decode(&bytes),
+            Err(DecodeError::UnsupportedVersion(3))
+        ));
+    }
+
+    #[test]
+    fn truncated_modules_are_rejected() {
+        let bytes = encode(&Module::new(generate::module(&mut Rng::new(0)).into_iter()));
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "length {len}");
+        }
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert!(matches!(decode(&bytes), Err(DecodeError::TrailingBytes)));
+    }
+
+    #[test]
+    fn nesting_is_bounded() {
+        let header = [b"\0RIR".as_slice(), &[2, 1, 1, b'x', 1, 0, 0, 0, 0]].concat();
+        let bytes = [header.as_slice(), &[15].repeat(200_000)].concat();
+        assert!(matches!(decode(&bytes), Err(DecodeError::TooDeep)));
+        let bytes = [header.as_slice(), &[1, 0, 0, 1, 0].repeat(1000), &[0, 1, 0]].concat();
+        let mut decoder = Decoder {
+            bytes: &bytes[header.len()..],
+            table: vec!["x".into()],
+            depth: 0,
+        };
+        let body = (0..1000).fold(Expression::Unit(Atom::Lit(0)), |body, _| {
+            let val = Expression::Unit(Atom::Lit(0));
+            Expression::Let("x".into(), val.into(), body.into())
+        });
+        assert_eq!(decoder.expr().unwrap(), body);
+    }
+
+    #[test]
+    fn floats_are_finite() {
+        let mut bytes = encode(&parse("fn (main) { 1.5 }"));
+        let pos = bytes
+            .windows(8)
+            .position(|bytes| bytes == 1.5f64.to_le_bytes());
+        let pos = pos.unwrap();
+        bytes[pos..pos + 8].copy_from_slice(&f64::NAN.to_le_bytes());
+        match decode(&bytes) {
+            Err(err) => assert_eq!(err.to_string(), "Encoded float NaN is not finite"),
+            Ok(_) => panic!("Expected a decoding error"),
+        }
+    }
+
+    #[test]
+    fn decoded_modules_are_checked() {
+        let module = Module::new_unchecked(
+            parse("fn (main) { (f 1) }\nfn (f x) { x }")
+                .into_parts()
+                .0
+                .map(|mut def| {
+                    if def.name == "main" {
+                        def.body = Expression::Call("f".into(), vec![]);
+                    }
+                    def
+                }),
+        );
+        assert!(matches!(
+            decode(&encode(&module)),
+            Err(DecodeError::Check(_))
+        ));
+    }
+}
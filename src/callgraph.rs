@@ -0,0 +1,269 @@
+use crate::expr::Expression;
+use crate::module::Module;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    // The function is called directly
+    Call,
+    // A closure of the function is created with `papp`
+    Papp,
+}
+
+// Graph of the references between the definitions of a module, leaving out extern functions.
+// Functions are kept sorted by name, so that traversals and exports are deterministic
+pub struct CallGraph<'a> {
+    edges: BTreeMap<&'a str, BTreeSet<(&'a str, EdgeKind)>>,
+}
+
+impl<'a> CallGraph<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        let edges = module
+            .toplevel()
+            .values()
+            .map(|def| {
+                let mut edges = BTreeSet::new();
+                references(&def.body, &mut edges);
+                edges.retain(|(callee, _)| module.get(callee).is_some());
+                (def.name.as_str(), edges)
+            })
+            .collect();
+        Self { edges }
+    }
+
+    pub fn callees(&self, name: &str) -> impl Iterator<Item = &'a str> + '_ {
+        self.edges[name].iter().map(|(callee, _)| *callee)
+    }
+
+    // Functions that can be reached from `root`, including itself
+    pub fn reachable(&self, root: &'a str) -> HashSet<&'a str> {
+        let mut seen = HashSet::from([root]);
+        let mut stack = vec![root];
+        while let Some(name) = stack.pop() {
+            for callee in self.callees(name) {
+                if seen.insert(callee) {
+                    stack.push(callee);
+                }
+            }
+        }
+        seen
+    }
+
+    // Strongly connected components, computed with Tarjan's algorithm. Components are given
+    // with callees before callers, so each one only depends on itself and the previous ones
+    pub fn components(&self) -> Vec<Vec<&'a str>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: HashMap::new(),
+            lowlink: HashMap::new(),
+            stack: vec![],
+            components: vec![],
+        };
+        for name in self.edges.keys() {
+            if !tarjan.index.contains_key(name) {
+                tarjan.visit(name);
+            }
+        }
+        tarjan.components
+    }
+
+    // A function is recursive if it belongs to a cycle, possibly through other functions
+    pub fn recursive_groups(&self) -> Vec<Vec<&'a str>> {
+        self.components()
+            .into_iter()
+            .filter(|group| match group[..] {
+                [name] => self.callees(name).any(|callee| callee == name),
+                _ => true,
+            })
+            .collect()
+    }
+
+    // Export in Graphviz DOT. Partial applications are dashed, and recursive groups are
+    // drawn as clusters
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph callgraph {\n");
+        for (i, group) in self.recursive_groups().iter().enumerate() {
+            writeln!(dot, "  subgraph cluster_{i} {{").unwrap();
+            writeln!(dot, "    style = dashed;").unwrap();
+            group
+                .iter()
+                .for_each(|name| writeln!(dot, "    \"{name}\";").unwrap());
+            writeln!(dot, "  }}").unwrap();
+        }
+        for (name, edges) in self.edges.iter() {
+            writeln!(dot, "  \"{name}\";").unwrap();
+            for (callee, kind) in edges {
+                let style = match kind {
+                    EdgeKind::Call => "",
+                    EdgeKind::Papp => " [style = dashed]",
+                };
+                writeln!(dot, "  \"{name}\" -> \"{callee}\"{style};").unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+struct Tarjan<'a, 'g> {
+    graph: &'g CallGraph<'a>,
+    index: HashMap<&'a str, usize>,
+    lowlink: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    components: Vec<Vec<&'a str>>,
+}
+
+impl<'a, 'g> Tarjan<'a, 'g> {
+    fn visit(&mut self, name: &'a str) {
+        let index = self.index.len();
+        self.index.insert(name, index);
+        self.lowlink.insert(name, index);
+        self.stack.push(name);
+        for callee in self.graph.callees(name) {
+            if !self.index.contains_key(callee) {
+                self.visit(callee);
+                let low = self.lowlink[name].min(self.lowlink[callee]);
+                self.lowlink.insert(name, low);
+            } else if self.stack.contains(&callee) {
+                let low = self.lowlink[name].min(self.index[callee]);
+                self.lowlink.insert(name, low);
+            }
+        }
+        if self.lowlink[name] == index {
+            let mut component = vec![];
+            while let Some(member) = self.stack.pop() {
+                component.push(member);
+                if member == name {
+                    break;
+                }
+            }
+            component.reverse();
+            self.components.push(component);
+        }
+    }
+}
+
+fn references<'a>(expr: &'a Expression, refs: &mut BTreeSet<(&'a str, EdgeKind)>) {
+    match expr {
+        Expression::Call(f, _) => {
+            refs.insert((f, EdgeKind::Call));
+        }
+        // Thunks, like partial applications, call the function later, if ever
+        Expression::Papp(f, _) | Expression::Thunk(f, _) => {
+            refs.insert((f, EdgeKind::Papp));
+        }
+        Expression::Let(_, val, body)
+        | Expression::Catch(val, _, body)
+        | Expression::Handle(val, _, _, body) => {
+            references(val, refs);
+            references(body, refs);
+        }
+        Expression::Match(_, cases, default) => {
+            cases.iter().for_each(|(_, e)| references(e, refs));
+            default.iter().for_each(|e| references(e, refs));
+        }
+        Expression::Unit(_)
+        | Expression::Apply(..)
+        | Expression::Operate(..)
+        | Expression::Prim(..)
+        | Expression::Str(_)
+        | Expression::Store(_)
+        | Expression::Fetch(_)
+        | Expression::Update(..)
+        | Expression::Force(_)
+        | Expression::Throw(_)
+        | Expression::Perform(_)
+        | Expression::Resume(..) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    fn parse(source: &str) -> Module {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        Module::new(ParserScanner::new(tokens).map(|m| m.unwrap()))
+    }
+
+    static SOURCE: &str = "
+        fn (even n) {
+          match n {
+            0 => { 1 }
+            _ => {
+              let m = (- n 1);
+              (odd m)
+            }
+          }
+        }
+
+        fn (odd n) {
+          match n {
+            0 => { 0 }
+            _ => {
+              let m = (- n 1);
+              (even m)
+            }
+          }
+        }
+
+        fn (loop n) {
+          (loop n)
+        }
+
+        fn (inc n) {
+          (+ n 1)
+        }
+
+        fn (main) {
+          let f = (papp inc);
+          let x = (apply f 2);
+          (even x)
+        }";
+
+    #[test]
+    fn mutual_recursion_is_one_component() {
+        let module = parse(SOURCE);
+        let graph = CallGraph::new(&module);
+        // `main` comes after every function it calls
+        assert_eq!(
+            graph.components(),
+            [vec!["even", "odd"], vec!["inc"], vec!["loop"], vec!["main"]]
+        );
+        assert_eq!(
+            graph.recursive_groups(),
+            [vec!["even", "odd"], vec!["loop"]]
+        );
+    }
+
+    #[test]
+    fn dot_has_clusters_and_dashed_papps() {
+        let module = parse(SOURCE);
+        let dot = "digraph callgraph {
+  subgraph cluster_0 {
+    style = dashed;
+    \"even\";
+    \"odd\";
+  }
+  subgraph cluster_1 {
+    style = dashed;
+    \"loop\";
+  }
+  \"even\";
+  \"even\" -> \"odd\";
+  \"inc\";
+  \"loop\";
+  \"loop\" -> \"loop\";
+  \"main\";
+  \"main\" -> \"even\";
+  \"main\" -> \"inc\" [style = dashed];
+  \"odd\";
+  \"odd\" -> \"even\";
+}
+";
+        assert_eq!(CallGraph::new(&module).to_dot(), dot);
+    }
+}
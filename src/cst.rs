@@ -0,0 +1,112 @@
+use crate::lexer::{Bracket, Lexeme, LexerError, Scanner, Span, Token, Trivia};
+use std::fmt;
+
+// Concrete syntax tree. Tokens are grouped by matching brackets, and keep their trivia, so
+// that the exact source can be reproduced from the tree
+pub enum Node {
+    Token(Lexeme),
+    // Opening bracket, the nodes in between, and closing bracket
+    Group(Lexeme, Vec<Node>, Lexeme),
+}
+
+pub struct Tree<'a> {
+    source: &'a str,
+    pub nodes: Vec<Node>,
+    // Trivia after the last token
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug)]
+pub enum CstError {
+    Lexer(LexerError),
+    Unbalanced(Span),
+}
+
+impl fmt::Display for CstError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CstError::Lexer(err) => write!(f, "{err}"),
+            CstError::Unbalanced(_) => write!(f, "Unbalanced bracket"),
+        }
+    }
+}
+
+fn matching(open: &Token, close: Bracket) -> bool {
+    matches!(
+        (open, close),
+        (Token::Paren(Bracket::LBrace), Bracket::RBrace)
+            | (Token::Paren(Bracket::LParen), Bracket::RParen)
+    )
+}
+
+impl<'a> Tree<'a> {
+    pub fn parse(source: &'a str) -> Result<Self, CstError> {
+        let mut scanner = Scanner::new(source);
+        // Groups being built, each with its opening bracket
+        let mut stack: Vec<(Option<Lexeme>, Vec<Node>)> = vec![(None, vec![])];
+        for lexeme in scanner.by_ref() {
+            let lexeme = lexeme.map_err(CstError::Lexer)?;
+            match lexeme.token {
+                Token::Paren(Bracket::LBrace | Bracket::LParen) => {
+                    stack.push((Some(lexeme), vec![]));
+                }
+                Token::Paren(close @ (Bracket::RBrace | Bracket::RParen)) => {
+                    let (Some(open), nodes) = stack.pop().unwrap() else {
+                        return Err(CstError::Unbalanced(lexeme.span));
+                    };
+                    if !matching(&open.token, close) {
+                        return Err(CstError::Unbalanced(open.span));
+                    }
+                    let group = Node::Group(open, nodes, lexeme);
+                    stack.last_mut().unwrap().1.push(group);
+                }
+                _ => stack.last_mut().unwrap().1.push(Node::Token(lexeme)),
+            }
+        }
+        let (open, nodes) = stack.pop().unwrap();
+        if let Some(open) = open {
+            return Err(CstError::Unbalanced(open.span));
+        }
+        Ok(Self {
+            source,
+            nodes,
+            trailing: scanner.trailing_trivia().to_vec(),
+        })
+    }
+
+    // Source text of a token
+    pub fn text(&self, lexeme: &Lexeme) -> &'a str {
+        &self.source[lexeme.span.start..lexeme.span.end]
+    }
+}
+
+fn fmt_lexeme(f: &mut fmt::Formatter<'_>, tree: &Tree, lexeme: &Lexeme) -> fmt::Result {
+    lexeme
+        .trivia
+        .iter()
+        .try_for_each(|trivia| write!(f, "{}", trivia.text()))?;
+    write!(f, "{}", tree.text(lexeme))
+}
+
+fn fmt_node(f: &mut fmt::Formatter<'_>, tree: &Tree, node: &Node) -> fmt::Result {
+    match node {
+        Node::Token(lexeme) => fmt_lexeme(f, tree, lexeme),
+        Node::Group(open, nodes, close) => {
+            fmt_lexeme(f, tree, open)?;
+            nodes.iter().try_for_each(|node| fmt_node(f, tree, node))?;
+            fmt_lexeme(f, tree, close)
+        }
+    }
+}
+
+// Reproduces the source exactly
+impl fmt::Display for Tree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.nodes
+            .iter()
+            .try_for_each(|node| fmt_node(f, self, node))?;
+        self.trailing
+            .iter()
+            .try_for_each(|trivia| write!(f, "{}", trivia.text()))
+    }
+}
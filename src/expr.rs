@@ -1,9 +1,67 @@
+use crate::lexer::Span;
+
+// Declarations at the top of a source file
+#[derive(Debug, Default, PartialEq)]
+pub struct Header {
+    // Name given by `module Name;`
+    pub name: Option<String>,
+    // Modules imported by `import Name;`
+    pub imports: Vec<String>,
+    // Functions provided by the host, declared by `extern fn (name a b);`
+    pub externs: Vec<Declaration>,
+}
+
+// Function header without a body, as listed in module interfaces
+#[derive(Clone, Debug, PartialEq)]
+pub struct Declaration {
+    pub name: String,
+    pub params: Vec<String>,
+    pub param_types: Vec<Option<Type>>,
+    pub ret_type: Option<Type>,
+}
+
+#[derive(Debug)]
 pub struct Definition {
     pub name: String,
     pub params: Vec<String>,
+    // Optional annotations, one for each parameter
+    pub param_types: Vec<Option<Type>>,
+    pub ret_type: Option<Type>,
     pub body: Expression,
+    pub span: Span,
+    // Spans of the body and of the expressions it holds, in the order they appear in the source.
+    // Empty when the definition was not parsed, or its body was rewritten
+    pub spans: Vec<Span>,
+    // Comments preceding the definition
+    pub comments: Vec<String>,
+}
+
+// Definitions are compared regardless of where they appear in the source
+impl PartialEq for Definition {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.params == other.params
+            && self.param_types == other.param_types
+            && self.ret_type == other.ret_type
+            && self.body == other.body
+            && self.comments == other.comments
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    // Closure taking the parameter types and returning the last type
+    Fn(Vec<Type>, Box<Type>),
+    // Named type constructor with its arguments, such as `List(Int)`
+    Con(String, Vec<Type>),
+    // Type variable, written in lowercase
+    Var(String),
+    // Recursive type `Rec(a, T)`, where `a` stands for the type itself inside `T`
+    Rec(String, Box<Type>),
 }
 
+#[derive(Debug, PartialEq)]
 pub enum Expression {
     Unit(Atom),
     Let(String, Box<Expression>, Box<Expression>),
@@ -17,11 +75,66 @@ pub enum Expression {
     Match(Atom, Vec<(usize, Expression)>, Option<Box<Expression>>),
     // Primitive operations
     Operate(Operation, Atom, Atom),
+    // Primitive provided by the runtime, such as I/O
+    Prim(Primitive, Vec<Atom>),
+    // Allocation of an immutable byte string
+    Str(Vec<u8>),
+    // Allocation of a mutable reference cell holding the atom
+    Store(Atom),
+    // Content of the reference cell held by the variable
+    Fetch(String),
+    // Replaces the content of the reference cell held by the variable
+    Update(String, Atom),
+    // Suspended call to a known function, evaluated when first forced
+    Thunk(String, Vec<Atom>),
+    // Result of the thunk held by the variable
+    Force(String),
+    // Raises the atom as an exception, which never returns
+    Throw(Atom),
+    // Evaluates the body, or the handler with the variable bound to the exception it raised
+    Catch(Box<Expression>, String, Box<Expression>),
+    // Gives the atom to the closest handler, and returns the value it is resumed with
+    Perform(Atom),
+    // Evaluates the body, or the handler with the variables bound to a performed value and to
+    // the continuation of the `perform`, up to and including the `handle`
+    Handle(Box<Expression>, String, String, Box<Expression>),
+    // Continues the continuation held by the variable, with the atom as the result of `perform`
+    Resume(String, Atom),
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Atom {
     Var(String),
     Lit(usize),
+    // Floating-point literal, always finite
+    Float(f64),
+}
+
+impl Type {
+    // Floating-point numbers are of the named type `Float`
+    pub fn float() -> Type {
+        Type::Con("Float".into(), vec![])
+    }
+
+    // Arbitrary-precision integers are of the named type `Big`
+    pub fn big() -> Type {
+        Type::Con("Big".into(), vec![])
+    }
+
+    // Byte strings are of the named type `Str`
+    pub fn str() -> Type {
+        Type::Con("Str".into(), vec![])
+    }
+
+    // Reference cells holding values of type `typ` are of the named type `Ref(typ)`
+    pub fn reference(typ: Type) -> Type {
+        Type::Con("Ref".into(), vec![typ])
+    }
+
+    // Thunks evaluating to values of type `typ` are of the named type `Lazy(typ)`
+    pub fn lazy(typ: Type) -> Type {
+        Type::Con("Lazy".into(), vec![typ])
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -44,4 +157,274 @@ pub enum Operation {
     Xor,
     Sr,
     Sl,
+    // Floating-point arithmetic
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    // Floating-point comparison
+    FEq,
+    FLt,
+    FLe,
+    FGt,
+    FGe,
+}
+
+// Every operation, in the order of their declaration
+pub static OPERATIONS: [Operation; 24] = [
+    Operation::Add,
+    Operation::Sub,
+    Operation::Mul,
+    Operation::Div,
+    Operation::Mod,
+    Operation::Eq,
+    Operation::Lt,
+    Operation::Le,
+    Operation::Gt,
+    Operation::Ge,
+    Operation::And,
+    Operation::Or,
+    Operation::Xor,
+    Operation::Sr,
+    Operation::Sl,
+    Operation::FAdd,
+    Operation::FSub,
+    Operation::FMul,
+    Operation::FDiv,
+    Operation::FEq,
+    Operation::FLt,
+    Operation::FLe,
+    Operation::FGt,
+    Operation::FGe,
+];
+
+impl Operation {
+    pub fn is_float(self) -> bool {
+        matches!(
+            self,
+            Operation::FAdd
+                | Operation::FSub
+                | Operation::FMul
+                | Operation::FDiv
+                | Operation::FEq
+                | Operation::FLt
+                | Operation::FLe
+                | Operation::FGt
+                | Operation::FGe
+        )
+    }
+
+    // Types of both operands and of the result. Comparisons give 0 or 1
+    pub fn signature(self) -> (Type, Type) {
+        match self {
+            Operation::FAdd | Operation::FSub | Operation::FMul | Operation::FDiv => {
+                (Type::float(), Type::float())
+            }
+            _ if self.is_float() => (Type::float(), Type::Int),
+            _ => (Type::Int, Type::Int),
+        }
+    }
+}
+
+// Primitives of the runtime, written `(#name args..)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    // `(#write_int stream n)` writes `n` in decimal to stdout (1) or stderr (2)
+    WriteInt,
+    // `(#write_byte stream b)` writes the byte `b`
+    WriteByte,
+    // `(#read_byte)` reads a byte from stdin, or returns 256 at the end of the input
+    ReadByte,
+    // `(#exit code)` stops the program
+    Exit,
+    // `(#write_str stream s)` writes the bytes of the string `s`
+    WriteStr,
+    // `(#str_len s)` is the number of bytes of `s`
+    StrLen,
+    // `(#str_get s i)` is the byte of `s` at index `i`
+    StrGet,
+    // `(#str_concat s t)` is a new string made of `s` followed by `t`
+    StrConcat,
+    // `(#str_slice s start end)` is a new string made of the bytes of `s` from `start` to `end`,
+    // excluded
+    StrSlice,
+    // `(#str_cmp s t)` compares `s` and `t` lexicographically, giving 0 if `s` is less than `t`,
+    // 1 if they are equal and 2 if it is greater
+    StrCmp,
+    // `(#array_new n x)` is a new array of `n` elements, all `x`
+    ArrayNew,
+    // `(#array_len a)` is the number of elements of `a`
+    ArrayLen,
+    // `(#array_get a i)` is the element of `a` at index `i`
+    ArrayGet,
+    // `(#array_set a i x)` replaces the element of `a` at index `i` by `x`
+    ArraySet,
+    // `(#int_to_float n)` is the float closest to `n`
+    IntToFloat,
+    // `(#float_to_int x)` is `x` rounded toward zero, which must fit in an integer
+    FloatToInt,
+    // `(#int_to_big n)` is `n` as a big integer
+    IntToBig,
+    // `(#big_to_int n)` is the integer equal to `n`, which must fit in an integer
+    BigToInt,
+    // `(#big_to_str n)` is a new string with `n` in decimal
+    BigToStr,
+    // `(#big_add m n)`, `(#big_sub m n)`, `(#big_mul m n)`, `(#big_div m n)` and `(#big_mod m n)`
+    // are the arithmetic operations of big integers
+    BigAdd,
+    BigSub,
+    BigMul,
+    BigDiv,
+    BigMod,
+    // `(#big_cmp m n)` compares `m` and `n` like `#str_cmp`
+    BigCmp,
+    // `(#big_shl n k)` and `(#big_shr n k)` shift `n` by the integer `k`
+    BigShl,
+    BigShr,
+    // `(#spawn t)` starts a fiber forcing the thunk `t`, and returns it
+    Spawn,
+    // `(#yield)` lets the other fibers run before continuing
+    Yield,
+    // `(#join f)` waits for the fiber `f` to finish, and returns the value of its thunk
+    Join,
+    // `(#chan_new)` creates an unbounded channel, and `(#chan_bounded n)` one holding at most
+    // `n` messages
+    ChanNew,
+    ChanBounded,
+    // `(#send c x)` sends `x` on the channel `c`, waiting while it is full, and returns 0
+    Send,
+    // `(#recv c)` waits for a message on the channel `c`, and returns it
+    Recv,
+}
+
+// Every primitive, in the order of their declaration
+pub static PRIMITIVES: [Primitive; 34] = [
+    Primitive::WriteInt,
+    Primitive::WriteByte,
+    Primitive::ReadByte,
+    Primitive::Exit,
+    Primitive::WriteStr,
+    Primitive::StrLen,
+    Primitive::StrGet,
+    Primitive::StrConcat,
+    Primitive::StrSlice,
+    Primitive::StrCmp,
+    Primitive::ArrayNew,
+    Primitive::ArrayLen,
+    Primitive::ArrayGet,
+    Primitive::ArraySet,
+    Primitive::IntToFloat,
+    Primitive::FloatToInt,
+    Primitive::IntToBig,
+    Primitive::BigToInt,
+    Primitive::BigToStr,
+    Primitive::BigAdd,
+    Primitive::BigSub,
+    Primitive::BigMul,
+    Primitive::BigDiv,
+    Primitive::BigMod,
+    Primitive::BigCmp,
+    Primitive::BigShl,
+    Primitive::BigShr,
+    Primitive::Spawn,
+    Primitive::Yield,
+    Primitive::Join,
+    Primitive::ChanNew,
+    Primitive::ChanBounded,
+    Primitive::Send,
+    Primitive::Recv,
+];
+
+impl Primitive {
+    pub fn name(self) -> &'static str {
+        match self {
+            Primitive::WriteInt => "write_int",
+            Primitive::WriteByte => "write_byte",
+            Primitive::ReadByte => "read_byte",
+            Primitive::Exit => "exit",
+            Primitive::WriteStr => "write_str",
+            Primitive::StrLen => "str_len",
+            Primitive::StrGet => "str_get",
+            Primitive::StrConcat => "str_concat",
+            Primitive::StrSlice => "str_slice",
+            Primitive::StrCmp => "str_cmp",
+            Primitive::ArrayNew => "array_new",
+            Primitive::ArrayLen => "array_len",
+            Primitive::ArrayGet => "array_get",
+            Primitive::ArraySet => "array_set",
+            Primitive::IntToFloat => "int_to_float",
+            Primitive::FloatToInt => "float_to_int",
+            Primitive::IntToBig => "int_to_big",
+            Primitive::BigToInt => "big_to_int",
+            Primitive::BigToStr => "big_to_str",
+            Primitive::BigAdd => "big_add",
+            Primitive::BigSub => "big_sub",
+            Primitive::BigMul => "big_mul",
+            Primitive::BigDiv => "big_div",
+            Primitive::BigMod => "big_mod",
+            Primitive::BigCmp => "big_cmp",
+            Primitive::BigShl => "big_shl",
+            Primitive::BigShr => "big_shr",
+            Primitive::Spawn => "spawn",
+            Primitive::Yield => "yield",
+            Primitive::Join => "join",
+            Primitive::ChanNew => "chan_new",
+            Primitive::ChanBounded => "chan_bounded",
+            Primitive::Send => "send",
+            Primitive::Recv => "recv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Primitive> {
+        PRIMITIVES.iter().copied().find(|prim| prim.name() == name)
+    }
+
+    pub fn arity(self) -> usize {
+        self.signature().0.len()
+    }
+
+    // Types of the parameters and of the result
+    pub fn signature(self) -> (Vec<Type>, Type) {
+        let str = Type::str;
+        let big = Type::big;
+        let a = || Type::Var("a".into());
+        let array = || Type::Con("Array".into(), vec![a()]);
+        let fiber = || Type::Con("Fiber".into(), vec![a()]);
+        let chan = || Type::Con("Chan".into(), vec![a()]);
+        match self {
+            Primitive::WriteInt | Primitive::WriteByte => (vec![Type::Int, Type::Int], Type::Int),
+            Primitive::ReadByte => (vec![], Type::Int),
+            // Never returns, so its result can be used as any type
+            Primitive::Exit => (vec![Type::Int], a()),
+            Primitive::WriteStr => (vec![Type::Int, str()], Type::Int),
+            Primitive::StrLen => (vec![str()], Type::Int),
+            Primitive::StrGet => (vec![str(), Type::Int], Type::Int),
+            Primitive::StrConcat => (vec![str(), str()], str()),
+            Primitive::StrSlice => (vec![str(), Type::Int, Type::Int], str()),
+            Primitive::StrCmp => (vec![str(), str()], Type::Int),
+            Primitive::ArrayNew => (vec![Type::Int, a()], array()),
+            Primitive::ArrayLen => (vec![array()], Type::Int),
+            Primitive::ArrayGet => (vec![array(), Type::Int], a()),
+            Primitive::ArraySet => (vec![array(), Type::Int, a()], Type::Int),
+            Primitive::IntToFloat => (vec![Type::Int], Type::float()),
+            Primitive::FloatToInt => (vec![Type::float()], Type::Int),
+            Primitive::IntToBig => (vec![Type::Int], big()),
+            Primitive::BigToInt => (vec![big()], Type::Int),
+            Primitive::BigToStr => (vec![big()], str()),
+            Primitive::BigAdd
+            | Primitive::BigSub
+            | Primitive::BigMul
+            | Primitive::BigDiv
+            | Primitive::BigMod => (vec![big(), big()], big()),
+            Primitive::BigCmp => (vec![big(), big()], Type::Int),
+            Primitive::BigShl | Primitive::BigShr => (vec![big(), Type::Int], big()),
+            Primitive::Spawn => (vec![Type::lazy(a())], fiber()),
+            Primitive::Yield => (vec![], Type::Int),
+            Primitive::Join => (vec![fiber()], a()),
+            Primitive::ChanNew => (vec![], chan()),
+            Primitive::ChanBounded => (vec![Type::Int], chan()),
+            Primitive::Send => (vec![chan(), a()], Type::Int),
+            Primitive::Recv => (vec![chan()], a()),
+        }
+    }
 }
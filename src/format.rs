@@ -0,0 +1,347 @@
+use crate::cst::{CstError, Node, Tree};
+use crate::lexer::{Bracket, Keyword, Lexeme, Symbol, Token, Trivia};
+
+// Formats source code with a canonical layout, walking its concrete syntax tree. Unlike the
+// `pretty` module, which prints definitions, comments are kept where they were, and blank
+// lines between statements are preserved, though collapsed into a single one
+pub fn format(source: &str) -> Result<String, CstError> {
+    let tree = Tree::parse(source)?;
+    let mut formatter = Formatter {
+        tree: &tree,
+        out: String::new(),
+        indent: 0,
+        at_line_start: true,
+        pending: false,
+        blank: false,
+        prev: None,
+    };
+    formatter.toplevel(&tree.nodes);
+    formatter.trivia(&tree.trailing);
+    formatter.newline();
+    formatter.flush();
+    Ok(formatter.out)
+}
+
+struct Formatter<'a> {
+    tree: &'a Tree<'a>,
+    out: String,
+    indent: usize,
+    // Whether nothing, not even the indentation, has been written on the current line
+    at_line_start: bool,
+    // Line break that is only written before the next token, so that a comment can still be
+    // appended to the current line
+    pending: bool,
+    // Blank line written along with the pending line break
+    blank: bool,
+    // Last token written on the current line, which decides the spacing before the next one
+    prev: Option<&'a Token>,
+}
+
+fn is_brace(lexeme: &Lexeme) -> bool {
+    lexeme.token == Token::Paren(Bracket::LBrace)
+}
+
+// Whether a space separates two tokens on the same line. Symbols are glued together, since
+// operators such as `=>` and `<=` are lexed as several symbols
+fn spaced(prev: Option<&Token>, next: &Token) -> bool {
+    match (prev, next) {
+        (_, Token::Paren(Bracket::RParen)) => false,
+        (_, Token::Symbol(Symbol::Colon | Symbol::Comma | Symbol::Semicolon)) => false,
+        (Some(Token::Paren(Bracket::LParen)), _) => false,
+        (Some(Token::Symbol(Symbol::Colon | Symbol::Comma | Symbol::Underscore)), _) => true,
+        (Some(Token::Symbol(_)), Token::Symbol(_)) => false,
+        // Arguments of type constructors, as in `Fn(Int) -> Int`
+        (Some(Token::Identifier(name)), Token::Paren(Bracket::LParen)) => {
+            !name.starts_with(char::is_uppercase)
+        }
+        _ => true,
+    }
+}
+
+impl<'a> Formatter<'a> {
+    fn newline(&mut self) {
+        if !self.at_line_start {
+            self.pending = true;
+        }
+    }
+
+    // Whether the next token starts a new line
+    fn line_start(&self) -> bool {
+        self.at_line_start || self.pending
+    }
+
+    // Blank lines are never written at the start of the file or of a block
+    fn flush(&mut self) {
+        if self.pending {
+            self.out.push('\n');
+            self.at_line_start = true;
+            self.pending = false;
+        }
+        if self.blank {
+            let out = &self.out;
+            if !out.is_empty() && !out.ends_with("\n\n") && !out.ends_with("{\n") {
+                self.out.push('\n');
+            }
+            self.blank = false;
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        self.blank = true;
+    }
+
+    fn write(&mut self, text: &str) {
+        self.flush();
+        if self.at_line_start {
+            (0..self.indent).for_each(|_| self.out.push_str("  "));
+            self.at_line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    // Comments that follow a token on the same line stay there, the others are written on
+    // their own line, as is whatever followed them on a new line. Returns the number of line
+    // breaks after the last comment
+    fn trivia(&mut self, trivia: &'a [Trivia]) -> usize {
+        let mut newlines = 0;
+        let mut comment = false;
+        for trivia in trivia {
+            if let Trivia::Whitespace(text) = trivia {
+                newlines += text.matches('\n').count();
+                continue;
+            }
+            if newlines == 0 && !self.at_line_start {
+                if !self.out.ends_with('(') {
+                    self.out.push(' ');
+                }
+                self.out.push_str(trivia.text());
+            } else {
+                if newlines >= 2 {
+                    self.blank_line();
+                } else {
+                    self.newline();
+                }
+                self.write(trivia.text());
+            }
+            if let Trivia::LineComment(_) = trivia {
+                self.newline();
+            }
+            self.prev = None;
+            newlines = 0;
+            comment = true;
+        }
+        if comment && newlines > 0 {
+            self.newline();
+        }
+        newlines
+    }
+
+    fn text(&mut self, lexeme: &'a Lexeme) {
+        self.flush();
+        if !self.at_line_start && spaced(self.prev, &lexeme.token) {
+            self.out.push(' ');
+        }
+        self.write(self.tree.text(lexeme));
+        self.prev = Some(&lexeme.token);
+    }
+
+    // Tokens that start a statement keep a blank line that preceded them
+    fn token(&mut self, lexeme: &'a Lexeme, statement: bool) {
+        let newlines = self.trivia(&lexeme.trivia);
+        if statement && self.line_start() && newlines >= 2 {
+            self.blank_line();
+        }
+        self.text(lexeme);
+    }
+
+    fn toplevel(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            match node {
+                // Extern functions are declared in the header
+                Node::Token(lexeme)
+                    if lexeme.token == Token::Keyword(Keyword::Fn)
+                        && self.prev != Some(&Token::Keyword(Keyword::Extern)) =>
+                {
+                    self.blank_line();
+                    self.token(lexeme, true);
+                }
+                Node::Group(open, nodes, close) if is_brace(open) => {
+                    self.block(open, nodes, close, Self::statements);
+                    self.newline();
+                }
+                // Headers, with one declaration per line
+                Node::Token(lexeme) => {
+                    let statement = self.line_start();
+                    self.token(lexeme, statement);
+                    if lexeme.token == Token::Symbol(Symbol::Semicolon) {
+                        self.newline();
+                    }
+                }
+                node => self.inline(node),
+            }
+        }
+    }
+
+    fn inline(&mut self, node: &'a Node) {
+        match node {
+            Node::Token(lexeme) => self.token(lexeme, false),
+            Node::Group(open, nodes, close) => {
+                self.token(open, false);
+                nodes.iter().for_each(|node| self.inline(node));
+                self.token(close, false);
+            }
+        }
+    }
+
+    // The contents of a block are indented, between lines with the braces
+    fn block(
+        &mut self,
+        open: &'a Lexeme,
+        nodes: &'a [Node],
+        close: &'a Lexeme,
+        contents: fn(&mut Self, &'a [Node]),
+    ) {
+        self.token(open, false);
+        self.indent += 1;
+        self.newline();
+        contents(self, nodes);
+        self.trivia(&close.trivia);
+        self.indent -= 1;
+        self.newline();
+        self.text(close);
+    }
+
+    // Expressions, with a line break after each `let` binding
+    fn statements(&mut self, nodes: &'a [Node]) {
+        for (i, node) in nodes.iter().enumerate() {
+            match node {
+                Node::Token(lexeme) => {
+                    let statement = self.line_start();
+                    self.token(lexeme, statement);
+                    if lexeme.token == Token::Symbol(Symbol::Semicolon) {
+                        self.newline();
+                    }
+                }
+                Node::Group(open, inner, close) if is_brace(open) => {
+                    let is_match = i >= 2
+                        && matches!(
+                            &nodes[i - 2],
+                            Node::Token(lexeme) if lexeme.token == Token::Keyword(Keyword::Match)
+                        );
+                    let contents = if is_match {
+                        Self::arms
+                    } else {
+                        Self::statements
+                    };
+                    self.block(open, inner, close, contents);
+                }
+                node => self.inline(node),
+            }
+        }
+    }
+
+    // Arms of a `match`, one per line
+    fn arms(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            match node {
+                Node::Token(lexeme) => {
+                    let statement = self.line_start();
+                    self.token(lexeme, statement);
+                }
+                Node::Group(open, inner, close) if is_brace(open) => {
+                    self.block(open, inner, close, Self::statements);
+                    self.newline();
+                }
+                node => self.inline(node),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use crate::cst::Tree;
+    use crate::expr::{Definition, Header};
+    use crate::lexer::{Scanner as LexerScanner, Trivia};
+    use crate::parser::Scanner as ParserScanner;
+
+    static CORPUS: &[(&str, &str)] = &[
+        ("sample", include_str!("../corpus/sample.ir")),
+        ("comments", include_str!("../corpus/comments.ir")),
+        ("layout", include_str!("../corpus/layout.ir")),
+        ("types", include_str!("../corpus/types.ir")),
+        ("names", include_str!("../corpus/names.ir")),
+        ("modules", include_str!("../corpus/modules/main.ir")),
+        ("list", include_str!("../corpus/modules/List.ir")),
+        ("extern", include_str!("../corpus/extern.ir")),
+        ("io", include_str!("../corpus/io.ir")),
+        ("strings", include_str!("../corpus/strings.ir")),
+        ("arrays", include_str!("../corpus/arrays.ir")),
+        ("lazy", include_str!("../corpus/lazy.ir")),
+        ("thunks", include_str!("../corpus/thunks.ir")),
+        ("floats", include_str!("../corpus/floats.ir")),
+        ("bignums", include_str!("../corpus/bignums.ir")),
+        ("exceptions", include_str!("../corpus/exceptions.ir")),
+        ("generators", include_str!("../corpus/generators.ir")),
+        ("fibers", include_str!("../corpus/fibers.ir")),
+        ("channels", include_str!("../corpus/channels.ir")),
+    ];
+
+    fn parse(source: &str) -> (Header, Vec<Definition>) {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        let mut parser = ParserScanner::new(tokens);
+        let header = parser.parse_header().unwrap();
+        (header, parser.map(|m| m.unwrap()).collect())
+    }
+
+    fn comments(source: &str) -> Vec<String> {
+        let mut scanner = LexerScanner::new(source);
+        let mut trivia = scanner
+            .by_ref()
+            .flat_map(|m| m.unwrap().trivia)
+            .collect::<Vec<_>>();
+        trivia.extend(scanner.trailing_trivia().iter().cloned());
+        let comments = trivia.iter().filter(|trivia| trivia.is_comment());
+        comments.map(Trivia::text).map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn tree_is_lossless() {
+        for (name, source) in CORPUS {
+            let tree = Tree::parse(source).unwrap();
+            assert_eq!(tree.to_string(), *source, "{name}");
+        }
+    }
+
+    #[test]
+    fn format_preserves_ast() {
+        for (name, source) in CORPUS {
+            let formatted = format(source).unwrap();
+            assert_eq!(parse(&formatted), parse(source), "{name}");
+        }
+    }
+
+    #[test]
+    fn format_preserves_comments() {
+        for (name, source) in CORPUS {
+            let formatted = format(source).unwrap();
+            assert_eq!(comments(&formatted), comments(source), "{name}");
+        }
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        for (name, source) in CORPUS {
+            let formatted = format(source).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted, "{name}");
+        }
+    }
+
+    #[test]
+    fn sample_is_formatted() {
+        let (_, source) = CORPUS[0];
+        assert_eq!(format(source).unwrap(), source);
+    }
+}
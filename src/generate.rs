@@ -0,0 +1,602 @@
+// Random generation of well-formed modules, for property tests. Generated modules pass
+// `module::check`, and always run to completion: functions only call the functions defined
+// before them, arithmetic is only generated where the possible values of its operands
+// cannot make it overflow or divide by zero, indices are in bounds, and exceptions and
+// effects are only raised directly in the body of their handler
+use crate::expr::{Atom, Definition, Expression, Operation, Primitive, Type, OPERATIONS};
+use crate::lexer::Span;
+
+// Xorshift generator, so that failures can be reproduced from their seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<T: Clone>(&mut self, items: &[T]) -> Option<T> {
+        match items {
+            [] => None,
+            items => Some(items[self.below(items.len())].clone()),
+        }
+    }
+}
+
+// Bound of numbers passed as arguments
+const ARG_MAX: u64 = 1000;
+// Bound of all numbers, far enough from overflowing
+const NUM_MAX: u64 = 1 << 40;
+
+// Statically known values of a variable
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    // Number between two bounds, included
+    Num(u64, u64),
+    // Closure expecting a number of arguments, all of them numbers up to `ARG_MAX`
+    Closure(usize, Box<Kind>),
+    // Thunk of a call, which is forced or spawned as a fiber
+    Thunk(Box<Kind>),
+}
+
+impl Kind {
+    // Whether a value of this kind can be used where `other` is expected
+    fn fits(&self, other: &Kind) -> bool {
+        match (self, other) {
+            (Kind::Num(lo, hi), Kind::Num(lo2, hi2)) => lo2 <= lo && hi <= hi2,
+            (Kind::Closure(n, ret), Kind::Closure(n2, ret2)) => n == n2 && ret.fits(ret2),
+            (Kind::Thunk(ret), Kind::Thunk(ret2)) => ret.fits(ret2),
+            _ => false,
+        }
+    }
+}
+
+const ARG: Kind = Kind::Num(0, ARG_MAX);
+
+#[derive(Clone)]
+enum Want {
+    Num,
+    Fits(Kind),
+    AnyClosure,
+}
+
+impl Want {
+    fn accepts(&self, kind: &Kind) -> bool {
+        match self {
+            Want::Num => matches!(kind, Kind::Num(..)),
+            Want::Fits(want) => kind.fits(want),
+            Want::AnyClosure => matches!(kind, Kind::Closure(..)),
+        }
+    }
+}
+
+struct Signature {
+    name: String,
+    params: Vec<Kind>,
+    ret: Kind,
+}
+
+struct Generator<'r> {
+    rng: &'r mut Rng,
+    // Functions defined so far, which the current one can refer to
+    sigs: Vec<Signature>,
+    // Variables in scope
+    env: Vec<(String, Kind)>,
+    fresh: usize,
+}
+
+// Generates a module with a `main` function returning a number
+pub fn module(rng: &mut Rng) -> Vec<Definition> {
+    let mut gen = Generator {
+        rng,
+        sigs: vec![],
+        env: vec![],
+        fresh: 0,
+    };
+    let count = 1 + gen.rng.below(6);
+    let mut defs = (0..count)
+        .map(|i| gen.definition(format!("f{i}"), false))
+        .collect::<Vec<_>>();
+    defs.push(gen.definition("main".to_string(), true));
+    defs
+}
+
+fn wrap(bindings: Vec<(String, Expression)>, body: Expression) -> Expression {
+    bindings.into_iter().rev().fold(body, |body, (x, val)| {
+        Expression::Let(x, val.into(), body.into())
+    })
+}
+
+fn to_type(kind: &Kind) -> Option<Type> {
+    match kind {
+        Kind::Num(..) => Some(Type::Int),
+        Kind::Closure(n, ret) => Some(Type::Fn(vec![Type::Int; *n], to_type(ret)?.into())),
+        Kind::Thunk(ret) => Some(Type::lazy(to_type(ret)?)),
+    }
+}
+
+// Possible values of either of two numbers
+fn union((lx, hx): (u64, u64), (ly, hy): (u64, u64)) -> (u64, u64) {
+    (lx.min(ly), hx.max(hy))
+}
+
+// Possible values of `x op y`, if it is always defined
+fn operate(op: Operation, (lx, hx): (u64, u64), (ly, hy): (u64, u64)) -> Option<(u64, u64)> {
+    let range = match op {
+        Operation::Add => (lx + ly, hx + hy),
+        Operation::Sub if lx >= hy => (lx - hy, hx - ly),
+        Operation::Mul => (lx * ly, hx.checked_mul(hy)?),
+        Operation::Div if ly > 0 => (lx / hy, hx / ly),
+        Operation::Mod if ly > 0 => (0, hx.min(hy - 1)),
+        Operation::Eq | Operation::Lt | Operation::Le | Operation::Gt | Operation::Ge => (0, 1),
+        Operation::And => (0, hx.min(hy)),
+        Operation::Or | Operation::Xor => (0, (hx.max(hy) + 1).next_power_of_two() - 1),
+        Operation::Sr if hy < 64 => (lx >> hy, hx >> ly),
+        Operation::Sl if hy < 64 => (lx << ly, hx.checked_mul(1 << hy)?),
+        _ => return None,
+    };
+    Some(range).filter(|(_, hi)| *hi <= NUM_MAX)
+}
+
+impl Generator<'_> {
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        match self.rng.chance(20) {
+            true => format!("{prefix}_{}'", self.fresh),
+            false => format!("{prefix}{}", self.fresh),
+        }
+    }
+
+    fn definition(&mut self, name: String, main: bool) -> Definition {
+        self.fresh = 0;
+        self.env = vec![];
+        let count = if main { 0 } else { self.rng.below(4) };
+        let mut params = vec![];
+        for _ in 0..count {
+            let closure = Kind::Closure(1 + self.rng.below(2), Kind::Num(0, NUM_MAX).into());
+            let kind =
+                if self.rng.chance(25) && !self.papps(&Want::Fits(closure.clone())).is_empty() {
+                    closure
+                } else {
+                    ARG
+                };
+            let param = self.fresh("p");
+            params.push((param, kind));
+        }
+        self.env = params.clone();
+        let want = if !main && self.rng.chance(20) && !self.papps(&Want::AnyClosure).is_empty() {
+            Want::AnyClosure
+        } else {
+            Want::Num
+        };
+        let (body, ret) = self.expr(&want, 3);
+        let param_types = params
+            .iter()
+            .map(|(_, kind)| to_type(kind).filter(|_| self.rng.chance(30)))
+            .collect();
+        let ret_type = to_type(&ret).filter(|_| self.rng.chance(30));
+        let comments = match self.rng.chance(20) {
+            true => vec![format!("// {name}")],
+            false => vec![],
+        };
+        self.sigs.push(Signature {
+            name: name.clone(),
+            params: params.iter().map(|(_, kind)| kind.clone()).collect(),
+            ret,
+        });
+        Definition {
+            name,
+            params: params.into_iter().map(|(param, _)| param).collect(),
+            param_types,
+            ret_type,
+            body,
+            span: Span::default(),
+            spans: vec![],
+            comments,
+        }
+    }
+
+    // Random choices that turn out impossible are retried. Leaves are always possible, as
+    // callers only want closures that can be built
+    fn expr(&mut self, want: &Want, depth: usize) -> (Expression, Kind) {
+        loop {
+            let choice = self.rng.below(if depth == 0 { 4 } else { 12 });
+            let expr = match choice {
+                0 => self.unit(want),
+                1 => self.leaf(want),
+                2 => self.cell(want),
+                3 => self.force(want),
+                4 | 5 => self.let_(want, depth),
+                6 => self.match_(want, depth),
+                7 => self.call(want),
+                8 => self.catch(want, depth),
+                9 => self.handle(want, depth),
+                _ => self.apply(want),
+            };
+            if let Some(expr) = expr {
+                return expr;
+            }
+        }
+    }
+
+    fn literal(&mut self) -> (Atom, Kind) {
+        let n = match self.rng.chance(80) {
+            true => self.rng.below(10),
+            false => self.rng.below(ARG_MAX as usize + 1),
+        } as u64;
+        (Atom::Lit(n as usize), Kind::Num(n, n))
+    }
+
+    fn var(&mut self, want: &Want) -> Option<(Atom, Kind)> {
+        let vars = self.env.iter().filter(|(_, kind)| want.accepts(kind));
+        let (x, kind) = self.rng.pick(&vars.cloned().collect::<Vec<_>>())?;
+        Some((Atom::Var(x), kind))
+    }
+
+    fn atom(&mut self, want: &Want) -> Option<(Atom, Kind)> {
+        match want {
+            Want::Num if self.rng.chance(50) => Some(self.literal()),
+            want => self.var(want),
+        }
+    }
+
+    // Argument of the given kind, possibly bound beforehand
+    fn arg(&mut self, kind: &Kind, bindings: &mut Vec<(String, Expression)>) -> Option<Atom> {
+        if let Kind::Num(..) = kind {
+            return match self.var(&Want::Fits(kind.clone())) {
+                Some((atom, _)) if self.rng.chance(50) => Some(atom),
+                _ => Some(Atom::Lit(self.rng.below(10))),
+            };
+        }
+        let want = Want::Fits(kind.clone());
+        if let Some((atom, _)) = self.var(&want) {
+            return Some(atom);
+        }
+        let (papp, kind) = self.papp(&want)?;
+        let x = self.fresh("v");
+        bindings.push((x.clone(), papp));
+        self.env.push((x.clone(), kind));
+        Some(Atom::Var(x))
+    }
+
+    fn unit(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        let (atom, kind) = self.atom(want)?;
+        Some((Expression::Unit(atom), kind))
+    }
+
+    fn leaf(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        match want {
+            Want::Num => self.operate(),
+            want => self.papp(want),
+        }
+    }
+
+    fn operate(&mut self) -> Option<(Expression, Kind)> {
+        let op = self.rng.pick(&OPERATIONS)?;
+        let (x, Kind::Num(lx, hx)) = self.atom(&Want::Num)? else {
+            unreachable!()
+        };
+        let (y, Kind::Num(ly, hy)) = self.atom(&Want::Num)? else {
+            unreachable!()
+        };
+        let (lo, hi) = operate(op, (lx, hx), (ly, hy))?;
+        Some((Expression::Operate(op, x, y), Kind::Num(lo, hi)))
+    }
+
+    // Functions that can be partially applied to give a closure, with the number of
+    // arguments to give them. Arguments that are closures must already be in scope
+    fn papps(&self, want: &Want) -> Vec<(usize, usize)> {
+        let mut papps = vec![];
+        for (i, sig) in self.sigs.iter().enumerate() {
+            for given in 0..sig.params.len() {
+                let rest = &sig.params[given..];
+                let kind = Kind::Closure(rest.len(), sig.ret.clone().into());
+                let possible = sig.params[..given].iter().all(|param| {
+                    let want = Want::Fits(param.clone());
+                    self.env.iter().any(|(_, kind)| want.accepts(kind))
+                });
+                if possible && rest.iter().all(|param| *param == ARG) && want.accepts(&kind) {
+                    papps.push((i, given));
+                }
+            }
+        }
+        papps
+    }
+
+    fn papp(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        let (i, given) = self.rng.pick(&self.papps(want))?;
+        let mut bindings = vec![];
+        let params = self.sigs[i].params[..given].to_vec();
+        let args = params
+            .iter()
+            .map(|param| self.arg(param, &mut bindings))
+            .collect::<Option<Vec<_>>>()?;
+        let sig = &self.sigs[i];
+        let kind = Kind::Closure(sig.params.len() - given, sig.ret.clone().into());
+        Some((
+            wrap(bindings, Expression::Papp(sig.name.clone(), args)),
+            kind,
+        ))
+    }
+
+    fn let_(&mut self, want: &Want, depth: usize) -> Option<(Expression, Kind)> {
+        let scope = self.env.len();
+        let (val, kind) = match self.rng.below(10) {
+            0..4 => self.papp(&Want::AnyClosure)?,
+            4..6 => self.thunk()?,
+            _ => self.expr(&Want::Num, depth - 1),
+        };
+        let x = self.fresh("v");
+        self.env.push((x.clone(), kind));
+        let (body, kind) = self.expr(want, depth - 1);
+        self.env.truncate(scope);
+        Some((Expression::Let(x, val.into(), body.into()), kind))
+    }
+
+    // Without a default branch, the cases cover all the possible values
+    fn match_(&mut self, want: &Want, depth: usize) -> Option<(Expression, Kind)> {
+        let (atom, kind) = match self.rng.chance(20) {
+            true => self.literal(),
+            false => self.var(&Want::Num)?,
+        };
+        let Kind::Num(lo, hi) = kind else {
+            unreachable!()
+        };
+        let exhaustive = hi - lo <= 2 && self.rng.chance(50);
+        let mut values = match exhaustive {
+            true => (lo..=hi).collect::<Vec<_>>(),
+            false => (0..self.rng.below(3))
+                .map(|_| lo + self.rng.below(4) as u64)
+                .collect(),
+        };
+        values.sort();
+        values.dedup();
+        // All the branches give closures of the same kind, which can be built by `papp`
+        let want = match want {
+            Want::AnyClosure => {
+                let (_, kind) = self.papp(want)?;
+                Want::Fits(kind)
+            }
+            want => want.clone(),
+        };
+        let mut range: Option<(u64, u64)> = None;
+        let mut branch = |gen: &mut Self| {
+            let scope = gen.env.len();
+            let (expr, kind) = gen.expr(&want, depth - 1);
+            gen.env.truncate(scope);
+            if let Kind::Num(lo, hi) = kind {
+                range = Some(range.map_or((lo, hi), |(lo2, hi2)| (lo.min(lo2), hi.max(hi2))));
+            }
+            expr
+        };
+        let cases = values
+            .into_iter()
+            .map(|n| (n as usize, branch(self)))
+            .collect::<Vec<_>>();
+        let default = match exhaustive {
+            true => None,
+            false => Some(branch(self).into()),
+        };
+        let kind = match (want, range) {
+            (Want::Fits(kind), _) => kind,
+            (_, Some((lo, hi))) => Kind::Num(lo, hi),
+            _ => return None,
+        };
+        Some((Expression::Match(atom, cases, default), kind))
+    }
+
+    fn call(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        let candidates = (0..self.sigs.len()).filter(|i| want.accepts(&self.sigs[*i].ret));
+        let i = self.rng.pick(&candidates.collect::<Vec<_>>())?;
+        let mut bindings = vec![];
+        let args = self.args(i, &mut bindings)?;
+        let sig = &self.sigs[i];
+        let call = Expression::Call(sig.name.clone(), args);
+        Some((wrap(bindings, call), sig.ret.clone()))
+    }
+
+    // Arguments of a call to the `i`th function, whose bindings are only in scope of the call
+    fn args(&mut self, i: usize, bindings: &mut Vec<(String, Expression)>) -> Option<Vec<Atom>> {
+        let scope = self.env.len();
+        let params = self.sigs[i].params.clone();
+        let args = params
+            .iter()
+            .map(|param| self.arg(param, bindings))
+            .collect::<Option<Vec<_>>>();
+        self.env.truncate(scope);
+        args
+    }
+
+    fn thunk(&mut self) -> Option<(Expression, Kind)> {
+        let i = self.rng.pick(&(0..self.sigs.len()).collect::<Vec<_>>())?;
+        let mut bindings = vec![];
+        let args = self.args(i, &mut bindings)?;
+        let sig = &self.sigs[i];
+        let thunk = Expression::Thunk(sig.name.clone(), args);
+        Some((wrap(bindings, thunk), Kind::Thunk(sig.ret.clone().into())))
+    }
+
+    // Forces a thunk in scope, or spawns a fiber forcing it and joins it
+    fn force(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        let thunks = self.env.iter().filter_map(|(t, kind)| match kind {
+            Kind::Thunk(ret) if want.accepts(ret) => Some((t.clone(), (**ret).clone())),
+            _ => None,
+        });
+        let (t, kind) = self.rng.pick(&thunks.collect::<Vec<_>>())?;
+        if self.rng.chance(50) {
+            return Some((Expression::Force(t), kind));
+        }
+        let f = self.fresh("f");
+        let mut bindings = vec![(
+            f.clone(),
+            Expression::Prim(Primitive::Spawn, vec![Atom::Var(t)]),
+        )];
+        if self.rng.chance(50) {
+            bindings.push((self.fresh("v"), Expression::Prim(Primitive::Yield, vec![])));
+        }
+        let join = Expression::Prim(Primitive::Join, vec![Atom::Var(f)]);
+        Some((wrap(bindings, join), kind))
+    }
+
+    // Number computed through a string, an array, a reference cell or floats
+    fn cell(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        let Want::Num = want else {
+            return None;
+        };
+        let (x, Kind::Num(lx, hx)) = self.atom(&Want::Num)? else {
+            unreachable!()
+        };
+        let (y, Kind::Num(ly, hy)) = self.atom(&Want::Num)? else {
+            unreachable!()
+        };
+        let (c, u) = (self.fresh("v"), self.fresh("v"));
+        let var = Atom::Var(c.clone());
+        let (bindings, expr, range) = match self.rng.below(4) {
+            0 => {
+                let s = (0..1 + self.rng.below(5))
+                    .map(|_| b'a' + self.rng.below(26) as u8)
+                    .collect::<Vec<_>>();
+                let i = self.rng.below(s.len());
+                let (expr, n) = match self.rng.chance(50) {
+                    true => (Expression::Prim(Primitive::StrLen, vec![var]), s.len()),
+                    false => {
+                        let args = vec![var, Atom::Lit(i)];
+                        (Expression::Prim(Primitive::StrGet, args), s[i] as usize)
+                    }
+                };
+                (vec![(c, Expression::Str(s))], expr, (n as u64, n as u64))
+            }
+            1 => {
+                let len = 1 + self.rng.below(4);
+                let (i, j) = (self.rng.below(len), self.rng.below(len));
+                let new = Expression::Prim(Primitive::ArrayNew, vec![Atom::Lit(len), x]);
+                let set = Expression::Prim(Primitive::ArraySet, vec![var.clone(), Atom::Lit(i), y]);
+                let get = Expression::Prim(Primitive::ArrayGet, vec![var, Atom::Lit(j)]);
+                let range = if i == j { (ly, hy) } else { (lx, hx) };
+                (vec![(c, new), (u, set)], get, range)
+            }
+            2 => {
+                let store = Expression::Store(x);
+                let update = Expression::Update(c.clone(), y);
+                let fetch = Expression::Fetch(c.clone());
+                match self.rng.chance(50) {
+                    true => (vec![(c, store), (u, update)], fetch, (ly, hy)),
+                    false => (vec![(c, store)], fetch, (lx, hx)),
+                }
+            }
+            // Adding k + 0.5 and truncating adds k, as numbers up to `NUM_MAX` are exact
+            _ => {
+                let k = self.rng.below(3);
+                let float = Expression::Prim(Primitive::IntToFloat, vec![x]);
+                let add = Expression::Operate(Operation::FAdd, var, Atom::Float(k as f64 + 0.5));
+                let int = Expression::Prim(Primitive::FloatToInt, vec![Atom::Var(u.clone())]);
+                let k = k as u64;
+                (vec![(c, float), (u, add)], int, (lx + k, hx + k))
+            }
+        };
+        Some((wrap(bindings, expr), Kind::Num(range.0, range.1)))
+    }
+
+    // Either the body throws an atom after a binding, or it returns normally
+    fn catch(&mut self, want: &Want, depth: usize) -> Option<(Expression, Kind)> {
+        let Want::Num = want else {
+            return None;
+        };
+        let scope = self.env.len();
+        let (body, thrown, ret) = match self.rng.chance(50) {
+            true => {
+                let (val, kind) = self.expr(&Want::Num, depth - 1);
+                let x = self.fresh("v");
+                self.env.push((x.clone(), kind));
+                let (atom, thrown) = self.atom(&Want::Num)?;
+                let body = Expression::Let(x, val.into(), Expression::Throw(atom).into());
+                (body, thrown, None)
+            }
+            false => {
+                let (body, ret) = self.expr(&Want::Num, depth - 1);
+                (body, ARG, Some(ret))
+            }
+        };
+        self.env.truncate(scope);
+        let e = self.fresh("e");
+        self.env.push((e.clone(), thrown));
+        let (handler, Kind::Num(lo, hi)) = self.expr(&Want::Num, depth - 1) else {
+            unreachable!()
+        };
+        self.env.truncate(scope);
+        let range = match ret {
+            Some(Kind::Num(lx, hx)) => union((lx, hx), (lo, hi)),
+            _ => (lo, hi),
+        };
+        let catch = Expression::Catch(body.into(), e, handler.into());
+        Some((catch, Kind::Num(range.0, range.1)))
+    }
+
+    // The body performs an atom right away, and the handler either resumes it or returns
+    fn handle(&mut self, want: &Want, depth: usize) -> Option<(Expression, Kind)> {
+        let Want::Num = want else {
+            return None;
+        };
+        let scope = self.env.len();
+        let (performed, kind) = self.atom(&Want::Num)?;
+        let (x, k) = (self.fresh("x"), self.fresh("k"));
+        let (resumed, resumed_kind) = match self.rng.chance(50) {
+            true => (Atom::Var(x.clone()), kind.clone()),
+            false => self.literal(),
+        };
+        let r = self.fresh("v");
+        self.env.push((r.clone(), resumed_kind));
+        let (rest, ret) = self.expr(&Want::Num, depth - 1);
+        self.env.truncate(scope);
+        let body = Expression::Let(r, Expression::Perform(performed).into(), rest.into());
+        let (handler, ret) = match self.rng.chance(60) {
+            true => (Expression::Resume(k.clone(), resumed), ret),
+            false => {
+                self.env.push((x.clone(), kind));
+                let handler = self.expr(&Want::Num, depth - 1);
+                self.env.truncate(scope);
+                handler
+            }
+        };
+        Some((Expression::Handle(body.into(), x, k, handler.into()), ret))
+    }
+
+    // Closures can be partially applied, or applied to more arguments than they expect when
+    // they return closures
+    fn apply(&mut self, want: &Want) -> Option<(Expression, Kind)> {
+        let mut options = vec![];
+        for (f, kind) in self.env.iter() {
+            let Kind::Closure(n, ret) = kind else {
+                continue;
+            };
+            for count in 1..*n {
+                options.push((f.clone(), count, Kind::Closure(n - count, ret.clone())));
+            }
+            options.push((f.clone(), *n, (**ret).clone()));
+            if let Kind::Closure(m, ret) = &**ret {
+                options.push((f.clone(), n + m, (**ret).clone()));
+            }
+        }
+        options.retain(|(_, _, kind)| want.accepts(kind));
+        let (f, count, kind) = self.rng.pick(&options)?;
+        let mut bindings = vec![];
+        let args = (0..count)
+            .map(|_| self.arg(&ARG, &mut bindings))
+            .collect::<Option<Vec<_>>>()?;
+        Some((Expression::Apply(f, args), kind))
+    }
+}
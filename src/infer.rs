@@ -0,0 +1,726 @@
+use crate::callgraph::CallGraph;
+use crate::expr::{Atom, Definition, Expression, Type};
+use crate::lexer::Span;
+use crate::module::Module;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Types are nodes of a graph, where unification links nodes together. Since the graph is
+// allowed to be cyclic, recursive types like the ones of Church-encoded lists are inferred
+// without any annotation. Functions are curried, so that applying a closure to more
+// arguments than it takes is the same as applying its result to the remaining ones
+type Node = usize;
+
+#[derive(Clone)]
+enum Term {
+    Var,
+    Link(Node),
+    Int,
+    Arrow(Node, Node),
+    // Closure with no remaining parameters, which calls its function once applied
+    Thunk(Node),
+    Con(String, Vec<Node>),
+}
+
+// Inferred type of a definition, whose type variables are all generalized
+pub struct Signature {
+    pub name: String,
+    pub params: Vec<String>,
+    pub typ: Type,
+}
+
+#[derive(Debug)]
+pub struct InferError {
+    pub function: String,
+    pub span: Span,
+    pub expected: Type,
+    pub found: Type,
+    pub context: String,
+}
+
+impl fmt::Display for InferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "In function `{}`: expected type `{:?}`, found `{:?}` in {}",
+            self.function,
+            self.expected.pretty(),
+            self.found.pretty(),
+            self.context
+        )
+    }
+}
+
+pub fn infer(module: &Module) -> Result<Vec<Signature>, Vec<InferError>> {
+    let mut infer = Infer {
+        module,
+        terms: vec![],
+        trail: vec![],
+        types: HashMap::new(),
+        generalized: HashSet::new(),
+        spans: HashMap::new(),
+        errors: vec![],
+    };
+    // Extern functions have the type of their annotations, where missing annotations are type
+    // variables, instantiated at each use like those of any other generalized function
+    for (name, decl) in module.externs() {
+        let mut vars = HashMap::new();
+        let mut node = |typ: &Option<Type>| match typ {
+            Some(typ) => infer.type_node(typ, &mut vars),
+            None => infer.fresh(),
+        };
+        let params = decl.param_types.iter().map(&mut node).collect::<Vec<_>>();
+        let ret = node(&decl.ret_type);
+        let typ = infer.arrows(&params, ret);
+        infer.types.insert(name, typ);
+        infer.generalized.insert(name);
+    }
+    // Definitions are generalized one strongly connected component at a time, after the
+    // components they depend on
+    for group in CallGraph::new(module).components() {
+        for name in group.iter() {
+            let node = infer.fresh();
+            infer.types.insert(name, node);
+        }
+        for name in group.iter() {
+            infer.definition(module.get(name).unwrap());
+        }
+        infer.generalized.extend(group);
+    }
+    if !infer.errors.is_empty() {
+        return Err(infer.errors);
+    }
+    let mut defs = module.toplevel().values().collect::<Vec<_>>();
+    defs.sort_by(|a, b| a.name.cmp(&b.name));
+    let signatures = defs
+        .into_iter()
+        .map(|def| Signature {
+            name: def.name.clone(),
+            params: def.params.clone(),
+            typ: infer.signature(infer.types[def.name.as_str()]),
+        })
+        .collect();
+    Ok(signatures)
+}
+
+struct Infer<'a> {
+    module: &'a Module,
+    terms: Vec<Term>,
+    // Previous terms of the nodes changed by the current unification, so it can be undone
+    trail: Vec<(Node, Term)>,
+    types: HashMap<&'a str, Node>,
+    // Definitions whose type is final, and must be instantiated at each use
+    generalized: HashSet<&'a str>,
+    // Spans of the expressions of the current definition, by their address
+    spans: HashMap<*const Expression, Span>,
+    errors: Vec<InferError>,
+}
+
+impl<'a> Infer<'a> {
+    fn add(&mut self, term: Term) -> Node {
+        self.terms.push(term);
+        self.terms.len() - 1
+    }
+
+    fn fresh(&mut self) -> Node {
+        self.add(Term::Var)
+    }
+
+    fn find(&self, mut node: Node) -> Node {
+        while let Term::Link(next) = self.terms[node] {
+            node = next;
+        }
+        node
+    }
+
+    fn set(&mut self, node: Node, term: Term) {
+        let old = std::mem::replace(&mut self.terms[node], term);
+        self.trail.push((node, old));
+    }
+
+    // Nodes are linked before their children are unified, which makes unification
+    // terminate on cyclic graphs
+    fn unify_inner(&mut self, x: Node, y: Node) -> bool {
+        let (x, y) = (self.find(x), self.find(y));
+        if x == y {
+            return true;
+        }
+        match (self.terms[x].clone(), self.terms[y].clone()) {
+            (Term::Var, _) => self.set(x, Term::Link(y)),
+            (_, Term::Var) => self.set(y, Term::Link(x)),
+            (Term::Int, Term::Int) => self.set(x, Term::Link(y)),
+            (Term::Arrow(x1, x2), Term::Arrow(y1, y2)) => {
+                self.set(x, Term::Link(y));
+                return self.unify_inner(x1, y1) && self.unify_inner(x2, y2);
+            }
+            (Term::Thunk(x1), Term::Thunk(y1)) => {
+                self.set(x, Term::Link(y));
+                return self.unify_inner(x1, y1);
+            }
+            (Term::Con(n, xs), Term::Con(m, ys)) if n == m && xs.len() == ys.len() => {
+                self.set(x, Term::Link(y));
+                return xs.into_iter().zip(ys).all(|(x, y)| self.unify_inner(x, y));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Mismatches are reported at the definition, for its annotations and uses
+    fn unify(
+        &mut self,
+        expected: Node,
+        found: Node,
+        def: &Definition,
+        context: impl Fn() -> String,
+    ) {
+        self.unify_spanned(expected, found, def, def.span, context);
+    }
+
+    // Mismatches are reported at the expression, or at the definition when it has no span
+    fn unify_at(&mut self, expected: Node, found: Node, def: &Definition, expr: &Expression) {
+        let span = self.spans.get(&(expr as *const _)).copied();
+        let span = span.unwrap_or(def.span);
+        self.unify_spanned(expected, found, def, span, || context(expr));
+    }
+
+    fn unify_spanned(
+        &mut self,
+        expected: Node,
+        found: Node,
+        def: &Definition,
+        span: Span,
+        context: impl Fn() -> String,
+    ) {
+        self.trail.clear();
+        if self.unify_inner(expected, found) {
+            return;
+        }
+        while let Some((node, term)) = self.trail.pop() {
+            self.terms[node] = term;
+        }
+        self.errors.push(InferError {
+            function: def.name.clone(),
+            span,
+            expected: self.to_type(expected),
+            found: self.to_type(found),
+            context: context(),
+        });
+    }
+
+    fn instantiate(&mut self, node: Node, copies: &mut HashMap<Node, Node>) -> Node {
+        let node = self.find(node);
+        if let Some(copy) = copies.get(&node) {
+            return *copy;
+        }
+        let copy = self.fresh();
+        copies.insert(node, copy);
+        let term = match self.terms[node].clone() {
+            Term::Var => return copy,
+            Term::Link(_) => unreachable!(),
+            Term::Int => Term::Int,
+            Term::Arrow(x, y) => {
+                Term::Arrow(self.instantiate(x, copies), self.instantiate(y, copies))
+            }
+            Term::Thunk(x) => Term::Thunk(self.instantiate(x, copies)),
+            Term::Con(name, args) => {
+                let args = args.into_iter().map(|x| self.instantiate(x, copies));
+                Term::Con(name, args.collect())
+            }
+        };
+        self.terms[copy] = term;
+        copy
+    }
+
+    fn function(&mut self, name: &str) -> Node {
+        let node = self.types[name];
+        if self.generalized.contains(name) {
+            self.instantiate(node, &mut HashMap::new())
+        } else {
+            node
+        }
+    }
+
+    // The type of a function taking `params` and returning `ret`
+    fn arrows(&mut self, params: &[Node], ret: Node) -> Node {
+        params
+            .iter()
+            .rev()
+            .fold(ret, |ret, param| self.add(Term::Arrow(*param, ret)))
+    }
+
+    fn type_node(&mut self, typ: &Type, vars: &mut HashMap<String, Node>) -> Node {
+        match typ {
+            Type::Int => self.add(Term::Int),
+            Type::Fn(params, ret) => {
+                let ret = self.type_node(ret, vars);
+                if params.is_empty() {
+                    return self.add(Term::Thunk(ret));
+                }
+                let params = params
+                    .iter()
+                    .map(|param| self.type_node(param, vars))
+                    .collect::<Vec<_>>();
+                self.arrows(&params, ret)
+            }
+            Type::Con(name, args) => {
+                let args = args.iter().map(|arg| self.type_node(arg, vars));
+                let term = Term::Con(name.clone(), args.collect());
+                self.add(term)
+            }
+            Type::Var(name) => match vars.get(name) {
+                Some(node) => *node,
+                None => {
+                    let node = self.fresh();
+                    vars.insert(name.clone(), node);
+                    node
+                }
+            },
+            Type::Rec(name, body) => {
+                let node = self.fresh();
+                let outer = vars.insert(name.clone(), node);
+                let body = self.type_node(body, vars);
+                if body != node {
+                    self.terms[node] = Term::Link(body);
+                }
+                match outer {
+                    Some(outer) => vars.insert(name.clone(), outer),
+                    None => vars.remove(name),
+                };
+                body
+            }
+        }
+    }
+
+    fn definition(&mut self, def: &'a Definition) {
+        let mut exprs = vec![];
+        preorder(&def.body, &mut exprs);
+        let spans = exprs.into_iter().zip(def.spans.iter().copied());
+        self.spans = spans.map(|(expr, span)| (expr as *const _, span)).collect();
+        let mut env = HashMap::new();
+        let mut vars = HashMap::new();
+        let params = def.params.iter().zip(def.param_types.iter());
+        let params = params
+            .map(|(param, typ)| {
+                let node = self.fresh();
+                if let Some(typ) = typ {
+                    let annotation = self.type_node(typ, &mut vars);
+                    self.unify(annotation, node, def, || format!("parameter `{param}`"));
+                }
+                env.insert(param.as_str(), node);
+                node
+            })
+            .collect::<Vec<_>>();
+        let ret = self.expr(def, &def.body, &mut env);
+        if let Some(typ) = &def.ret_type {
+            let annotation = self.type_node(typ, &mut vars);
+            self.unify(annotation, ret, def, || "the return value".into());
+        }
+        let typ = self.arrows(&params, ret);
+        let node = self.types[def.name.as_str()];
+        self.unify(node, typ, def, || format!("the uses of `{}`", def.name));
+    }
+
+    fn atom(&mut self, atom: &Atom, env: &HashMap<&'a str, Node>) -> Node {
+        match atom {
+            Atom::Var(x) => env[x.as_str()],
+            Atom::Lit(_) => self.add(Term::Int),
+            Atom::Float(_) => self.type_node(&Type::float(), &mut HashMap::new()),
+        }
+    }
+
+    fn atoms(&mut self, atoms: &[Atom], env: &HashMap<&'a str, Node>) -> Vec<Node> {
+        atoms.iter().map(|atom| self.atom(atom, env)).collect()
+    }
+
+    fn apply(&mut self, def: &Definition, expr: &Expression, func: Node, args: &[Node]) -> Node {
+        if let Term::Thunk(ret) = self.terms[self.find(func)] {
+            return self.apply(def, expr, ret, args);
+        }
+        if args.is_empty() {
+            return func;
+        }
+        let ret = self.fresh();
+        let typ = self.arrows(args, ret);
+        self.unify_at(func, typ, def, expr);
+        ret
+    }
+
+    fn expr(
+        &mut self,
+        def: &'a Definition,
+        expr: &'a Expression,
+        env: &mut HashMap<&'a str, Node>,
+    ) -> Node {
+        match expr {
+            Expression::Unit(atom) => self.atom(atom, env),
+            Expression::Let(x, val, body) => {
+                let node = self.expr(def, val, env);
+                env.insert(x, node);
+                self.expr(def, body, env)
+            }
+            Expression::Apply(f, args) => {
+                let args = self.atoms(args, env);
+                self.apply(def, expr, env[f.as_str()], &args)
+            }
+            Expression::Call(f, args) => {
+                let func = self.function(f);
+                let args = self.atoms(args, env);
+                let ret = self.fresh();
+                let typ = self.arrows(&args, ret);
+                self.unify_at(func, typ, def, expr);
+                ret
+            }
+            Expression::Papp(f, args) => {
+                let func = self.function(f);
+                let args = self.atoms(args, env);
+                let arity = self.module.arity(f).unwrap();
+                let ret = self.fresh();
+                let typ = self.arrows(&args, ret);
+                self.unify_at(func, typ, def, expr);
+                if args.len() == arity {
+                    return self.add(Term::Thunk(ret));
+                }
+                ret
+            }
+            Expression::Match(atom, cases, default) => {
+                let scrutinee = self.atom(atom, env);
+                let int = self.add(Term::Int);
+                self.unify_at(int, scrutinee, def, expr);
+                let ret = self.fresh();
+                let branches = cases.iter().map(|(_, e)| e).chain(default.as_deref());
+                for branch in branches {
+                    let node = self.expr(def, branch, env);
+                    self.unify_at(ret, node, def, branch);
+                }
+                ret
+            }
+            Expression::Operate(op, x, y) => {
+                let (operand, result) = op.signature();
+                for atom in [x, y] {
+                    let node = self.atom(atom, env);
+                    let operand = self.type_node(&operand, &mut HashMap::new());
+                    self.unify_at(operand, node, def, expr);
+                }
+                self.type_node(&result, &mut HashMap::new())
+            }
+            Expression::Prim(prim, args) => {
+                let (params, ret) = prim.signature();
+                let mut vars = HashMap::new();
+                for (atom, param) in args.iter().zip(params.iter()) {
+                    let node = self.atom(atom, env);
+                    let param = self.type_node(param, &mut vars);
+                    self.unify_at(param, node, def, expr);
+                }
+                self.type_node(&ret, &mut vars)
+            }
+            Expression::Str(_) => self.type_node(&Type::str(), &mut HashMap::new()),
+            Expression::Store(x) => {
+                let node = self.atom(x, env);
+                self.add(Term::Con("Ref".into(), vec![node]))
+            }
+            Expression::Fetch(p) => {
+                let content = self.fresh();
+                let reference = self.add(Term::Con("Ref".into(), vec![content]));
+                self.unify_at(reference, env[p.as_str()], def, expr);
+                content
+            }
+            Expression::Update(p, x) => {
+                let content = self.atom(x, env);
+                let reference = self.add(Term::Con("Ref".into(), vec![content]));
+                self.unify_at(reference, env[p.as_str()], def, expr);
+                self.add(Term::Int)
+            }
+            Expression::Thunk(f, args) => {
+                let func = self.function(f);
+                let args = self.atoms(args, env);
+                let ret = self.fresh();
+                let typ = self.arrows(&args, ret);
+                self.unify_at(func, typ, def, expr);
+                self.add(Term::Con("Lazy".into(), vec![ret]))
+            }
+            Expression::Force(t) => {
+                let ret = self.fresh();
+                let thunk = self.add(Term::Con("Lazy".into(), vec![ret]));
+                self.unify_at(thunk, env[t.as_str()], def, expr);
+                ret
+            }
+            // Exceptions are not tracked by types: any value can be thrown, and the handler
+            // knows nothing about the one it catches
+            Expression::Throw(x) => {
+                self.atom(x, env);
+                self.fresh()
+            }
+            Expression::Catch(body, x, handler) => {
+                let ret = self.expr(def, body, env);
+                let exception = self.fresh();
+                env.insert(x, exception);
+                let node = self.expr(def, handler, env);
+                self.unify_at(ret, node, def, handler);
+                ret
+            }
+            // Like exceptions, performed values are not tracked, and neither are the values
+            // given back to `perform`. A continuation of type `Cont(a, b)` is resumed with a
+            // value of type `a`, and returns the result of its `handle`, of type `b`
+            Expression::Perform(x) => {
+                self.atom(x, env);
+                self.fresh()
+            }
+            Expression::Handle(body, x, k, handler) => {
+                let ret = self.expr(def, body, env);
+                let performed = self.fresh();
+                env.insert(x, performed);
+                let resumed = self.fresh();
+                let cont = self.add(Term::Con("Cont".into(), vec![resumed, ret]));
+                env.insert(k, cont);
+                let node = self.expr(def, handler, env);
+                self.unify_at(ret, node, def, handler);
+                ret
+            }
+            Expression::Resume(k, x) => {
+                let resumed = self.atom(x, env);
+                let ret = self.fresh();
+                let cont = self.add(Term::Con("Cont".into(), vec![resumed, ret]));
+                self.unify_at(cont, env[k.as_str()], def, expr);
+                ret
+            }
+        }
+    }
+
+    fn to_type(&self, node: Node) -> Type {
+        let mut recursive = HashSet::new();
+        self.find_recursive(node, &mut vec![], &mut HashSet::new(), &mut recursive);
+        let mut names = HashMap::new();
+        self.to_type_inner(node, &recursive, &mut names)
+    }
+
+    // Like `to_type`, but a recursive type is unrolled once at the top, so that the parameters
+    // of a definition can always be read off its type
+    fn signature(&self, node: Node) -> Type {
+        let node = self.find(node);
+        let mut recursive = HashSet::new();
+        self.find_recursive(node, &mut vec![], &mut HashSet::new(), &mut recursive);
+        let mut names = HashMap::new();
+        self.to_type_body(node, &recursive, &mut names)
+    }
+
+    // Nodes that are reachable from themselves must be printed as recursive types
+    fn find_recursive(
+        &self,
+        node: Node,
+        path: &mut Vec<Node>,
+        done: &mut HashSet<Node>,
+        recursive: &mut HashSet<Node>,
+    ) {
+        let node = self.find(node);
+        if path.contains(&node) {
+            recursive.insert(node);
+            return;
+        }
+        if !done.insert(node) {
+            return;
+        }
+        path.push(node);
+        for child in self.children(node) {
+            self.find_recursive(child, path, done, recursive);
+        }
+        path.pop();
+    }
+
+    fn children(&self, node: Node) -> Vec<Node> {
+        match &self.terms[node] {
+            Term::Arrow(x, y) => vec![*x, *y],
+            Term::Thunk(x) => vec![*x],
+            Term::Con(_, args) => args.clone(),
+            _ => vec![],
+        }
+    }
+
+    fn name(node: Node, names: &mut HashMap<Node, String>) -> String {
+        let next = names.len();
+        let name = names.entry(node).or_insert_with(|| {
+            let letter = (b'a' + (next % 26) as u8) as char;
+            match next / 26 {
+                0 => letter.to_string(),
+                n => format!("{letter}{n}"),
+            }
+        });
+        name.clone()
+    }
+
+    fn to_type_inner(
+        &self,
+        node: Node,
+        recursive: &HashSet<Node>,
+        names: &mut HashMap<Node, String>,
+    ) -> Type {
+        let node = self.find(node);
+        if recursive.contains(&node) && names.contains_key(&node) {
+            return Type::Var(names[&node].clone());
+        }
+        if recursive.contains(&node) {
+            let name = Self::name(node, names);
+            let body = self.to_type_body(node, recursive, names);
+            return Type::Rec(name, body.into());
+        }
+        self.to_type_body(node, recursive, names)
+    }
+
+    fn to_type_body(
+        &self,
+        node: Node,
+        recursive: &HashSet<Node>,
+        names: &mut HashMap<Node, String>,
+    ) -> Type {
+        match &self.terms[node] {
+            Term::Var => Type::Var(Self::name(node, names)),
+            Term::Link(_) => unreachable!(),
+            Term::Int => Type::Int,
+            Term::Arrow(x, y) => {
+                let mut params = vec![self.to_type_inner(*x, recursive, names)];
+                let mut ret = self.find(*y);
+                while let Term::Arrow(x, y) = self.terms[ret] {
+                    if recursive.contains(&ret) {
+                        break;
+                    }
+                    params.push(self.to_type_inner(x, recursive, names));
+                    ret = self.find(y);
+                }
+                Type::Fn(params, self.to_type_inner(ret, recursive, names).into())
+            }
+            Term::Thunk(x) => Type::Fn(vec![], self.to_type_inner(*x, recursive, names).into()),
+            Term::Con(name, args) => {
+                let args = args.iter();
+                let args = args.map(|arg| self.to_type_inner(*arg, recursive, names));
+                Type::Con(name.clone(), args.collect())
+            }
+        }
+    }
+}
+
+fn context(expr: &Expression) -> String {
+    format!("`{:?}`", expr.pretty())
+}
+
+// The expression and the ones it holds, in the order the parser records their spans
+fn preorder<'e>(expr: &'e Expression, exprs: &mut Vec<&'e Expression>) {
+    exprs.push(expr);
+    match expr {
+        Expression::Let(_, val, body) => {
+            preorder(val, exprs);
+            preorder(body, exprs);
+        }
+        Expression::Match(_, cases, default) => {
+            let branches = cases.iter().map(|(_, e)| e).chain(default.as_deref());
+            branches.for_each(|branch| preorder(branch, exprs));
+        }
+        Expression::Catch(body, _, handler) | Expression::Handle(body, _, _, handler) => {
+            preorder(body, exprs);
+            preorder(handler, exprs);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    // Errors preceded by the line and column where they are reported
+    fn errors(source: &str) -> Vec<String> {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        let module = Module::new(ParserScanner::new(tokens).map(|m| m.unwrap()));
+        let errors = infer(&module).err().unwrap_or_default();
+        let errors = errors.iter().map(|err| {
+            let (line, col) = err.span.line_col(source);
+            format!("{line}:{col}: {err}")
+        });
+        errors.collect()
+    }
+
+    // Signatures printed as annotated headers, like `--infer` does
+    fn signatures(source: &str) -> Vec<String> {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        let module = Module::new(ParserScanner::new(tokens).map(|m| m.unwrap()));
+        let sigs = infer(&module).unwrap();
+        sigs.iter()
+            .map(|sig| format!("{:?}", sig.pretty()))
+            .collect()
+    }
+
+    #[test]
+    fn functions_are_generalized() {
+        let source = "fn (id x) {
+  x
+}
+
+fn (flip f x y) {
+  (apply f y x)
+}
+
+fn (main) {
+  let a = (id 1);
+  let s = \"a\";
+  let t = (id s);
+  let i = (papp id);
+  let g = (papp flip i);
+  a
+}";
+        assert_eq!(
+            signatures(source),
+            [
+                "fn (flip f: Fn(a, b) -> c x: b y: a) -> c",
+                "fn (id x: a) -> a",
+                "fn (main) -> Int",
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive_types_are_inferred() {
+        let signatures = signatures(include_str!("../corpus/sample.ir"));
+        let church = ["nil", "cons", "buildList", "sumList", "sumListAux"];
+        let church = signatures.into_iter().filter(|sig| {
+            church
+                .iter()
+                .any(|name| sig.starts_with(&format!("fn ({name} ")))
+        });
+        assert_eq!(
+            church.collect::<Vec<_>>(),
+            [
+                "fn (buildList n: Int) -> Rec(a, Fn(b, Fn(Int, a) -> b) -> b)",
+                "fn (cons x: a xs: b n: c c: Fn(a, b) -> d) -> d",
+                "fn (nil n: a c: b) -> a",
+                "fn (sumList xs: Rec(a, Fn(Int, Fn(Int, a) -> Int) -> Int)) -> Int",
+                "fn (sumListAux x: Int ys: Fn(Int, Rec(a, Fn(Int, Fn(Int, a) -> Int) -> Int)) -> Int) \
+                 -> Int",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_expressions() {
+        let source = "fn (inc x: Int) -> Int {
+  (+ x 1)
+}
+
+fn (main) {
+  let s = \"a\";
+  let n = (inc s);
+  match n {
+    0 => { n }
+    _ => { s }
+  }
+}";
+        assert_eq!(
+            errors(source),
+            [
+                "7:11: In function `main`: expected type `Fn(Int) -> Int`, found `Fn(Str) -> a` \
+                 in `(inc s)`",
+                "10:12: In function `main`: expected type `Int`, found `Str` in `s`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use crate::expr::{Declaration, Definition};
+use crate::lexer::{LexerError, Scanner as LexerScanner};
+use crate::parser::{ParserError, Scanner as ParserScanner};
+use std::fmt;
+
+// Functions exported by a module, with their arities and annotations. Modules importing it
+// are checked against its interface instead of its source. Interfaces are written in the
+// syntax of the IR, with declarations in place of definitions:
+//
+// module List;
+//
+// fn (range n: Int);
+#[derive(Debug, PartialEq)]
+pub struct Interface {
+    pub module: String,
+    pub declarations: Vec<Declaration>,
+}
+
+#[derive(Debug)]
+pub enum InterfaceError {
+    Lexer(LexerError),
+    Parser(ParserError),
+    Unnamed,
+}
+
+impl fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceError::Lexer(err) => write!(f, "{err}"),
+            InterfaceError::Parser(err) => write!(f, "{err}"),
+            InterfaceError::Unnamed => write!(f, "Interface does not declare its module"),
+        }
+    }
+}
+
+impl Interface {
+    pub fn new(module: &str, definitions: &[Definition]) -> Self {
+        let declarations = definitions.iter().map(|def| Declaration {
+            name: def.name.clone(),
+            params: def.params.clone(),
+            param_types: def.param_types.clone(),
+            ret_type: def.ret_type.clone(),
+        });
+        Self {
+            module: module.to_owned(),
+            declarations: declarations.collect(),
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, InterfaceError> {
+        let tokens = LexerScanner::new(source)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(InterfaceError::Lexer)?;
+        let mut parser = ParserScanner::new(tokens.into_iter());
+        let header = parser.parse_header().map_err(InterfaceError::Parser)?;
+        let module = header.name.ok_or(InterfaceError::Unnamed)?;
+        let mut declarations = vec![];
+        while let Some(decl) = parser.parse_declaration() {
+            declarations.push(decl.map_err(InterfaceError::Parser)?);
+        }
+        Ok(Self {
+            module,
+            declarations,
+        })
+    }
+}
+
+// Laid out like formatted source
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "module {};", self.module)?;
+        self.declarations
+            .iter()
+            .try_for_each(|decl| write!(f, "\n{:?}\n", decl.pretty()))
+    }
+}
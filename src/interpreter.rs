@@ -1,41 +1,66 @@
 use crate::{
-    expr::{Atom, Expression, Operation},
+    bignum::BigNum,
+    expr::{Atom, Expression, Operation, Primitive},
     module::Module,
 };
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{Read, Write};
 
 type Ptr = u32;
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Value {
     Num(usize),
+    Float(f64),
     Ptr(Ptr),
 }
 
-enum HeapCell {
+enum HeapCell<'m> {
     Papp(String, Vec<Value>),
+    // Immutable byte string
+    Str(Vec<u8>),
+    Array(Vec<Value>),
+    // Big integer, which is immutable like strings
+    Big(BigNum),
+    // Mutable reference cell, created by `store`
+    Ref(Value),
+    // Suspended call, replaced by its result once forced
+    Thunk(String, Vec<Value>),
+    Evaluated(Value),
+    // Thunk being forced by the fiber, which only forces it again if it depends on itself
+    Blackhole(FiberId),
+    // Continuation captured by `perform`, with the registers of the function that performed
+    Cont(Vec<Frame<'m>>, Registers),
+    Chan(Channel),
 }
 
-impl Value {
-    fn expect_num(self) -> usize {
-        match self {
-            Value::Num(x) => x,
-            _ => panic!("Expected number"),
-        }
-    }
+// Messages sent and not received yet, with the fibers waiting to send or receive, in the order
+// they started waiting. Fibers only wait to send while the channel is full, and to receive
+// while it is empty
+struct Channel {
+    messages: VecDeque<Value>,
+    // `None` for unbounded channels
+    capacity: Option<usize>,
+    senders: VecDeque<(FiberId, Value)>,
+    receivers: VecDeque<FiberId>,
+}
 
+impl Value {
     fn expect_ptr(self) -> Ptr {
         match self {
             Value::Ptr(x) => x,
-            _ => panic!("Expected partial application"),
+            _ => panic!("Expected pointer"),
         }
     }
 }
 
-struct Frame(HashMap<String, Value>);
+// Variables of a function call, each of which is assigned once
+#[derive(Clone)]
+struct Registers(HashMap<String, Value>);
 
-impl Frame {
+impl Registers {
     fn new() -> Self {
         Self(HashMap::new())
     }
@@ -49,14 +74,70 @@ impl Frame {
     }
 }
 
+// What remains to do with the value of the expression being evaluated. Keeping these on
+// `State::stack` instead of Rust's stack lets `perform` capture them as a heap object
+#[derive(Clone)]
+enum Frame<'m> {
+    // Binds the variable to the value, then evaluates the body
+    Let(&'m str, &'m Expression),
+    // Returns the value to a caller, restoring its registers
+    Return(Registers),
+    // Applies the value, a closure, to the remaining arguments
+    Apply(Vec<Value>),
+    // Replaces the thunk by the value. The suspended call is kept to restore the thunk if it is
+    // interrupted
+    Force(Ptr, String, Vec<Value>),
+    // Handler of the exceptions thrown by the body
+    Catch(&'m str, &'m Expression),
+    // Handler of the effects performed by the body, with the variables bound to the performed
+    // value and to the continuation
+    Handle(&'m str, &'m str, &'m Expression),
+}
+
+type FiberId = usize;
+
+// What a fiber does when it is not running
+enum Status {
+    Running,
+    // Waiting for its turn to force the thunk, which it was spawned with or was blocked on
+    Force(Ptr),
+    // Waiting for its turn to continue with the value
+    Ready(Value),
+    // Waiting for the fiber to finish
+    Join(FiberId),
+    // Waiting for another fiber to evaluate the thunk
+    Blocked(Ptr),
+    // Waiting for room in the channel, or for a message from it
+    Send(Ptr),
+    Recv(Ptr),
+    Done(Value),
+}
+
+// Green thread, with its own stack and registers but sharing the heap with the other fibers.
+// Those of the running fiber are in `State` instead
+struct Fiber<'m> {
+    stack: Vec<Frame<'m>>,
+    registers: Registers,
+    status: Status,
+}
+
+// Next step of the interpreter
+enum Control<'m> {
+    Eval(&'m Expression),
+    Return(Value),
+    Halt(Halt),
+}
+
 static INIT_HEAP_SIZE: usize = 1 << 24;
 static INIT_STACK_SIZE: usize = 1 << 18;
+// Bytes of the largest array or big integer, since failing to allocate would abort the process
+static MAX_ALLOC: usize = 1 << 32;
 
-fn alloc_heap() -> Vec<HeapCell> {
+fn alloc_heap<'m>() -> Vec<HeapCell<'m>> {
     Vec::with_capacity(INIT_HEAP_SIZE)
 }
 
-fn alloc_stack() -> Vec<Frame> {
+fn alloc_stack<'m>() -> Vec<Frame<'m>> {
     Vec::with_capacity(INIT_STACK_SIZE)
 }
 
@@ -67,154 +148,1398 @@ fn push_within_capacity<T>(mem: &mut Vec<T>, val: T, msg: &str) {
     mem.push(val)
 }
 
-pub struct State {
-    heap: Vec<HeapCell>,
-    stack: Vec<Frame>,
-    frame: Frame,
+// Implementation of an extern function by the host
+pub type Native = Box<dyn Fn(&[Value]) -> Result<Value, RuntimeError>>;
+
+// Streams given to the I/O primitives, which tests replace to capture the output in memory
+pub trait Io {
+    // Writes to stdout (1) or stderr (2)
+    fn write(&mut self, stream: usize, bytes: &[u8]);
+    // Reads a byte from stdin, or `None` at the end of the input
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+// The streams of the process
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn write(&mut self, stream: usize, bytes: &[u8]) {
+        let res = match stream {
+            1 => std::io::stdout().write_all(bytes),
+            _ => std::io::stderr().write_all(bytes),
+        };
+        res.expect("Could not write output")
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        // Output is flushed first, so that prompts are shown before waiting for input
+        std::io::stdout().flush().expect("Could not write output");
+        let mut byte = [0];
+        match std::io::stdin()
+            .read(&mut byte)
+            .expect("Could not read input")
+        {
+            0 => None,
+            _ => Some(byte[0]),
+        }
+    }
+}
+
+// Errors of well-formed programs, which cannot be detected before running them
+#[derive(Debug, PartialEq)]
+pub enum RuntimeError {
+    // Index into a string or an array, with its length
+    OutOfBounds(usize, usize),
+    // Stream given to an output primitive that is neither stdout (1) nor stderr (2)
+    InvalidStream(usize),
+    // Integer given to `#write_byte` that does not fit in a byte
+    InvalidByte(usize),
+    // Start and end of a slice of a string, with its length
+    SliceOutOfBounds(usize, usize, usize),
+    // Thunk forced again while it is being forced
+    Loop,
+    // `perform` outside of any `handle`
+    Unhandled,
+    // Extern function with no closure bound to it by the host
+    Unbound(String),
+    // Fibers that have not finished, each with what it waits for
+    Deadlock(Vec<(usize, Wait)>),
+    // Float converted by `#float_to_int` that has no integer counterpart
+    NotRepresentable(f64),
+    // `#big_sub` of a greater big integer, whose result would be negative
+    NegativeDifference,
+    // Integer or big integer division by zero
+    DivisionByZero,
+    // Integer operation whose result does not fit in an integer, such as a negative difference
+    // or a shift by 64 bits or more, or big integer converted by `#big_to_int` that does not
+    Overflow,
+    // Operation given an operand that is not an integer, or not a float for float operations
+    Operand(Operation, Value),
+    // Primitive given a float where it takes an integer, or the other way around
+    Argument(Primitive, Value),
+    // `match` on a value that is not an integer
+    Scrutinee(Value),
+    // `match` with no case for its integer and no default
+    NoMatch(usize),
+    // Allocation of more than `MAX_ALLOC` bytes, with its size
+    TooLarge(usize),
+    // `#join` of a value that is not the id of a spawned fiber
+    NotAFiber(Value),
+    // Failure of a closure bound by the host, with its message
+    Native(String),
 }
 
-impl Operation {
-    fn run(&self, x: usize, y: usize) -> usize {
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::OutOfBounds(index, len) => {
+                write!(f, "Index {index} is out of bounds for length {len}")
+            }
+            RuntimeError::InvalidStream(stream) => write!(f, "Stream {stream} is not 1 or 2"),
+            RuntimeError::InvalidByte(b) => write!(f, "Integer {b} is not a byte"),
+            RuntimeError::SliceOutOfBounds(start, end, len) => {
+                write!(f, "Slice {start}..{end} is out of bounds for length {len}")
+            }
+            RuntimeError::Loop => write!(f, "<<loop>>"),
+            RuntimeError::Unhandled => write!(f, "Effect performed outside of any handler"),
+            RuntimeError::Unbound(name) => write!(f, "Extern function `{name}` is not bound"),
+            RuntimeError::Deadlock(waits) => {
+                write!(f, "Every fiber is waiting")?;
+                for (i, (fiber, wait)) in waits.iter().enumerate() {
+                    let sep = if i == 0 { ": " } else { ", " };
+                    write!(f, "{sep}fiber {fiber} {wait}")?;
+                }
+                Ok(())
+            }
+            RuntimeError::NotRepresentable(x) => {
+                write!(f, "Float {x:?} is not representable as an integer")
+            }
+            RuntimeError::NegativeDifference => {
+                write!(f, "Subtraction of a greater big integer")
+            }
+            RuntimeError::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeError::Overflow => write!(f, "Result does not fit in an integer"),
+            RuntimeError::Operand(op, val) if op.is_float() => {
+                write!(f, "Float operation `{:?}` applied to {val:?}", op.pretty())
+            }
+            RuntimeError::Operand(op, val) => {
+                write!(
+                    f,
+                    "Integer operation `{:?}` applied to {val:?}",
+                    op.pretty()
+                )
+            }
+            RuntimeError::Argument(prim, val) => {
+                write!(f, "Primitive `#{}` applied to {val:?}", prim.name())
+            }
+            RuntimeError::Scrutinee(val) => write!(f, "Match on {val:?}, which is not an integer"),
+            RuntimeError::NoMatch(n) => write!(f, "No case matches {n}"),
+            RuntimeError::TooLarge(size) => {
+                write!(f, "Allocation of {size} bytes is too large")
+            }
+            RuntimeError::NotAFiber(val) => write!(f, "{val:?} is not a fiber"),
+            RuntimeError::Native(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+// What a fiber waits for, when no fiber can run
+#[derive(Debug, PartialEq)]
+pub enum Wait {
+    // Fiber to finish
+    Join(usize),
+    // Fiber evaluating a thunk
+    Thunk(usize),
+    // Channel, by its address
+    Send(Ptr),
+    Recv(Ptr),
+}
+
+impl fmt::Display for Wait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Operation::Add => x + y,
-            Operation::Sub => x - y,
-            Operation::Mul => x * y,
-            Operation::Div => x / y,
-            Operation::Mod => x % y,
-            Operation::Eq => (x == y) as usize,
-            Operation::Lt => (x < y) as usize,
-            Operation::Le => (x <= y) as usize,
-            Operation::Gt => (x > y) as usize,
-            Operation::Ge => (x >= y) as usize,
-            Operation::And => x & y,
-            Operation::Or => x | y,
-            Operation::Xor => x ^ y,
-            Operation::Sr => x >> y,
-            Operation::Sl => x << y,
-        }
-    }
-}
-
-impl State {
+            Wait::Join(fiber) => write!(f, "joins fiber {fiber}"),
+            Wait::Thunk(fiber) => write!(f, "forces a thunk evaluated by fiber {fiber}"),
+            Wait::Send(chan) => write!(f, "sends to full channel {chan}"),
+            Wait::Recv(chan) => write!(f, "receives from empty channel {chan}"),
+        }
+    }
+}
+
+// Reason for the evaluation to stop before returning a value
+#[derive(Debug, PartialEq)]
+pub enum Halt {
+    // `#exit` with its code
+    Exit(usize),
+    Error(RuntimeError),
+    // Exception raised by `throw` and not caught yet
+    Throw(Value),
+}
+
+// Value returned by `#read_byte` at the end of the input
+pub static EOF: usize = 256;
+
+pub struct State<'m> {
+    heap: Vec<HeapCell<'m>>,
+    stack: Vec<Frame<'m>>,
+    registers: Registers,
+    // Every fiber started, `main` being the first one
+    fibers: Vec<Fiber<'m>>,
+    current: FiberId,
+    // Fibers waiting for their turn, in the order they will run
+    ready: VecDeque<FiberId>,
+    natives: HashMap<String, Native>,
+    io: Box<dyn Io>,
+}
+
+impl Operation {
+    // Operands of the wrong kind pass the static checks when they go through parameters
+    // without annotations, so they are checked here
+    fn run(&self, x: Value, y: Value) -> Result<Value, Halt> {
+        let error = |err| Err(Halt::Error(err));
+        if self.is_float() {
+            let (x, y) = match (x, y) {
+                (Value::Float(x), Value::Float(y)) => (x, y),
+                (Value::Float(_), val) | (val, _) => {
+                    return error(RuntimeError::Operand(*self, val))
+                }
+            };
+            return Ok(match self {
+                Operation::FAdd => Value::Float(x + y),
+                Operation::FSub => Value::Float(x - y),
+                Operation::FMul => Value::Float(x * y),
+                Operation::FDiv => Value::Float(x / y),
+                // Comparisons with NaN are false
+                Operation::FEq => Value::Num((x == y) as usize),
+                Operation::FLt => Value::Num((x < y) as usize),
+                Operation::FLe => Value::Num((x <= y) as usize),
+                Operation::FGt => Value::Num((x > y) as usize),
+                Operation::FGe => Value::Num((x >= y) as usize),
+                _ => unreachable!(),
+            });
+        }
+        let (x, y) = match (x, y) {
+            (Value::Num(x), Value::Num(y)) => (x, y),
+            (Value::Num(_), val) | (val, _) => return error(RuntimeError::Operand(*self, val)),
+        };
+        let shift = u32::try_from(y).ok();
+        let res = match self {
+            Operation::Add => x.checked_add(y),
+            Operation::Sub => x.checked_sub(y),
+            Operation::Mul => x.checked_mul(y),
+            Operation::Div | Operation::Mod if y == 0 => {
+                return error(RuntimeError::DivisionByZero)
+            }
+            Operation::Div => Some(x / y),
+            Operation::Mod => Some(x % y),
+            Operation::Eq => Some((x == y) as usize),
+            Operation::Lt => Some((x < y) as usize),
+            Operation::Le => Some((x <= y) as usize),
+            Operation::Gt => Some((x > y) as usize),
+            Operation::Ge => Some((x >= y) as usize),
+            Operation::And => Some(x & y),
+            Operation::Or => Some(x | y),
+            Operation::Xor => Some(x ^ y),
+            Operation::Sr => shift.and_then(|s| x.checked_shr(s)),
+            Operation::Sl => shift.and_then(|s| x.checked_shl(s)),
+            _ => unreachable!(),
+        };
+        res.map(Value::Num)
+            .ok_or(Halt::Error(RuntimeError::Overflow))
+    }
+}
+
+impl<'m> State<'m> {
     pub fn new() -> Self {
         Self {
-            frame: Frame::new(),
+            registers: Registers::new(),
             heap: alloc_heap(),
             stack: alloc_stack(),
+            fibers: vec![Fiber {
+                stack: vec![],
+                registers: Registers::new(),
+                status: Status::Running,
+            }],
+            current: 0,
+            ready: VecDeque::new(),
+            natives: HashMap::new(),
+            io: Box::new(StdIo),
         }
     }
 
-    fn alloc_on_stack(&mut self, reg: Frame) {
-        push_within_capacity(&mut self.stack, reg, "Stack has overflown")
+    #[allow(dead_code)]
+    pub fn set_io(&mut self, io: impl Io + 'static) {
+        self.io = Box::new(io);
+    }
+
+    // Binds the extern function `name` to a closure, which is given exactly as many arguments
+    // as the function is declared with
+    pub fn bind(
+        &mut self,
+        name: &str,
+        native: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        self.natives.insert(name.to_owned(), Box::new(native));
+    }
+
+    fn alloc_on_stack(&mut self, frame: Frame<'m>) {
+        push_within_capacity(&mut self.stack, frame, "Stack has overflown")
     }
 
-    fn alloc_on_heap(&mut self, obj: HeapCell) -> Ptr {
+    fn alloc_on_heap(&mut self, obj: HeapCell<'m>) -> Ptr {
         let ptr = self.heap.len();
         push_within_capacity(&mut self.heap, obj, "Memory has run out");
         ptr as Ptr
     }
 
-    fn retrieve_ptr(&self, ptr: Ptr) -> &HeapCell {
+    fn retrieve_ptr(&self, ptr: Ptr) -> &HeapCell<'m> {
         &self.heap[ptr as usize]
     }
 
+    fn retrieve_str(&self, val: Value) -> &[u8] {
+        match self.retrieve_ptr(val.expect_ptr()) {
+            HeapCell::Str(bytes) => bytes,
+            _ => panic!("Expected string"),
+        }
+    }
+
+    fn retrieve_array(&mut self, val: Value) -> &mut Vec<Value> {
+        match &mut self.heap[val.expect_ptr() as usize] {
+            HeapCell::Array(array) => array,
+            _ => panic!("Expected array"),
+        }
+    }
+
+    fn retrieve_ref(&mut self, val: Value) -> &mut Value {
+        match &mut self.heap[val.expect_ptr() as usize] {
+            HeapCell::Ref(content) => content,
+            _ => panic!("Expected reference"),
+        }
+    }
+
+    fn retrieve_channel(&mut self, val: Value) -> &mut Channel {
+        match &mut self.heap[val.expect_ptr() as usize] {
+            HeapCell::Chan(chan) => chan,
+            _ => panic!("Expected channel"),
+        }
+    }
+
+    fn retrieve_big(&self, val: Value) -> &BigNum {
+        match self.retrieve_ptr(val.expect_ptr()) {
+            HeapCell::Big(n) => n,
+            _ => panic!("Expected big integer"),
+        }
+    }
+
+    fn alloc_big(&mut self, n: BigNum) -> Value {
+        Value::Ptr(self.alloc_on_heap(HeapCell::Big(n)))
+    }
+
+    fn alloc_str(&mut self, bytes: Vec<u8>) -> Value {
+        Value::Ptr(self.alloc_on_heap(HeapCell::Str(bytes)))
+    }
+
     fn retrieve_atom(&self, atom: &Atom) -> Value {
         match atom {
-            Atom::Var(x) => self.frame.get(x),
+            Atom::Var(x) => self.registers.get(x),
             Atom::Lit(x) => Value::Num(*x),
+            Atom::Float(x) => Value::Float(*x),
         }
     }
 
-    fn call(&mut self, func: &str, args: &[Value], module: &Module) -> Value {
-        let func = module.get(func).unwrap();
-        let mut frame = Frame::new();
-        for (param, value) in func.params.iter().zip(args.iter()) {
-            frame.insert(param.to_owned(), *value);
-        }
-
-        std::mem::swap(&mut frame, &mut self.frame);
-        self.alloc_on_stack(frame);
+    fn retrieve_atoms(&self, atoms: &[Atom]) -> Vec<Value> {
+        atoms.iter().map(|atom| self.retrieve_atom(atom)).collect()
+    }
 
-        let val = self.eval(&func.body, module);
-        let frame = self.stack.pop().unwrap();
-        self.frame = frame;
-        val
+    fn call(&mut self, func: &str, args: Vec<Value>, module: &'m Module) -> Control<'m> {
+        let Some(func) = module.get(func) else {
+            return match self.natives[func](&args) {
+                Ok(val) => Control::Return(val),
+                Err(err) => Control::Halt(Halt::Error(err)),
+            };
+        };
+        let mut registers = Registers::new();
+        for (param, value) in func.params.iter().zip(args) {
+            registers.insert(param.to_owned(), value);
+        }
+        let caller = std::mem::replace(&mut self.registers, registers);
+        self.alloc_on_stack(Frame::Return(caller));
+        Control::Eval(&func.body)
     }
 
-    fn apply(&mut self, ptr: Ptr, more_args: &[Value], module: &Module) -> Value {
-        let HeapCell::Papp(func, init_args) = self.retrieve_ptr(ptr);
-        let args = {
-            let mut args = init_args.clone();
-            args.extend_from_slice(more_args);
-            args
+    fn apply(&mut self, ptr: Ptr, more_args: Vec<Value>, module: &'m Module) -> Control<'m> {
+        let HeapCell::Papp(func, init_args) = self.retrieve_ptr(ptr) else {
+            panic!("Expected partial application")
         };
-        let def = module.get(func).unwrap();
-        match args.len().cmp(&def.params.len()) {
+        let func = func.clone();
+        let mut args = init_args.clone();
+        args.extend(more_args);
+        let arity = module.arity(&func).unwrap();
+        match args.len().cmp(&arity) {
             Ordering::Less => {
-                let papp = HeapCell::Papp(func.to_owned(), args);
-                let ptr = self.alloc_on_heap(papp);
-                Value::Ptr(ptr)
+                let papp = HeapCell::Papp(func, args);
+                Control::Return(Value::Ptr(self.alloc_on_heap(papp)))
             }
-            Ordering::Equal => self.call(&func.clone(), &args, module),
+            Ordering::Equal => self.call(&func, args, module),
             Ordering::Greater => {
-                let call_args = &args[0..def.params.len()];
-                let val = self.call(&func.clone(), call_args, module);
-                let ptr = val.expect_ptr();
-                let rest = &args[def.params.len()..];
-                self.apply(ptr, rest, module)
+                let rest = args.split_off(arity);
+                self.alloc_on_stack(Frame::Apply(rest));
+                self.call(&func, args, module)
+            }
+        }
+    }
+
+    // Evaluates a thunk at most once. While its function runs, the thunk is replaced by a black
+    // hole, so that a thunk depending on itself is reported instead of overflowing the stack,
+    // and other fibers forcing it wait for its value
+    fn force(&mut self, ptr: Ptr, module: &'m Module) -> Control<'m> {
+        let cell = &mut self.heap[ptr as usize];
+        match std::mem::replace(cell, HeapCell::Blackhole(self.current)) {
+            HeapCell::Thunk(func, args) => {
+                self.alloc_on_stack(Frame::Force(ptr, func.clone(), args.clone()));
+                self.call(&func, args, module)
+            }
+            HeapCell::Evaluated(val) => {
+                *cell = HeapCell::Evaluated(val);
+                Control::Return(val)
+            }
+            HeapCell::Blackhole(fiber) if fiber == self.current => {
+                Control::Halt(Halt::Error(RuntimeError::Loop))
+            }
+            HeapCell::Blackhole(fiber) => {
+                *cell = HeapCell::Blackhole(fiber);
+                self.fibers[self.current].status = Status::Blocked(ptr);
+                self.switch(module)
+            }
+            _ => panic!("Expected thunk"),
+        }
+    }
+
+    // Moves the frames up to the closest `handle` into a continuation, and runs the handler
+    // with the registers of the function containing the `handle`. These were saved by the first
+    // call above it, if the effect was not performed by that function itself
+    fn perform(&mut self, val: Value) -> Control<'m> {
+        let handle = self
+            .stack
+            .iter()
+            .rposition(|f| matches!(f, Frame::Handle(..)));
+        let Some(i) = handle else {
+            return Control::Halt(Halt::Error(RuntimeError::Unhandled));
+        };
+        let frames = self.stack.split_off(i);
+        let Frame::Handle(x, k, handler) = frames[0] else {
+            unreachable!()
+        };
+        // Thunks being evaluated by the continuation can be forced again, like after an
+        // exception, since it may never be resumed
+        for frame in &frames {
+            if let Frame::Force(ptr, func, args) = frame {
+                self.heap[*ptr as usize] = HeapCell::Thunk(func.clone(), args.clone());
+                self.wake_blocked(*ptr);
+            }
+        }
+        let registers = frames.iter().find_map(|frame| match frame {
+            Frame::Return(registers) => Some(registers.clone()),
+            _ => None,
+        });
+        let registers = registers.unwrap_or_else(|| self.registers.clone());
+        let performer = std::mem::replace(&mut self.registers, registers);
+        let cont = self.alloc_on_heap(HeapCell::Cont(frames, performer));
+        self.registers.insert(x.to_owned(), val);
+        self.registers.insert(k.to_owned(), Value::Ptr(cont));
+        Control::Eval(handler)
+    }
+
+    // Pushes a copy of the frames of the continuation, so that it can be resumed several times.
+    // Once they have all returned, the value of the `handle` is returned to the resumer
+    fn resume(&mut self, ptr: Ptr, val: Value) -> Control<'m> {
+        let HeapCell::Cont(frames, registers) = self.retrieve_ptr(ptr) else {
+            panic!("Expected continuation")
+        };
+        let (frames, registers) = (frames.clone(), registers.clone());
+        // Thunks that were not forced again meanwhile are being evaluated again
+        for frame in &frames {
+            if let Frame::Force(ptr, ..) = frame {
+                let cell = &mut self.heap[*ptr as usize];
+                if let HeapCell::Thunk(..) = cell {
+                    *cell = HeapCell::Blackhole(self.current);
+                }
             }
         }
+        let resumer = std::mem::replace(&mut self.registers, registers);
+        self.alloc_on_stack(Frame::Return(resumer));
+        frames
+            .into_iter()
+            .for_each(|frame| self.alloc_on_stack(frame));
+        Control::Return(val)
+    }
+
+    fn primitive(&mut self, prim: Primitive, args: &[Value]) -> Result<Value, Halt> {
+        let num = |i: usize| match args[i] {
+            Value::Num(n) => Ok(n),
+            val => Err(Halt::Error(RuntimeError::Argument(prim, val))),
+        };
+        let stream = |i: usize| match num(i)? {
+            stream @ (1 | 2) => Ok(stream),
+            stream => Err(Halt::Error(RuntimeError::InvalidStream(stream))),
+        };
+        let res = match prim {
+            Primitive::WriteInt => {
+                self.io.write(stream(0)?, num(1)?.to_string().as_bytes());
+                0
+            }
+            Primitive::WriteByte => {
+                let b = num(1)?;
+                let byte =
+                    u8::try_from(b).map_err(|_| Halt::Error(RuntimeError::InvalidByte(b)))?;
+                self.io.write(stream(0)?, &[byte]);
+                0
+            }
+            Primitive::ReadByte => self.io.read_byte().map_or(EOF, usize::from),
+            Primitive::Exit => return Err(Halt::Exit(num(0)?)),
+            Primitive::WriteStr => {
+                let bytes = self.retrieve_str(args[1]).to_vec();
+                self.io.write(stream(0)?, &bytes);
+                0
+            }
+            Primitive::StrLen => self.retrieve_str(args[0]).len(),
+            Primitive::StrGet => {
+                let (s, i) = (self.retrieve_str(args[0]), num(1)?);
+                let byte = s.get(i).ok_or(RuntimeError::OutOfBounds(i, s.len()));
+                *byte.map_err(Halt::Error)? as usize
+            }
+            Primitive::StrConcat => {
+                let mut s = self.retrieve_str(args[0]).to_vec();
+                s.extend_from_slice(self.retrieve_str(args[1]));
+                return Ok(self.alloc_str(s));
+            }
+            Primitive::StrSlice => {
+                let (s, start, end) = (self.retrieve_str(args[0]), num(1)?, num(2)?);
+                let slice = s.get(start..end).map(<[u8]>::to_vec);
+                let err = RuntimeError::SliceOutOfBounds(start, end, s.len());
+                let slice = slice.ok_or(Halt::Error(err))?;
+                return Ok(self.alloc_str(slice));
+            }
+            Primitive::StrCmp => {
+                let ordering = self.retrieve_str(args[0]).cmp(self.retrieve_str(args[1]));
+                (ordering as isize + 1) as usize
+            }
+            Primitive::ArrayNew => {
+                let len = num(0)?;
+                let size = len.saturating_mul(std::mem::size_of::<Value>());
+                if size > MAX_ALLOC {
+                    return Err(Halt::Error(RuntimeError::TooLarge(size)));
+                }
+                let array = HeapCell::Array(vec![args[1]; len]);
+                return Ok(Value::Ptr(self.alloc_on_heap(array)));
+            }
+            Primitive::ArrayLen => self.retrieve_array(args[0]).len(),
+            Primitive::ArrayGet => {
+                let (array, i) = (self.retrieve_array(args[0]), num(1)?);
+                let elem = array
+                    .get(i)
+                    .ok_or(RuntimeError::OutOfBounds(i, array.len()));
+                return elem.copied().map_err(Halt::Error);
+            }
+            Primitive::ArraySet => {
+                let (array, i) = (self.retrieve_array(args[0]), num(1)?);
+                let len = array.len();
+                let elem = array.get_mut(i).ok_or(RuntimeError::OutOfBounds(i, len));
+                *elem.map_err(Halt::Error)? = args[2];
+                0
+            }
+            Primitive::IntToFloat => return Ok(Value::Float(num(0)? as f64)),
+            Primitive::FloatToInt => {
+                // Rounds toward zero. NaN fails both comparisons, and `usize::MAX as f64` rounds
+                // up to a power of two that is itself out of range
+                let x = match args[0] {
+                    Value::Float(x) => x,
+                    val => return Err(Halt::Error(RuntimeError::Argument(prim, val))),
+                };
+                let int = x.trunc();
+                if !(int >= 0.0 && int < usize::MAX as f64) {
+                    return Err(Halt::Error(RuntimeError::NotRepresentable(x)));
+                }
+                int as usize
+            }
+            Primitive::IntToBig => return Ok(self.alloc_big(BigNum::from_usize(num(0)?))),
+            Primitive::BigToInt => {
+                let n = self.retrieve_big(args[0]).to_usize();
+                n.ok_or(Halt::Error(RuntimeError::Overflow))?
+            }
+            Primitive::BigToStr => {
+                let s = self.retrieve_big(args[0]).to_string();
+                return Ok(self.alloc_str(s.into_bytes()));
+            }
+            Primitive::BigCmp => {
+                let ordering = self.retrieve_big(args[0]).cmp(self.retrieve_big(args[1]));
+                (ordering as isize + 1) as usize
+            }
+            Primitive::BigAdd
+            | Primitive::BigSub
+            | Primitive::BigMul
+            | Primitive::BigDiv
+            | Primitive::BigMod => {
+                let (m, n) = (self.retrieve_big(args[0]), self.retrieve_big(args[1]));
+                let res = match prim {
+                    Primitive::BigAdd => Some(m.add(n)),
+                    Primitive::BigMul => Some(m.mul(n)),
+                    Primitive::BigSub => m.sub(n),
+                    Primitive::BigDiv => m.div_rem(n).map(|(q, _)| q),
+                    _ => m.div_rem(n).map(|(_, r)| r),
+                };
+                let err = match prim {
+                    Primitive::BigSub => RuntimeError::NegativeDifference,
+                    _ => RuntimeError::DivisionByZero,
+                };
+                let res = res.ok_or(Halt::Error(err))?;
+                return Ok(self.alloc_big(res));
+            }
+            Primitive::BigShl | Primitive::BigShr => {
+                let (n, k) = (self.retrieve_big(args[0]), num(1)?);
+                let size = n.bits().saturating_add(k) / 8;
+                if prim == Primitive::BigShl && size > MAX_ALLOC {
+                    return Err(Halt::Error(RuntimeError::TooLarge(size)));
+                }
+                let res = match prim {
+                    Primitive::BigShl => n.shl(k),
+                    _ => n.shr(k),
+                };
+                return Ok(self.alloc_big(res));
+            }
+            Primitive::ChanNew | Primitive::ChanBounded => {
+                let chan = Channel {
+                    messages: VecDeque::new(),
+                    capacity: args.first().map(|_| num(0)).transpose()?,
+                    senders: VecDeque::new(),
+                    receivers: VecDeque::new(),
+                };
+                return Ok(Value::Ptr(self.alloc_on_heap(HeapCell::Chan(chan))));
+            }
+            // These switch between fibers, which `fiber_primitive` does
+            Primitive::Spawn
+            | Primitive::Yield
+            | Primitive::Join
+            | Primitive::Send
+            | Primitive::Recv => unreachable!(),
+        };
+        Ok(Value::Num(res))
     }
 
-    fn eval(&mut self, expr: &Expression, module: &Module) -> Value {
-        match expr {
+    fn eval(&mut self, expr: &'m Expression, module: &'m Module) -> Control<'m> {
+        let val = match expr {
             Expression::Unit(atom) => self.retrieve_atom(atom),
             Expression::Let(x, v, b) => {
-                let v_val = self.eval(v, module);
-                self.frame.insert(x.to_owned(), v_val);
-                self.eval(b, module)
-            }
-            Expression::Call(f, args) => {
-                let args = args
-                    .iter()
-                    .map(|arg| self.retrieve_atom(arg))
-                    .collect::<Vec<_>>();
-                self.call(f, &args, module)
+                self.alloc_on_stack(Frame::Let(x, b));
+                return Control::Eval(v);
             }
+            Expression::Call(f, args) => return self.call(f, self.retrieve_atoms(args), module),
             Expression::Apply(f, args) => {
-                let ptr = self.frame.get(f).expect_ptr();
-                let args = args
-                    .iter()
-                    .map(|arg| self.retrieve_atom(arg))
-                    .collect::<Vec<_>>();
-                self.apply(ptr, &args, module)
+                let ptr = self.registers.get(f).expect_ptr();
+                return self.apply(ptr, self.retrieve_atoms(args), module);
             }
             Expression::Papp(f, args) => {
-                let args = args.iter().map(|arg| self.retrieve_atom(arg)).collect();
-                let papp = HeapCell::Papp(f.to_owned(), args);
-                let ptr = self.alloc_on_heap(papp);
-                Value::Ptr(ptr)
+                let papp = HeapCell::Papp(f.to_owned(), self.retrieve_atoms(args));
+                Value::Ptr(self.alloc_on_heap(papp))
             }
             Expression::Match(atom, cases, def) => {
-                let x = self.retrieve_atom(atom).expect_num();
+                let x = match self.retrieve_atom(atom) {
+                    Value::Num(x) => x,
+                    val => return Control::Halt(Halt::Error(RuntimeError::Scrutinee(val))),
+                };
                 let branch = cases
                     .iter()
                     .find_map(|(y, branch)| if x == *y { Some(branch) } else { None })
-                    .or(def.as_deref())
-                    .expect("Match failed");
-                self.eval(branch, module)
+                    .or(def.as_deref());
+                return match branch {
+                    Some(branch) => Control::Eval(branch),
+                    None => Control::Halt(Halt::Error(RuntimeError::NoMatch(x))),
+                };
             }
             Expression::Operate(op, x, y) => {
-                let x_num = self.retrieve_atom(x).expect_num();
-                let y_num = self.retrieve_atom(y).expect_num();
-                Value::Num(op.run(x_num, y_num))
+                match op.run(self.retrieve_atom(x), self.retrieve_atom(y)) {
+                    Ok(val) => val,
+                    Err(halt) => return Control::Halt(halt),
+                }
+            }
+            Expression::Prim(prim, args) => {
+                let args = self.retrieve_atoms(args);
+                match prim {
+                    Primitive::Spawn
+                    | Primitive::Yield
+                    | Primitive::Join
+                    | Primitive::Send
+                    | Primitive::Recv => return self.fiber_primitive(*prim, &args, module),
+                    _ => match self.primitive(*prim, &args) {
+                        Ok(val) => val,
+                        Err(halt) => return Control::Halt(halt),
+                    },
+                }
+            }
+            Expression::Str(bytes) => self.alloc_str(bytes.clone()),
+            Expression::Store(x) => {
+                let cell = HeapCell::Ref(self.retrieve_atom(x));
+                Value::Ptr(self.alloc_on_heap(cell))
+            }
+            Expression::Fetch(p) => *self.retrieve_ref(self.registers.get(p)),
+            Expression::Thunk(f, args) => {
+                let thunk = HeapCell::Thunk(f.to_owned(), self.retrieve_atoms(args));
+                Value::Ptr(self.alloc_on_heap(thunk))
+            }
+            Expression::Force(t) => return self.force(self.registers.get(t).expect_ptr(), module),
+            Expression::Update(p, x) => {
+                let val = self.retrieve_atom(x);
+                *self.retrieve_ref(self.registers.get(p)) = val;
+                Value::Num(0)
+            }
+            Expression::Throw(x) => return Control::Halt(Halt::Throw(self.retrieve_atom(x))),
+            Expression::Catch(body, x, handler) => {
+                self.alloc_on_stack(Frame::Catch(x, handler));
+                return Control::Eval(body);
+            }
+            Expression::Perform(x) => return self.perform(self.retrieve_atom(x)),
+            Expression::Handle(body, x, k, handler) => {
+                self.alloc_on_stack(Frame::Handle(x, k, handler));
+                return Control::Eval(body);
+            }
+            Expression::Resume(k, x) => {
+                let ptr = self.registers.get(k).expect_ptr();
+                return self.resume(ptr, self.retrieve_atom(x));
+            }
+        };
+        Control::Return(val)
+    }
+
+    // Gives the value to the top frame of the stack
+    fn ret(&mut self, val: Value, module: &'m Module) -> Control<'m> {
+        match self.stack.pop().unwrap() {
+            Frame::Let(x, body) => {
+                self.registers.insert(x.to_owned(), val);
+                Control::Eval(body)
+            }
+            Frame::Return(caller) => {
+                self.registers = caller;
+                Control::Return(val)
+            }
+            Frame::Apply(rest) => self.apply(val.expect_ptr(), rest, module),
+            // A thunk whose evaluation was captured by a continuation may have been evaluated
+            // again before the continuation is resumed, and keeps its first value
+            Frame::Force(ptr, ..) => match self.heap[ptr as usize] {
+                HeapCell::Evaluated(val) => Control::Return(val),
+                _ => {
+                    self.heap[ptr as usize] = HeapCell::Evaluated(val);
+                    self.wake_blocked(ptr);
+                    Control::Return(val)
+                }
+            },
+            Frame::Catch(..) | Frame::Handle(..) => Control::Return(val),
+        }
+    }
+
+    // Starts, switches between and joins fibers. A fiber runs until it yields, blocks or
+    // finishes, and the next one is then the one that has waited for the longest
+    fn fiber_primitive(
+        &mut self,
+        prim: Primitive,
+        args: &[Value],
+        module: &'m Module,
+    ) -> Control<'m> {
+        match prim {
+            Primitive::Spawn => {
+                self.fibers.push(Fiber {
+                    stack: alloc_stack(),
+                    registers: Registers::new(),
+                    status: Status::Force(args[0].expect_ptr()),
+                });
+                let fiber = self.fibers.len() - 1;
+                self.ready.push_back(fiber);
+                Control::Return(Value::Num(fiber))
+            }
+            Primitive::Yield => {
+                self.fibers[self.current].status = Status::Ready(Value::Num(0));
+                self.ready.push_back(self.current);
+                self.switch(module)
+            }
+            Primitive::Join => {
+                let fiber = match args[0] {
+                    Value::Num(fiber) if fiber < self.fibers.len() => fiber,
+                    val => return Control::Halt(Halt::Error(RuntimeError::NotAFiber(val))),
+                };
+                if let Status::Done(val) = self.fibers[fiber].status {
+                    return Control::Return(val);
+                }
+                self.fibers[self.current].status = Status::Join(fiber);
+                self.switch(module)
+            }
+            // A message is handed to the first waiting receiver directly
+            Primitive::Send => {
+                let (current, val) = (self.current, args[1]);
+                let chan = self.retrieve_channel(args[0]);
+                if let Some(receiver) = chan.receivers.pop_front() {
+                    self.make_ready(receiver, val);
+                } else if chan.capacity.is_none_or(|n| chan.messages.len() < n) {
+                    chan.messages.push_back(val);
+                } else {
+                    chan.senders.push_back((current, val));
+                    self.fibers[current].status = Status::Send(args[0].expect_ptr());
+                    return self.switch(module);
+                }
+                Control::Return(Value::Num(0))
+            }
+            // Receiving makes room for the message of the first waiting sender, which is thus
+            // received directly from channels of capacity 0
+            Primitive::Recv => {
+                let current = self.current;
+                let chan = self.retrieve_channel(args[0]);
+                if let Some((sender, msg)) = chan.senders.pop_front() {
+                    chan.messages.push_back(msg);
+                    self.make_ready(sender, Value::Num(0));
+                }
+                let chan = self.retrieve_channel(args[0]);
+                match chan.messages.pop_front() {
+                    Some(val) => Control::Return(val),
+                    None => {
+                        chan.receivers.push_back(current);
+                        self.fibers[current].status = Status::Recv(args[0].expect_ptr());
+                        self.switch(module)
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn make_ready(&mut self, fiber: FiberId, val: Value) {
+        self.fibers[fiber].status = Status::Ready(val);
+        self.ready.push_back(fiber);
+    }
+
+    // Makes ready the fibers for which `wake` gives a new status, in the order they were spawned
+    fn wake(&mut self, wake: impl Fn(&Status) -> Option<Status>) {
+        for (id, fiber) in self.fibers.iter_mut().enumerate() {
+            if let Some(status) = wake(&fiber.status) {
+                fiber.status = status;
+                self.ready.push_back(id);
+            }
+        }
+    }
+
+    // Fibers blocked on a thunk force it again, which gives its value, or evaluates it if it
+    // was interrupted by an exception
+    fn wake_blocked(&mut self, ptr: Ptr) {
+        self.wake(|status| match status {
+            Status::Blocked(p) if *p == ptr => Some(Status::Force(ptr)),
+            _ => None,
+        })
+    }
+
+    // Called once the current fiber has stored why it stops running
+    fn switch(&mut self, module: &'m Module) -> Control<'m> {
+        let Some(next) = self.ready.pop_front() else {
+            return Control::Halt(Halt::Error(self.deadlock()));
+        };
+        let fiber = &mut self.fibers[next];
+        let status = std::mem::replace(&mut fiber.status, Status::Running);
+        // A fiber yielding when no other one is ready keeps running
+        if next != self.current {
+            let stack = std::mem::take(&mut fiber.stack);
+            let registers = std::mem::replace(&mut fiber.registers, Registers::new());
+            let current = &mut self.fibers[self.current];
+            current.stack = std::mem::replace(&mut self.stack, stack);
+            current.registers = std::mem::replace(&mut self.registers, registers);
+            self.current = next;
+        }
+        match status {
+            Status::Force(ptr) => self.force(ptr, module),
+            Status::Ready(val) => Control::Return(val),
+            _ => unreachable!(),
+        }
+    }
+
+    // Lists the fibers that will never run again, since none is ready
+    fn deadlock(&self) -> RuntimeError {
+        let waits = self.fibers.iter().enumerate().filter_map(|(id, fiber)| {
+            let wait = match fiber.status {
+                Status::Join(fiber) => Wait::Join(fiber),
+                Status::Blocked(ptr) => match self.heap[ptr as usize] {
+                    HeapCell::Blackhole(fiber) => Wait::Thunk(fiber),
+                    _ => unreachable!(),
+                },
+                Status::Send(ptr) => Wait::Send(ptr),
+                Status::Recv(ptr) => Wait::Recv(ptr),
+                _ => return None,
+            };
+            Some((id, wait))
+        });
+        RuntimeError::Deadlock(waits.collect())
+    }
+
+    // Ends the current fiber, giving its value to the fibers joining it
+    fn finish(&mut self, val: Value, module: &'m Module) -> Control<'m> {
+        let current = self.current;
+        self.fibers[current].status = Status::Done(val);
+        self.wake(|status| match status {
+            Status::Join(fiber) if *fiber == current => Some(Status::Ready(val)),
+            _ => None,
+        });
+        self.switch(module)
+    }
+
+    // Pops frames up to the closest `catch` for an exception, or all of them otherwise
+    fn unwind(&mut self, halt: Halt) -> Result<Control<'m>, Halt> {
+        if let Halt::Exit(_) = halt {
+            return Err(halt);
+        }
+        while let Some(frame) = self.stack.pop() {
+            match (frame, &halt) {
+                (Frame::Return(caller), _) => self.registers = caller,
+                // A thunk interrupted by an exception or an error can be forced again
+                (Frame::Force(ptr, func, args), _) => {
+                    self.heap[ptr as usize] = HeapCell::Thunk(func, args);
+                    self.wake_blocked(ptr);
+                }
+                (Frame::Catch(x, handler), Halt::Throw(val)) => {
+                    self.registers.insert(x.to_owned(), *val);
+                    return Ok(Control::Eval(handler));
+                }
+                _ => {}
+            }
+        }
+        Err(halt)
+    }
+
+    // Runs on a single Rust frame until the stack is empty
+    fn execute(&mut self, expr: &'m Expression, module: &'m Module) -> Result<Value, Halt> {
+        let mut control = Control::Eval(expr);
+        loop {
+            control = match control {
+                Control::Eval(expr) => self.eval(expr, module),
+                // The program ends with `main`, even if other fibers have not finished
+                Control::Return(val) if self.stack.is_empty() && self.current == 0 => {
+                    return Ok(val)
+                }
+                Control::Return(val) if self.stack.is_empty() => self.finish(val, module),
+                Control::Return(val) => self.ret(val, module),
+                Control::Halt(halt) => self.unwind(halt)?,
             }
         }
     }
 
-    pub fn run(&mut self, module: &Module) -> Value {
+    pub fn run(&mut self, module: &'m Module) -> Result<Value, Halt> {
         let main = module.get("main").expect("Expected a `main` function");
         assert_eq!(main.params.len(), 0, "`main` should take 0 argumets");
-        self.eval(&main.body, module)
+        // The first one by name, so that the error does not depend on the order of the map
+        let unbound = module.externs().keys();
+        let unbound = unbound.filter(|name| !self.natives.contains_key(*name));
+        if let Some(name) = unbound.min() {
+            return Err(Halt::Error(RuntimeError::Unbound(name.clone())));
+        }
+        self.execute(&main.body, module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Streams kept in memory, shared with the test
+    #[derive(Clone, Default)]
+    struct Buffers {
+        input: Rc<RefCell<Vec<u8>>>,
+        stdout: Rc<RefCell<Vec<u8>>>,
+        stderr: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Io for Buffers {
+        fn write(&mut self, stream: usize, bytes: &[u8]) {
+            let output = if stream == 1 {
+                &self.stdout
+            } else {
+                &self.stderr
+            };
+            output.borrow_mut().extend_from_slice(bytes);
+        }
+
+        fn read_byte(&mut self) -> Option<u8> {
+            let mut input = self.input.borrow_mut();
+            (!input.is_empty()).then(|| input.remove(0))
+        }
+    }
+
+    fn parse(source: &str) -> Module {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        let mut parser = ParserScanner::new(tokens);
+        let header = parser.parse_header().unwrap();
+        let module = Module::new_unchecked(parser.map(|m| m.unwrap()));
+        let module = module.with_externs(header.externs.into_iter());
+        crate::module::check(&module).unwrap();
+        module
+    }
+
+    fn run(source: &str, input: &str) -> (Result<Value, Halt>, Buffers) {
+        let module = parse(source);
+        let buffers = Buffers::default();
+        buffers
+            .input
+            .borrow_mut()
+            .extend_from_slice(input.as_bytes());
+        let mut state = State::new();
+        state.set_io(buffers.clone());
+        (state.run(&module), buffers)
+    }
+
+    #[test]
+    fn output_is_captured() {
+        let (result, buffers) = run(include_str!("../corpus/io.ir"), "hello\n");
+        assert!(matches!(result, Ok(Value::Num(0))));
+        assert_eq!(buffers.stdout.borrow()[..], *b"hello\n");
+        assert_eq!(buffers.stderr.borrow()[..], *b"6\n");
+    }
+
+    #[test]
+    fn exit_halts() {
+        let (result, buffers) = run(include_str!("../corpus/io.ir"), "");
+        assert_eq!(result.unwrap_err(), Halt::Exit(1));
+        assert!(buffers.stdout.borrow().is_empty());
+        assert_eq!(buffers.stderr.borrow()[..], *b"0\n");
+    }
+
+    #[test]
+    fn natives_are_bound() {
+        let module = parse(include_str!("../corpus/extern.ir"));
+        let mut state = State::new();
+        state.bind("print", |_| Ok(Value::Num(1)));
+        let err = state.run(&module).unwrap_err();
+        let unbound = RuntimeError::Unbound("add".into());
+        assert_eq!(err, Halt::Error(unbound));
+        state.bind("add", |args| match args {
+            [Value::Num(x), Value::Num(y)] => Ok(Value::Num(x + y)),
+            _ => Err(RuntimeError::Native("Expected numbers".into())),
+        });
+        assert_eq!(state.run(&module), Ok(Value::Num(3)));
+        // Failures of natives stop the program like those of primitives
+        state.bind("add", |_| Err(RuntimeError::Overflow));
+        let err = state.run(&module).unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::Overflow));
+    }
+
+    #[test]
+    fn strings_are_manipulated() {
+        let (result, buffers) = run(include_str!("../corpus/strings.ir"), "");
+        assert!(matches!(result, Ok(Value::Num(20))));
+        assert_eq!(buffers.stdout.borrow()[..], *b"world!\n");
+    }
+
+    #[test]
+    fn arrays_are_mutable() {
+        let (result, _) = run(include_str!("../corpus/arrays.ir"), "");
+        assert!(matches!(result, Ok(Value::Num(285))));
+    }
+
+    #[test]
+    fn out_of_bounds_is_an_error() {
+        let source = "fn (main) {\n  let a = (#array_new 2 0);\n  (#array_set a 2 1)\n}";
+        let err = RuntimeError::OutOfBounds(2, 2);
+        assert_eq!(run(source, "").0.unwrap_err(), Halt::Error(err));
+        let source = "fn (main) {\n  let s = \"abc\";\n  (#str_slice s 2 4)\n}";
+        let err = RuntimeError::SliceOutOfBounds(2, 4, 3);
+        assert_eq!(run(source, "").0.unwrap_err(), Halt::Error(err));
+        let err = run("fn (main) {\n  (#write_byte 7 65)\n}", "")
+            .0
+            .unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::InvalidStream(7)));
+        let err = run("fn (main) {\n  (#write_byte 1 300)\n}", "")
+            .0
+            .unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::InvalidByte(300)));
+    }
+
+    #[test]
+    fn huge_arrays_are_an_error() {
+        let source = "fn (main) {\n  (#array_new 100000000000000 0)\n}";
+        let size = 100000000000000 * std::mem::size_of::<Value>();
+        let err = RuntimeError::TooLarge(size);
+        assert_eq!(run(source, "").0.unwrap_err(), Halt::Error(err));
+    }
+
+    #[test]
+    fn references_are_updated() {
+        let (result, _) = run(include_str!("../corpus/lazy.ir"), "");
+        assert!(matches!(result, Ok(Value::Num(289))));
+    }
+
+    #[test]
+    fn thunks_are_evaluated_once() {
+        let (result, _) = run(include_str!("../corpus/thunks.ir"), "");
+        assert!(matches!(result, Ok(Value::Num(289))));
+    }
+
+    #[test]
+    fn loops_are_detected() {
+        let source = "
+            fn (knot r) {
+              let t = (fetch r);
+              (force t)
+            }
+
+            fn (main) {
+              let r = (store 0);
+              let t = (thunk knot r);
+              let u = (update r t);
+              (force t)
+            }";
+        let err = run(source, "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::Loop));
+    }
+
+    #[test]
+    fn floats_are_converted() {
+        let result = run(include_str!("../corpus/floats.ir"), "").0;
+        assert!(matches!(result, Ok(Value::Num(1414))));
+        let source = "
+            fn (main) {
+              let x = (/. 0.0 0.0);
+              let nan = (==. x x);
+              let y = (#float_to_int x);
+              nan
+            }";
+        let err = run(source, "").0.unwrap_err();
+        assert!(matches!(err, Halt::Error(RuntimeError::NotRepresentable(x)) if x.is_nan()));
+    }
+
+    #[test]
+    fn operations_check_their_operands() {
+        let source = "
+            fn (f x) {
+              (+ x 1)
+            }
+
+            fn (main) {
+              (f 1.5)
+            }";
+        let err = run(source, "").0.unwrap_err();
+        let operand = RuntimeError::Operand(Operation::Add, Value::Float(1.5));
+        assert_eq!(err, Halt::Error(operand));
+        let err = run("fn (main) { (- 0 1) }", "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::Overflow));
+        let err = run("fn (main) { (/ 1 0) }", "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::DivisionByZero));
+        let err = run("fn (main) { (<< 1 64) }", "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::Overflow));
+        // Primitives and `match` check their arguments the same way
+        let source = "
+            fn (f x) {
+              (#int_to_float x)
+            }
+
+            fn (main) {
+              (f 1.5)
+            }";
+        let err = run(source, "").0.unwrap_err();
+        let argument = RuntimeError::Argument(Primitive::IntToFloat, Value::Float(1.5));
+        assert_eq!(err, Halt::Error(argument));
+        let source = "
+            fn (f x) {
+              match x {
+                0 => {
+                  1
+                }
+              }
+            }
+
+            fn (main) {
+              (f 1.5)
+            }";
+        let err = run(source, "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::Scrutinee(Value::Float(1.5))));
+        let source = source.replace("1.5", "2");
+        let err = run(&source, "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::NoMatch(2)));
+    }
+
+    #[test]
+    fn big_integers_do_not_overflow() {
+        let (result, buffers) = run(include_str!("../corpus/bignums.ir"), "");
+        assert!(matches!(result, Ok(Value::Num(90318))));
+        assert_eq!(
+            buffers.stdout.borrow()[..],
+            *b"265252859812191058636308480000000"
+        );
+        let source = "
+            fn (main) {
+              let m = (#int_to_big 1);
+              let n = (#int_to_big 2);
+              let d = (#big_sub m n);
+              0
+            }";
+        let err = run(source, "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::NegativeDifference));
+        let source = "fn (main) {\n  let n = (#int_to_big 1);\n  (#big_shl n 100000000000000)\n}";
+        let err = RuntimeError::TooLarge(100000000000001 / 8);
+        assert_eq!(run(source, "").0.unwrap_err(), Halt::Error(err));
+    }
+
+    #[test]
+    fn exceptions_unwind() {
+        let result = run(include_str!("../corpus/exceptions.ir"), "").0;
+        assert_eq!(result, Ok(Value::Num(155)));
+        // Runtime errors are not exceptions, so they cannot be caught
+        let source = "
+            fn (main) {
+              let s = \"\";
+              let r = catch {
+                (#str_get s 0)
+              } with e => {
+                0
+              };
+              (throw r)
+            }";
+        let err = run(source, "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::OutOfBounds(0, 0)));
+        let err = run("fn (main) { (throw 7) }", "").0.unwrap_err();
+        assert_eq!(err, Halt::Throw(Value::Num(7)));
+    }
+
+    #[test]
+    fn continuations_are_resumed() {
+        let result = run(include_str!("../corpus/generators.ir"), "").0;
+        assert_eq!(result, Ok(Value::Num(45)));
+        // Resuming twice runs the rest of the body once for each choice
+        let source = "
+            fn (main) {
+              handle {
+                let a = (perform 0);
+                let b = (perform 0);
+                let c = (* a 2);
+                (+ c b)
+              } with x k => {
+                let l = (resume k 0);
+                let r = (resume k 1);
+                (+ l r)
+              }
+            }";
+        assert_eq!(run(source, "").0, Ok(Value::Num(6)));
+        let err = run("fn (main) { (perform 0) }", "").0.unwrap_err();
+        assert_eq!(err, Halt::Error(RuntimeError::Unhandled));
+    }
+
+    #[test]
+    fn captured_thunks_are_restored() {
+        // The first handler abandons the evaluation of `t`, which is evaluated again by the
+        // second one. Resuming twice keeps the first value of `u`
+        let source = "
+            fn (body n) {
+              let x = (perform n);
+              (+ x 10)
+            }
+
+            fn (abandon t) {
+              handle {
+                (force t)
+              } with x k => {
+                0
+              }
+            }
+
+            fn (once t) {
+              handle {
+                (force t)
+              } with x k => {
+                (resume k 5)
+              }
+            }
+
+            fn (twice t) {
+              handle {
+                (force t)
+              } with x k => {
+                let l = (resume k 1);
+                let r = (resume k 2);
+                (+ l r)
+              }
+            }
+
+            fn (main) {
+              let t = (thunk body 0);
+              let a = (abandon t);
+              let b = (once t);
+              let c = (force t);
+              let u = (thunk body 0);
+              let d = (twice u);
+              let s = (+ b c);
+              (+ s d)
+            }";
+        assert_eq!(run(source, "").0, Ok(Value::Num(52)));
+    }
+
+    #[test]
+    fn deep_recursion_does_not_use_the_rust_stack() {
+        let source = "
+            fn (down n) {
+              match n {
+                0 => {
+                  0
+                }
+                _ => {
+                  let m = (- n 1);
+                  let r = (down m);
+                  (+ r 1)
+                }
+              }
+            }
+
+            fn (main) {
+              (down 100000)
+            }";
+        assert_eq!(run(source, "").0, Ok(Value::Num(100000)));
+    }
+
+    #[test]
+    fn fibers_are_scheduled_in_order() {
+        let (result, buffers) = run(include_str!("../corpus/fibers.ir"), "");
+        assert_eq!(result, Ok(Value::Num(6)));
+        assert_eq!(buffers.stdout.borrow()[..], *b"12312323\n");
+        // The spawned fiber waits for `main` to finish evaluating the thunk
+        let source = "
+            fn (slow n) {
+              let a = (#yield);
+              let b = (#yield);
+              (+ n 5)
+            }
+
+            fn (main) {
+              let t = (thunk slow 0);
+              let f = (#spawn t);
+              let a = (force t);
+              let b = (#join f);
+              (+ a b)
+            }";
+        assert_eq!(run(source, "").0, Ok(Value::Num(10)));
+        // A fiber joining itself
+        let source = "
+            fn (wait r) {
+              let f = (fetch r);
+              (#join f)
+            }
+
+            fn (main) {
+              let r = (store 0);
+              let t = (thunk wait r);
+              let f = (#spawn t);
+              let u = (update r f);
+              (#join f)
+            }";
+        let err = run(source, "").0.unwrap_err();
+        let waits = vec![(0, Wait::Join(1)), (1, Wait::Join(1))];
+        assert_eq!(err, Halt::Error(RuntimeError::Deadlock(waits)));
+        // Only the ids of spawned fibers can be joined
+        let source = "fn (j x) {\n  (#join x)\n}\n\nfn (main) {\n  (j 5)\n}";
+        let err = RuntimeError::NotAFiber(Value::Num(5));
+        assert_eq!(run(source, "").0.unwrap_err(), Halt::Error(err));
+        let err = run(
+            "fn (j x) {\n  (#join x)\n}\n\nfn (main) {\n  (j 1.5)\n}",
+            "",
+        );
+        assert_eq!(
+            err.0.unwrap_err(),
+            Halt::Error(RuntimeError::NotAFiber(Value::Float(1.5)))
+        );
+    }
+
+    #[test]
+    fn channels_block_fibers() {
+        let (result, buffers) = run(include_str!("../corpus/channels.ir"), "");
+        assert_eq!(result, Ok(Value::Num(45)));
+        assert_eq!(buffers.stdout.borrow()[..], *b"1 2 3 4 5 6 7 8 9 \n");
+        // Each send returns once a receiver takes the message, so the second one waits for the
+        // second receive
+        let source = "
+            fn (twice c) {
+              let a = (#send c 1);
+              let b = (#write_int 1 a);
+              let d = (#send c 2);
+              (#write_int 1 d)
+            }
+
+            fn (main) {
+              let c = (#chan_bounded 0);
+              let t = (thunk twice c);
+              let f = (#spawn t);
+              let x = (#recv c);
+              let a = (#write_int 1 x);
+              let y = (#recv c);
+              let b = (#write_int 1 y);
+              (#join f)
+            }";
+        let (result, buffers) = run(source, "");
+        assert_eq!(result, Ok(Value::Num(0)));
+        assert_eq!(buffers.stdout.borrow()[..], *b"0120");
+        let source = "
+            fn (fill c) {
+              let a = (#send c 1);
+              (#send c 2)
+            }
+
+            fn (main) {
+              let c = (#chan_bounded 1);
+              let d = (#chan_new);
+              let t = (thunk fill c);
+              let f = (#spawn t);
+              (#recv d)
+            }";
+        let Err(Halt::Error(err)) = run(source, "").0 else {
+            panic!("Expected a deadlock")
+        };
+        let waits = vec![(0, Wait::Recv(1)), (1, Wait::Send(0))];
+        assert_eq!(err, RuntimeError::Deadlock(waits));
+        assert_eq!(
+            err.to_string(),
+            "Every fiber is waiting: fiber 0 receives from empty channel 1, \
+             fiber 1 sends to full channel 0"
+        );
     }
 }
@@ -0,0 +1,1139 @@
+use crate::expr::{Atom, Declaration, Definition, Expression, Primitive, Type, OPERATIONS};
+use crate::lexer::Span;
+use crate::module::{self, CheckError, Module};
+use std::collections::HashSet;
+use std::fmt;
+
+// JSON documents, and the mapping of modules to them, for tools written in other languages.
+// The schema follows the definitions of `expr` and is described in the README
+static VERSION: u64 = 2;
+
+// Arrays and objects nested deeper are rejected, since documents are parsed and imported
+// recursively. Each `let` expression nests its body one level deeper
+static MAX_DEPTH: usize = 512;
+
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // Numbers without sign, fraction or exponent, kept exact
+    Int(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Members keep their order
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    // Line and column of the error
+    Syntax(usize, usize, &'static str),
+    // Path to a value that does not follow the schema, and what was expected instead
+    Schema(String, String),
+    UnsupportedVersion(u64),
+    Duplicate(String),
+    Check(CheckError),
+    // Path to an infinite or NaN float literal, which JSON cannot represent
+    NotFinite(String, f64),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::Syntax(line, col, msg) => write!(f, "{line}:{col}: {msg}"),
+            JsonError::Schema(path, expected) => write!(f, "`{path}`: Expected {expected}"),
+            JsonError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "Unsupported schema version {version}, expected {VERSION}"
+                )
+            }
+            JsonError::Duplicate(name) => write!(f, "Function `{name}` is given twice"),
+            JsonError::Check(err) => write!(f, "{err}"),
+            JsonError::NotFinite(path, x) => {
+                write!(f, "`{path}`: Float {x:?} cannot be written in JSON")
+            }
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<Json, JsonError> {
+    let mut parser = Parser {
+        source,
+        pos: 0,
+        depth: 0,
+    };
+    let json = parser.value()?;
+    parser.whitespace();
+    if parser.pos < source.len() {
+        return Err(parser.error("Unexpected text after the document"));
+    }
+    Ok(json)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    // Arrays and objects being parsed
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &'static str) -> JsonError {
+        let span = Span {
+            start: self.pos,
+            end: self.pos,
+        };
+        let (line, col) = span.line_col(self.source);
+        JsonError::Syntax(line, col, msg)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.whitespace();
+        let found = self.peek() == Some(byte);
+        self.pos += found as usize;
+        found
+    }
+
+    fn keyword(&mut self, keyword: &str, json: Json) -> Result<Json, JsonError> {
+        if !self.source[self.pos..].starts_with(keyword) {
+            return Err(self.error("Expected a value"));
+        }
+        self.pos += keyword.len();
+        Ok(json)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.whitespace();
+        if let Some(b'[' | b'{') = self.peek() {
+            if self.depth == MAX_DEPTH {
+                return Err(self.error("Arrays and objects are nested too deeply"));
+            }
+            self.depth += 1;
+            let container = self.container();
+            self.depth -= 1;
+            return container;
+        }
+        match self.peek() {
+            None => Err(self.error("Unexpected end of the document")),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn container(&mut self) -> Result<Json, JsonError> {
+        match self.peek() {
+            Some(b'[') => {
+                self.pos += 1;
+                let items = self.sequence(b']', Self::value)?;
+                Ok(Json::Array(items))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let members = self.sequence(b'}', |parser| {
+                    parser.whitespace();
+                    if parser.peek() != Some(b'"') {
+                        return Err(parser.error("Expected a member name"));
+                    }
+                    let name = parser.string()?;
+                    if !parser.eat(b':') {
+                        return Err(parser.error("Expected `:`"));
+                    }
+                    Ok((name, parser.value()?))
+                })?;
+                Ok(Json::Object(members))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Items separated by commas, up to the closing bracket
+    fn sequence<T>(
+        &mut self,
+        close: u8,
+        item: impl Fn(&mut Self) -> Result<T, JsonError>,
+    ) -> Result<Vec<T>, JsonError> {
+        let mut items = vec![];
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            if !self.eat(b',') {
+                return Err(self.error("Expected `,`"));
+            }
+        }
+    }
+
+    fn digits(&mut self) -> Result<(), JsonError> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("Expected a digit"));
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        let negative = self.eat(b'-');
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else {
+            self.digits()?;
+        }
+        let mut integer = !negative;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.digits()?;
+            integer = false;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.digits()?;
+            integer = false;
+        }
+        let text = &self.source[start..self.pos];
+        match text.parse() {
+            Ok(n) if integer => Ok(Json::Int(n)),
+            // Integers too large for `Int` are read approximately, like any other number
+            _ => Ok(Json::Float(text.parse().unwrap())),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let rest = &self.source[self.pos..];
+            let len = rest
+                .find(|c| matches!(c, '"' | '\\' | '\0'..='\u{1f}'))
+                .unwrap_or(rest.len());
+            string.push_str(&rest[..len]);
+            self.pos += len;
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    string.push(self.escape()?);
+                }
+                Some(_) => return Err(self.error("Unescaped control character in string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let escaped = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let high = self.hex()?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high).ok_or(self.error("Unpaired surrogate"));
+                }
+                // Characters outside the basic multilingual plane are written as two
+                // escaped UTF-16 surrogates
+                if !self.source[self.pos..].starts_with("\\u") {
+                    return Err(self.error("Unpaired surrogate"));
+                }
+                self.pos += 2;
+                let low = self.hex()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(self.error("Unpaired surrogate"));
+                }
+                let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+                return Ok(char::from_u32(code).unwrap());
+            }
+            _ => return Err(self.error("Invalid escape sequence")),
+        };
+        self.pos += 1;
+        Ok(escaped)
+    }
+
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let digits = self.source.get(self.pos..self.pos + 4);
+        let code = digits.filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()));
+        let code = code.ok_or(self.error("Expected four hexadecimal digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(code, 16).unwrap())
+    }
+}
+
+// Arrays and objects holding other non-empty arrays or objects are laid out one item per line, indented
+// by two spaces, and the others on a single line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Json {
+    fn is_container(&self) -> bool {
+        match self {
+            Json::Array(items) => !items.is_empty(),
+            Json::Object(members) => !members.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Int(n) => write!(f, "{n}"),
+            // Infinite numbers cannot be written in JSON
+            Json::Float(x) if !x.is_finite() => write!(f, "null"),
+            Json::Float(x) => write!(f, "{x:?}"),
+            Json::String(string) => write_string(f, string),
+            Json::Array(items) => {
+                let items = items.iter().map(|item| (None, item));
+                write_container(f, indent, ('[', ']'), items.collect())
+            }
+            Json::Object(members) => {
+                let members = members.iter().map(|(name, value)| (Some(name), value));
+                write_container(f, indent, ('{', '}'), members.collect())
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ..='\u{1f}' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_container(
+    f: &mut fmt::Formatter<'_>,
+    indent: usize,
+    (open, close): (char, char),
+    items: Vec<(Option<&String>, &Json)>,
+) -> fmt::Result {
+    let multiline = items.iter().any(|(_, item)| item.is_container());
+    write!(f, "{open}")?;
+    for (i, (name, item)) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        if multiline {
+            write!(f, "\n{:1$}", "", indent + 2)?;
+        } else if i > 0 {
+            write!(f, " ")?;
+        }
+        if let Some(name) = name {
+            write_string(f, name)?;
+            write!(f, ": ")?;
+        }
+        item.write(f, indent + 2)?;
+    }
+    if multiline {
+        write!(f, "\n{:1$}", "", indent)?;
+    }
+    write!(f, "{close}")
+}
+
+// Definitions and externs are sorted by name, so that a module is always exported the same way.
+// Float literals are finite when they come from source code, a binary encoding or JSON, but
+// modules built by hand are checked
+pub fn export(module: &Module) -> Result<Json, JsonError> {
+    let mut defs = module.toplevel().values().collect::<Vec<_>>();
+    defs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut externs = module.externs().values().collect::<Vec<_>>();
+    externs.sort_by(|a, b| a.name.cmp(&b.name));
+    let json = object([
+        ("version", Json::Int(VERSION)),
+        (
+            "definitions",
+            Json::Array(defs.into_iter().map(export_definition).collect()),
+        ),
+        (
+            "externs",
+            Json::Array(externs.into_iter().map(export_declaration).collect()),
+        ),
+    ]);
+    match non_finite(&json, "$") {
+        Some((path, x)) => Err(JsonError::NotFinite(path, x)),
+        None => Ok(json),
+    }
+}
+
+// Path to the first number that JSON cannot represent
+fn non_finite(json: &Json, path: &str) -> Option<(String, f64)> {
+    match json {
+        Json::Float(x) if !x.is_finite() => Some((path.into(), *x)),
+        Json::Array(items) => items
+            .iter()
+            .enumerate()
+            .find_map(|(i, item)| non_finite(item, &format!("{path}[{i}]"))),
+        Json::Object(members) => members
+            .iter()
+            .find_map(|(name, value)| non_finite(value, &format!("{path}.{name}"))),
+        _ => None,
+    }
+}
+
+fn object<const N: usize>(members: [(&str, Json); N]) -> Json {
+    let members = members
+        .into_iter()
+        .map(|(name, value)| (name.into(), value));
+    Json::Object(members.collect())
+}
+
+fn string(string: &str) -> Json {
+    Json::String(string.into())
+}
+
+fn strings(strings: &[String]) -> Json {
+    Json::Array(strings.iter().map(|s| string(s)).collect())
+}
+
+fn export_definition(def: &Definition) -> Json {
+    let types = def.param_types.iter().map(export_maybe_type);
+    object([
+        ("name", string(&def.name)),
+        ("params", strings(&def.params)),
+        ("param_types", Json::Array(types.collect())),
+        ("ret_type", export_maybe_type(&def.ret_type)),
+        ("comments", strings(&def.comments)),
+        ("body", export_expr(&def.body)),
+    ])
+}
+
+fn export_declaration(decl: &Declaration) -> Json {
+    let types = decl.param_types.iter().map(export_maybe_type);
+    object([
+        ("name", string(&decl.name)),
+        ("params", strings(&decl.params)),
+        ("param_types", Json::Array(types.collect())),
+        ("ret_type", export_maybe_type(&decl.ret_type)),
+    ])
+}
+
+fn export_maybe_type(typ: &Option<Type>) -> Json {
+    typ.as_ref().map_or(Json::Null, export_type)
+}
+
+fn export_types(types: &[Type]) -> Json {
+    Json::Array(types.iter().map(export_type).collect())
+}
+
+fn export_type(typ: &Type) -> Json {
+    match typ {
+        Type::Int => object([("kind", string("Int"))]),
+        Type::Fn(params, ret) => object([
+            ("kind", string("Fn")),
+            ("params", export_types(params)),
+            ("ret", export_type(ret)),
+        ]),
+        Type::Con(name, args) => object([
+            ("kind", string("Con")),
+            ("name", string(name)),
+            ("args", export_types(args)),
+        ]),
+        Type::Var(name) => object([("kind", string("Var")), ("name", string(name))]),
+        Type::Rec(var, body) => object([
+            ("kind", string("Rec")),
+            ("var", string(var)),
+            ("body", export_type(body)),
+        ]),
+    }
+}
+
+fn export_atom(atom: &Atom) -> Json {
+    match atom {
+        Atom::Var(x) => string(x),
+        Atom::Lit(n) => Json::Int(*n as u64),
+        Atom::Float(x) => Json::Float(*x),
+    }
+}
+
+fn export_atoms(atoms: &[Atom]) -> Json {
+    Json::Array(atoms.iter().map(export_atom).collect())
+}
+
+fn export_expr(expr: &Expression) -> Json {
+    match expr {
+        Expression::Unit(atom) => object([("kind", string("Unit")), ("atom", export_atom(atom))]),
+        Expression::Let(x, val, body) => object([
+            ("kind", string("Let")),
+            ("name", string(x)),
+            ("value", export_expr(val)),
+            ("body", export_expr(body)),
+        ]),
+        Expression::Apply(f, args) => object([
+            ("kind", string("Apply")),
+            ("closure", string(f)),
+            ("args", export_atoms(args)),
+        ]),
+        Expression::Call(f, args) => object([
+            ("kind", string("Call")),
+            ("function", string(f)),
+            ("args", export_atoms(args)),
+        ]),
+        Expression::Papp(f, args) => object([
+            ("kind", string("Papp")),
+            ("function", string(f)),
+            ("args", export_atoms(args)),
+        ]),
+        Expression::Match(atom, cases, default) => {
+            let cases = cases.iter().map(|(n, expr)| {
+                object([("value", Json::Int(*n as u64)), ("body", export_expr(expr))])
+            });
+            object([
+                ("kind", string("Match")),
+                ("atom", export_atom(atom)),
+                ("cases", Json::Array(cases.collect())),
+                (
+                    "default",
+                    default.as_deref().map_or(Json::Null, export_expr),
+                ),
+            ])
+        }
+        Expression::Operate(op, x, y) => object([
+            ("kind", string("Operate")),
+            ("op", string(&format!("{op:?}"))),
+            ("lhs", export_atom(x)),
+            ("rhs", export_atom(y)),
+        ]),
+        Expression::Prim(prim, args) => object([
+            ("kind", string("Prim")),
+            ("prim", string(prim.name())),
+            ("args", export_atoms(args)),
+        ]),
+        // Each byte is the character of the same code point, so that any byte string is
+        // represented, and ASCII strings are readable
+        Expression::Str(bytes) => object([
+            ("kind", string("Str")),
+            (
+                "value",
+                Json::String(bytes.iter().map(|b| *b as char).collect()),
+            ),
+        ]),
+        Expression::Store(x) => object([("kind", string("Store")), ("atom", export_atom(x))]),
+        Expression::Fetch(p) => object([("kind", string("Fetch")), ("reference", string(p))]),
+        Expression::Update(p, x) => object([
+            ("kind", string("Update")),
+            ("reference", string(p)),
+            ("atom", export_atom(x)),
+        ]),
+        Expression::Thunk(f, args) => object([
+            ("kind", string("Thunk")),
+            ("function", string(f)),
+            ("args", export_atoms(args)),
+        ]),
+        Expression::Force(t) => object([("kind", string("Force")), ("thunk", string(t))]),
+        Expression::Throw(x) => object([("kind", string("Throw")), ("atom", export_atom(x))]),
+        Expression::Catch(body, x, handler) => object([
+            ("kind", string("Catch")),
+            ("body", export_expr(body)),
+            ("name", string(x)),
+            ("handler", export_expr(handler)),
+        ]),
+        Expression::Perform(x) => object([("kind", string("Perform")), ("atom", export_atom(x))]),
+        Expression::Handle(body, x, k, handler) => object([
+            ("kind", string("Handle")),
+            ("body", export_expr(body)),
+            ("name", string(x)),
+            ("continuation", string(k)),
+            ("handler", export_expr(handler)),
+        ]),
+        Expression::Resume(k, x) => object([
+            ("kind", string("Resume")),
+            ("continuation", string(k)),
+            ("atom", export_atom(x)),
+        ]),
+    }
+}
+
+pub fn import(json: &Json) -> Result<Module, JsonError> {
+    let names = ["version", "definitions", "externs"];
+    let [version, defs, externs] = members(json, "$", names)?;
+    let version = import_uint(version, "$.version")?;
+    if version != VERSION {
+        return Err(JsonError::UnsupportedVersion(version));
+    }
+    let defs = import_list(defs, "$.definitions", import_definition)?;
+    let externs = import_list(externs, "$.externs", import_declaration)?;
+    let mut names = HashSet::new();
+    let defined = defs.iter().map(|def| &def.name);
+    let declared = externs.iter().map(|decl| &decl.name);
+    if let Some(name) = defined.chain(declared).find(|name| !names.insert(*name)) {
+        return Err(JsonError::Duplicate(name.clone()));
+    }
+    let module = Module::new_unchecked(defs.into_iter()).with_externs(externs.into_iter());
+    module::check(&module).map_err(JsonError::Check)?;
+    Ok(module)
+}
+
+fn schema_error<T>(path: &str, expected: &str) -> Result<T, JsonError> {
+    Err(JsonError::Schema(path.into(), expected.into()))
+}
+
+// Values of the members of an object, which must have exactly the given names
+fn members<'a, const N: usize>(
+    json: &'a Json,
+    path: &str,
+    names: [&str; N],
+) -> Result<[&'a Json; N], JsonError> {
+    let Json::Object(members) = json else {
+        return schema_error(path, "an object");
+    };
+    if let Some((name, _)) = members.iter().find(|(name, _)| !names.contains(&&**name)) {
+        return schema_error(&format!("{path}.{name}"), "no such member");
+    }
+    let mut values = [&Json::Null; N];
+    for (value, name) in values.iter_mut().zip(names) {
+        let mut found = members.iter().filter(|(member, _)| member == name);
+        match (found.next(), found.next()) {
+            (Some((_, json)), None) => *value = json,
+            (None, _) => return schema_error(&format!("{path}.{name}"), "a member"),
+            (Some(_), Some(_)) => {
+                return schema_error(&format!("{path}.{name}"), "a single member")
+            }
+        }
+    }
+    Ok(values)
+}
+
+// Kind of a variant, named by its `kind` member
+fn kind<'a>(json: &'a Json, path: &str) -> Result<&'a str, JsonError> {
+    let Json::Object(members) = json else {
+        return schema_error(path, "an object");
+    };
+    match members.iter().find(|(name, _)| name == "kind") {
+        Some((_, kind)) => import_str(kind, &format!("{path}.kind")),
+        None => schema_error(&format!("{path}.kind"), "a member"),
+    }
+}
+
+fn import_list<T>(
+    json: &Json,
+    path: &str,
+    item: impl Fn(&Json, &str) -> Result<T, JsonError>,
+) -> Result<Vec<T>, JsonError> {
+    let Json::Array(items) = json else {
+        return schema_error(path, "an array");
+    };
+    let items = items.iter().enumerate();
+    items
+        .map(|(i, json)| item(json, &format!("{path}[{i}]")))
+        .collect()
+}
+
+fn import_maybe<T>(
+    json: &Json,
+    path: &str,
+    item: impl Fn(&Json, &str) -> Result<T, JsonError>,
+) -> Result<Option<T>, JsonError> {
+    match json {
+        Json::Null => Ok(None),
+        json => item(json, path).map(Some),
+    }
+}
+
+fn import_str<'a>(json: &'a Json, path: &str) -> Result<&'a str, JsonError> {
+    match json {
+        Json::String(string) => Ok(string),
+        _ => schema_error(path, "a string"),
+    }
+}
+
+fn import_string(json: &Json, path: &str) -> Result<String, JsonError> {
+    import_str(json, path).map(String::from)
+}
+
+fn import_uint(json: &Json, path: &str) -> Result<u64, JsonError> {
+    match json {
+        Json::Int(n) => Ok(*n),
+        _ => schema_error(path, "a non-negative integer"),
+    }
+}
+
+fn import_usize(json: &Json, path: &str) -> Result<usize, JsonError> {
+    let n = import_uint(json, path)?;
+    n.try_into()
+        .or_else(|_| schema_error(path, "a smaller integer"))
+}
+
+fn import_declaration(json: &Json, path: &str) -> Result<Declaration, JsonError> {
+    let names = ["name", "params", "param_types", "ret_type"];
+    import_signature(members(json, path, names)?, path)
+}
+
+fn import_signature(
+    [name, params, param_types, ret_type]: [&Json; 4],
+    path: &str,
+) -> Result<Declaration, JsonError> {
+    let params = import_list(params, &format!("{path}.params"), import_string)?;
+    let types_path = format!("{path}.param_types");
+    let param_types = import_list(param_types, &types_path, |json, path| {
+        import_maybe(json, path, import_type)
+    })?;
+    if param_types.len() != params.len() {
+        return schema_error(&types_path, "as many types as parameters");
+    }
+    Ok(Declaration {
+        name: import_string(name, &format!("{path}.name"))?,
+        params,
+        param_types,
+        ret_type: import_maybe(ret_type, &format!("{path}.ret_type"), import_type)?,
+    })
+}
+
+fn import_definition(json: &Json, path: &str) -> Result<Definition, JsonError> {
+    let names = [
+        "name",
+        "params",
+        "param_types",
+        "ret_type",
+        "comments",
+        "body",
+    ];
+    let [name, params, param_types, ret_type, comments, body] = members(json, path, names)?;
+    let decl = import_signature([name, params, param_types, ret_type], path)?;
+    Ok(Definition {
+        name: decl.name,
+        params: decl.params,
+        param_types: decl.param_types,
+        ret_type: decl.ret_type,
+        body: import_expr(body, &format!("{path}.body"))?,
+        span: Span::default(),
+        spans: vec![],
+        comments: import_list(comments, &format!("{path}.comments"), import_string)?,
+    })
+}
+
+fn import_types(json: &Json, path: &str) -> Result<Vec<Type>, JsonError> {
+    import_list(json, path, import_type)
+}
+
+fn import_type(json: &Json, path: &str) -> Result<Type, JsonError> {
+    let member = |name| format!("{path}.{name}");
+    match kind(json, path)? {
+        "Int" => {
+            members(json, path, ["kind"])?;
+            Ok(Type::Int)
+        }
+        "Fn" => {
+            let [_, params, ret] = members(json, path, ["kind", "params", "ret"])?;
+            let params = import_types(params, &member("params"))?;
+            Ok(Type::Fn(params, import_type(ret, &member("ret"))?.into()))
+        }
+        "Con" => {
+            let [_, name, args] = members(json, path, ["kind", "name", "args"])?;
+            let name = import_string(name, &member("name"))?;
+            Ok(Type::Con(name, import_types(args, &member("args"))?))
+        }
+        "Var" => {
+            let [_, name] = members(json, path, ["kind", "name"])?;
+            Ok(Type::Var(import_string(name, &member("name"))?))
+        }
+        "Rec" => {
+            let [_, var, body] = members(json, path, ["kind", "var", "body"])?;
+            let var = import_string(var, &member("var"))?;
+            Ok(Type::Rec(var, import_type(body, &member("body"))?.into()))
+        }
+        _ => schema_error(&member("kind"), "a kind of type"),
+    }
+}
+
+fn import_atom(json: &Json, path: &str) -> Result<Atom, JsonError> {
+    match json {
+        Json::String(x) => Ok(Atom::Var(x.clone())),
+        Json::Int(_) => Ok(Atom::Lit(import_usize(json, path)?)),
+        Json::Float(x) if x.is_finite() => Ok(Atom::Float(*x)),
+        Json::Float(_) => schema_error(path, "a finite number"),
+        _ => schema_error(path, "a variable or a number"),
+    }
+}
+
+fn import_atoms(json: &Json, path: &str) -> Result<Vec<Atom>, JsonError> {
+    import_list(json, path, import_atom)
+}
+
+// Chains of `let` expressions are imported in a loop rather than recursively, since they are
+// as long as functions
+fn import_expr(json: &Json, path: &str) -> Result<Expression, JsonError> {
+    let (mut json, mut path) = (json, path.to_owned());
+    let mut bindings = vec![];
+    while kind(json, &path)? == "Let" {
+        let [_, x, val, body] = members(json, &path, ["kind", "name", "value", "body"])?;
+        let x = import_string(x, &format!("{path}.name"))?;
+        bindings.push((x, import_expr(val, &format!("{path}.value"))?));
+        path.push_str(".body");
+        json = body;
+    }
+    let body = match kind(json, &path)? {
+        "Match" => import_match(json, &path)?,
+        "Catch" => import_catch(json, &path)?,
+        "Handle" => import_handle(json, &path)?,
+        _ => import_variant(json, &path)?,
+    };
+    let bindings = bindings.into_iter().rev();
+    Ok(bindings.fold(body, |body, (x, val)| {
+        Expression::Let(x, val.into(), body.into())
+    }))
+}
+
+fn import_match(json: &Json, path: &str) -> Result<Expression, JsonError> {
+    let member = |name| format!("{path}.{name}");
+    let names = ["kind", "atom", "cases", "default"];
+    let [_, atom, cases, default] = members(json, path, names)?;
+    let cases = import_list(cases, &member("cases"), |json, path| {
+        let [n, body] = members(json, path, ["value", "body"])?;
+        let n = import_usize(n, &format!("{path}.value"))?;
+        Ok((n, import_expr(body, &format!("{path}.body"))?))
+    })?;
+    let default = import_maybe(default, &member("default"), import_expr)?;
+    Ok(Expression::Match(
+        import_atom(atom, &member("atom"))?,
+        cases,
+        default.map(Box::new),
+    ))
+}
+
+fn import_catch(json: &Json, path: &str) -> Result<Expression, JsonError> {
+    let member = |name| format!("{path}.{name}");
+    let names = ["kind", "body", "name", "handler"];
+    let [_, body, x, handler] = members(json, path, names)?;
+    Ok(Expression::Catch(
+        import_expr(body, &member("body"))?.into(),
+        import_string(x, &member("name"))?,
+        import_expr(handler, &member("handler"))?.into(),
+    ))
+}
+
+fn import_handle(json: &Json, path: &str) -> Result<Expression, JsonError> {
+    let member = |name| format!("{path}.{name}");
+    let names = ["kind", "body", "name", "continuation", "handler"];
+    let [_, body, x, k, handler] = members(json, path, names)?;
+    Ok(Expression::Handle(
+        import_expr(body, &member("body"))?.into(),
+        import_string(x, &member("name"))?,
+        import_string(k, &member("continuation"))?,
+        import_expr(handler, &member("handler"))?.into(),
+    ))
+}
+
+// Expressions that hold no other expression. Those that do are imported by smaller functions,
+// so that nesting them uses little stack
+fn import_variant(json: &Json, path: &str) -> Result<Expression, JsonError> {
+    let member = |name| format!("{path}.{name}");
+    match kind(json, path)? {
+        "Unit" => {
+            let [_, atom] = members(json, path, ["kind", "atom"])?;
+            Ok(Expression::Unit(import_atom(atom, &member("atom"))?))
+        }
+        "Apply" => {
+            let [_, f, args] = members(json, path, ["kind", "closure", "args"])?;
+            Ok(Expression::Apply(
+                import_string(f, &member("closure"))?,
+                import_atoms(args, &member("args"))?,
+            ))
+        }
+        "Call" => {
+            let [_, f, args] = members(json, path, ["kind", "function", "args"])?;
+            Ok(Expression::Call(
+                import_string(f, &member("function"))?,
+                import_atoms(args, &member("args"))?,
+            ))
+        }
+        "Papp" => {
+            let [_, f, args] = members(json, path, ["kind", "function", "args"])?;
+            Ok(Expression::Papp(
+                import_string(f, &member("function"))?,
+                import_atoms(args, &member("args"))?,
+            ))
+        }
+        "Operate" => {
+            let [_, op, x, y] = members(json, path, ["kind", "op", "lhs", "rhs"])?;
+            let name = import_str(op, &member("op"))?;
+            let op = OPERATIONS.iter().find(|op| format!("{op:?}") == name);
+            let Some(op) = op else {
+                return schema_error(&member("op"), "an operation");
+            };
+            Ok(Expression::Operate(
+                *op,
+                import_atom(x, &member("lhs"))?,
+                import_atom(y, &member("rhs"))?,
+            ))
+        }
+        "Prim" => {
+            let [_, prim, args] = members(json, path, ["kind", "prim", "args"])?;
+            let name = import_str(prim, &member("prim"))?;
+            let Some(prim) = Primitive::from_name(name) else {
+                return schema_error(&member("prim"), "a primitive");
+            };
+            Ok(Expression::Prim(prim, import_atoms(args, &member("args"))?))
+        }
+        "Str" => {
+            let [_, value] = members(json, path, ["kind", "value"])?;
+            let value = import_str(value, &member("value"))?;
+            let bytes = value.chars().map(|c| u8::try_from(c).ok());
+            match bytes.collect() {
+                Some(bytes) => Ok(Expression::Str(bytes)),
+                None => schema_error(&member("value"), "a string of bytes"),
+            }
+        }
+        "Store" => {
+            let [_, atom] = members(json, path, ["kind", "atom"])?;
+            Ok(Expression::Store(import_atom(atom, &member("atom"))?))
+        }
+        "Fetch" => {
+            let [_, p] = members(json, path, ["kind", "reference"])?;
+            Ok(Expression::Fetch(import_string(p, &member("reference"))?))
+        }
+        "Update" => {
+            let [_, p, atom] = members(json, path, ["kind", "reference", "atom"])?;
+            Ok(Expression::Update(
+                import_string(p, &member("reference"))?,
+                import_atom(atom, &member("atom"))?,
+            ))
+        }
+        "Thunk" => {
+            let [_, f, args] = members(json, path, ["kind", "function", "args"])?;
+            Ok(Expression::Thunk(
+                import_string(f, &member("function"))?,
+                import_atoms(args, &member("args"))?,
+            ))
+        }
+        "Force" => {
+            let [_, t] = members(json, path, ["kind", "thunk"])?;
+            Ok(Expression::Force(import_string(t, &member("thunk"))?))
+        }
+        "Throw" => {
+            let [_, x] = members(json, path, ["kind", "atom"])?;
+            Ok(Expression::Throw(import_atom(x, &member("atom"))?))
+        }
+        "Perform" => {
+            let [_, x] = members(json, path, ["kind", "atom"])?;
+            Ok(Expression::Perform(import_atom(x, &member("atom"))?))
+        }
+        "Resume" => {
+            let [_, k, x] = members(json, path, ["kind", "continuation", "atom"])?;
+            Ok(Expression::Resume(
+                import_string(k, &member("continuation"))?,
+                import_atom(x, &member("atom"))?,
+            ))
+        }
+        _ => schema_error(&member("kind"), "a kind of expression"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{self, Rng};
+
+    #[test]
+    fn modules_round_trip() {
+        for seed in 0..200 {
+            let module = Module::new(generate::module(&mut Rng::new(seed)).into_iter());
+            let text = export(&module).unwrap().to_string();
+            let imported = import(&parse(&text).unwrap()).unwrap();
+            assert_eq!(imported.toplevel(), module.toplevel(), "seed {seed}");
+            assert_eq!(export(&imported).unwrap().to_string(), text, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn externs_round_trip() {
+        let print = Declaration {
+            name: "print".into(),
+            params: vec!["x".into()],
+            param_types: vec![Some(Type::Int)],
+            ret_type: None,
+        };
+        let main = Definition {
+            name: "main".into(),
+            params: vec![],
+            param_types: vec![],
+            ret_type: None,
+            body: Expression::Papp("print".into(), vec![]),
+            span: Span::default(),
+            spans: vec![],
+            comments: vec![],
+        };
+        let module = Module::new_unchecked([main].into_iter()).with_externs([print].into_iter());
+        let imported = import(&parse(&export(&module).unwrap().to_string()).unwrap()).unwrap();
+        assert_eq!(imported.externs(), module.externs());
+        assert_eq!(imported.toplevel(), module.toplevel());
+    }
+
+    #[test]
+    fn strings_are_bytes() {
+        let bytes = vec![0, b'"', b'a', 0xe9, 0xff];
+        let json = export_expr(&Expression::Str(bytes.clone()));
+        assert_eq!(
+            json.to_string(),
+            r#"{"kind": "Str", "value": "\u0000\"aéÿ"}"#
+        );
+        let imported = import_expr(&parse(&json.to_string()).unwrap(), "$").unwrap();
+        assert_eq!(imported, Expression::Str(bytes));
+        let json = parse(r#"{"kind": "Str", "value": "\u0100"}"#).unwrap();
+        let err = import_expr(&json, "$").unwrap_err();
+        assert_eq!(err.to_string(), "`$.value`: Expected a string of bytes");
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let json = parse(r#" {"a": [1, -2, 0.5, 1e3, true, null], "\u00e9\"\ud83d\ude00": {}} "#);
+        let expected = Json::Object(vec![
+            (
+                "a".into(),
+                Json::Array(vec![
+                    Json::Int(1),
+                    Json::Float(-2.0),
+                    Json::Float(0.5),
+                    Json::Float(1000.0),
+                    Json::Bool(true),
+                    Json::Null,
+                ]),
+            ),
+            ("é\"😀".into(), Json::Object(vec![])),
+        ]);
+        assert_eq!(json.unwrap(), expected);
+        let string = Json::String("tab\t \u{1} é".into());
+        assert_eq!(string.to_string(), r#""tab\t \u0001 é""#);
+        assert_eq!(parse(&string.to_string()).unwrap(), string);
+    }
+
+    #[test]
+    fn syntax_errors_are_located() {
+        let cases = [
+            ("[1,\n 2", (2, 3)),
+            ("{\"a\" 1}", (1, 6)),
+            ("[01]", (1, 3)),
+            ("\"\\ud800\"", (1, 8)),
+            ("[1] 2", (1, 5)),
+            ("\"a\nb\"", (1, 3)),
+        ];
+        for (source, position) in cases {
+            match parse(source) {
+                Err(JsonError::Syntax(line, col, _)) => assert_eq!((line, col), position),
+                _ => panic!("Expected a syntax error for {source:?}"),
+            }
+        }
+        let source = "[".repeat(200_000);
+        match parse(&source) {
+            Err(JsonError::Syntax(line, col, msg)) => {
+                assert_eq!((line, col), (1, MAX_DEPTH + 1));
+                assert_eq!(msg, "Arrays and objects are nested too deeply");
+            }
+            _ => panic!("Expected a syntax error"),
+        }
+    }
+
+    #[test]
+    fn long_functions_are_imported() {
+        let body = (0..150).fold(Expression::Unit(Atom::Lit(0)), |body, i| {
+            let val = Expression::Unit(Atom::Lit(i));
+            Expression::Let(format!("x{i}"), val.into(), body.into())
+        });
+        let main = Definition {
+            name: "main".into(),
+            params: vec![],
+            param_types: vec![],
+            ret_type: None,
+            body,
+            span: Span::default(),
+            spans: vec![],
+            comments: vec![],
+        };
+        let module = Module::new([main].into_iter());
+        let imported = import(&parse(&export(&module).unwrap().to_string()).unwrap()).unwrap();
+        assert_eq!(imported.toplevel(), module.toplevel());
+    }
+
+    #[test]
+    fn floats_are_finite() {
+        let main = Definition {
+            name: "main".into(),
+            params: vec![],
+            param_types: vec![],
+            ret_type: None,
+            body: Expression::Unit(Atom::Float(f64::INFINITY)),
+            span: Span::default(),
+            spans: vec![],
+            comments: vec![],
+        };
+        let module = Module::new([main].into_iter());
+        let err = export(&module).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`$.definitions[0].body.atom`: Float inf cannot be written in JSON"
+        );
+        let json = parse(r#"{"kind": "Unit", "atom": 1e400}"#).unwrap();
+        let err = import_expr(&json, "$").unwrap_err();
+        assert_eq!(err.to_string(), "`$.atom`: Expected a finite number");
+    }
+
+    #[test]
+    fn schema_errors_have_paths() {
+        let def = r#"{"name": "main", "params": [], "param_types": [], "ret_type": null,
+            "comments": [], "body": {"kind": "Operate", "op": "Pow", "lhs": 1, "rhs": 2}}"#;
+        let source = format!(r#"{{"version": 2, "definitions": [{def}], "externs": []}}"#);
+        match import(&parse(&source).unwrap()) {
+            Err(JsonError::Schema(path, _)) => assert_eq!(path, "$.definitions[0].body.op"),
+            _ => panic!("Expected a schema error"),
+        }
+        let source = source
+            .replace("\"Pow\"", "\"Add\"")
+            .replace("\"lhs\"", "\"x\"");
+        match import(&parse(&source).unwrap()) {
+            Err(JsonError::Schema(path, _)) => assert_eq!(path, "$.definitions[0].body.x"),
+            _ => panic!("Expected a schema error"),
+        }
+        let source = source
+            .replace("\"x\"", "\"lhs\"")
+            .replace("\"version\": 2", "\"version\": 3");
+        assert!(matches!(
+            import(&parse(&source).unwrap()),
+            Err(JsonError::UnsupportedVersion(3))
+        ));
+    }
+}
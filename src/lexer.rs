@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::Chars;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -7,6 +8,20 @@ pub enum Keyword {
     Match,
     Apply,
     Papp,
+    Module,
+    Import,
+    Extern,
+    Store,
+    Fetch,
+    Update,
+    Thunk,
+    Force,
+    Throw,
+    Catch,
+    With,
+    Perform,
+    Handle,
+    Resume,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -24,6 +39,9 @@ pub enum Symbol {
     Pipe,
     Caret,
     Underscore,
+    Colon,
+    Comma,
+    Dot,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -50,6 +68,9 @@ impl Symbol {
             Symbol::Pipe => '|',
             Symbol::Caret => '^',
             Symbol::Underscore => '_',
+            Symbol::Colon => ':',
+            Symbol::Comma => ',',
+            Symbol::Dot => '.',
         }
     }
 }
@@ -65,31 +86,121 @@ impl Bracket {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Keyword(Keyword),
     Identifier(String),
     Number(usize),
+    // Floating-point literal, with a fractional part or an exponent
+    Float(f64),
     Symbol(Symbol),
     Paren(Bracket),
+    // Name of a primitive of the runtime, written `#name`
+    Primitive(String),
+    // Byte string literal, with its escapes decoded
+    String(Vec<u8>),
+}
+
+// Byte offsets of a piece of source code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    // One-based line and column of the start of the span
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        (line, col)
+    }
+}
+
+// Source text with no meaning to the parser, kept so that it can be reproduced when formatting
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trivia {
+    Whitespace(String),
+    // `// ...` up to the end of the line
+    LineComment(String),
+    // `/* ... */`, which can be nested
+    BlockComment(String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(text) | Trivia::LineComment(text) | Trivia::BlockComment(text) => {
+                text
+            }
+        }
+    }
+
+    pub fn is_comment(&self) -> bool {
+        !matches!(self, Trivia::Whitespace(_))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+    // Whitespace and comments preceding the token
+    pub trivia: Vec<Trivia>,
 }
 
 #[derive(Debug)]
 pub enum LexerError {
     NumErr,
     UnknownToken(char),
+    UnterminatedComment,
+    UnterminatedString,
+    InvalidEscape(String),
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexerError::NumErr => write!(f, "Invalid number literal"),
+            LexerError::UnknownToken(c) => write!(f, "Unknown token `{c}`"),
+            LexerError::UnterminatedComment => write!(f, "Unterminated block comment"),
+            LexerError::UnterminatedString => write!(f, "Unterminated string literal"),
+            LexerError::InvalidEscape(escape) => write!(f, "Invalid escape `{escape}`"),
+        }
+    }
 }
 
 pub struct Scanner<'a> {
+    source: &'a str,
     start: &'a str,
     chars: Chars<'a>,
+    // Trivia after the last token
+    trailing: Vec<Trivia>,
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source_code: &'a str) -> Self {
         Self {
+            source: source_code,
             start: source_code,
             chars: source_code.chars(),
+            trailing: vec![],
         }
     }
 
@@ -97,6 +208,10 @@ impl<'a> Scanner<'a> {
         self.chars.clone().next()
     }
 
+    fn offset(&self) -> usize {
+        self.source.len() - self.chars.as_str().len()
+    }
+
     fn is_init(&self) -> bool {
         self.chars.as_str() == self.start
     }
@@ -121,21 +236,88 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    // Identifiers may be qualified by a module path, as in `List.cons`
     fn scan_identifier(&mut self) {
-        if self.scan_char_if(char::is_alphabetic) {
-            while self.scan_char_if(char::is_alphanumeric) {}
+        while self.scan_char_if(is_identifier_start) {
+            while self.scan_char_if(is_identifier_char) {}
+            let mut rest = self.chars.clone();
+            if rest.next() != Some('.') || !rest.next().is_some_and(is_identifier_start) {
+                break;
+            }
+            self.chars.next();
         }
     }
 
+    // Floats have a fractional part, as in `1.5`, or an exponent, as in `2e-3`
     fn scan_number(&mut self) {
-        if self.scan_char_if(char::is_numeric) {
+        if !self.scan_char_if(char::is_numeric) {
+            return;
+        }
+        while self.scan_char_if(char::is_alphanumeric) {}
+        let mut rest = self.chars.clone();
+        if rest.next() == Some('.') && rest.next().is_some_and(|c| c.is_ascii_digit()) {
+            self.chars.next();
+            while self.scan_char_if(char::is_alphanumeric) {}
+        }
+        let scanned = &self.start[..self.start.len() - self.chars.as_str().len()];
+        let mut rest = self.chars.clone();
+        if scanned.ends_with(['e', 'E'])
+            && matches!(rest.next(), Some('+' | '-'))
+            && rest.next().is_some_and(|c| c.is_ascii_digit())
+        {
+            self.chars.next();
             while self.scan_char_if(char::is_alphanumeric) {}
         }
     }
 
-    pub fn skip_whitespace(&mut self) {
+    fn scan_whitespace(&mut self) {
         while self.scan_char_if(char::is_whitespace) {}
-        self.start = self.chars.as_str();
+    }
+
+    fn scan_line_comment(&mut self) {
+        while self.scan_char_if(|c| c != '\n') {}
+    }
+
+    fn scan_block_comment(&mut self) -> Result<(), LexerError> {
+        let mut depth = 0;
+        loop {
+            let rest = self.chars.as_str();
+            if rest.starts_with("/*") {
+                depth += 1;
+            } else if rest.starts_with("*/") {
+                depth -= 1;
+            } else if self.chars.next().is_some() {
+                continue;
+            } else {
+                return Err(LexerError::UnterminatedComment);
+            }
+            self.chars.nth(1);
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn lex_trivia(&mut self) -> Result<Vec<Trivia>, LexerError> {
+        let mut trivia = vec![];
+        loop {
+            self.scan_whitespace();
+            if let Some(text) = self.consume_scan() {
+                trivia.push(Trivia::Whitespace(text.into()));
+            }
+            let rest = self.chars.as_str();
+            let comment = if rest.starts_with("//") {
+                self.scan_line_comment();
+                Trivia::LineComment
+            } else if rest.starts_with("/*") {
+                self.scan_block_comment()?;
+                Trivia::BlockComment
+            } else {
+                return Ok(trivia);
+            };
+            let text = self.consume_scan().unwrap();
+            trivia.push(comment(text.into()));
+        }
     }
 
     pub fn lex_identifier(&mut self) -> Option<Token> {
@@ -148,18 +330,96 @@ impl<'a> Scanner<'a> {
             "match" => Some(Token::Keyword(Keyword::Match)),
             "apply" => Some(Token::Keyword(Keyword::Apply)),
             "papp" => Some(Token::Keyword(Keyword::Papp)),
+            "module" => Some(Token::Keyword(Keyword::Module)),
+            "import" => Some(Token::Keyword(Keyword::Import)),
+            "extern" => Some(Token::Keyword(Keyword::Extern)),
+            "store" => Some(Token::Keyword(Keyword::Store)),
+            "fetch" => Some(Token::Keyword(Keyword::Fetch)),
+            "update" => Some(Token::Keyword(Keyword::Update)),
+            "thunk" => Some(Token::Keyword(Keyword::Thunk)),
+            "force" => Some(Token::Keyword(Keyword::Force)),
+            "throw" => Some(Token::Keyword(Keyword::Throw)),
+            "catch" => Some(Token::Keyword(Keyword::Catch)),
+            "with" => Some(Token::Keyword(Keyword::With)),
+            "perform" => Some(Token::Keyword(Keyword::Perform)),
+            "handle" => Some(Token::Keyword(Keyword::Handle)),
+            "resume" => Some(Token::Keyword(Keyword::Resume)),
+            // A lone underscore is the wildcard pattern
+            "_" => Some(Token::Symbol(Symbol::Underscore)),
             _ => Some(Token::Identifier(res.into())),
         }
     }
 
+    pub fn lex_primitive(&mut self) -> Option<Token> {
+        let mut rest = self.chars.clone();
+        if rest.next() != Some('#') || !rest.next().is_some_and(is_identifier_start) {
+            return None;
+        }
+        self.chars.next();
+        self.start = self.chars.as_str();
+        self.scan_identifier();
+        let name = self.consume_scan()?;
+        Some(Token::Primitive(name.into()))
+    }
+
+    // Strings are written between double quotes, with the escapes `\n`, `\t`, `\r`, `\0`, `\\`,
+    // `\"` and `\xHH` for any byte. Other characters stand for their UTF-8 encoding
+    pub fn lex_string(&mut self) -> Option<Result<Token, LexerError>> {
+        if self.peek()? != '"' {
+            return None;
+        }
+        self.chars.next();
+        let mut bytes = vec![];
+        let res = loop {
+            let byte = match self.chars.next() {
+                None => break Err(LexerError::UnterminatedString),
+                Some('"') => break Ok(Token::String(bytes)),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => b'\n',
+                    Some('t') => b'\t',
+                    Some('r') => b'\r',
+                    Some('0') => 0,
+                    Some('\\') => b'\\',
+                    Some('"') => b'"',
+                    Some('x') => {
+                        let digits = self.chars.as_str().get(..2).unwrap_or_default();
+                        match u8::from_str_radix(digits, 16) {
+                            Ok(byte) if digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                                self.chars.nth(1);
+                                byte
+                            }
+                            _ => break Err(LexerError::InvalidEscape(format!("\\x{digits}"))),
+                        }
+                    }
+                    Some(c) => break Err(LexerError::InvalidEscape(format!("\\{c}"))),
+                    None => break Err(LexerError::UnterminatedString),
+                },
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    continue;
+                }
+            };
+            bytes.push(byte);
+        };
+        self.start = self.chars.as_str();
+        Some(res)
+    }
+
     pub fn lex_number(&mut self) -> Option<Result<Token, LexerError>> {
         assert!(self.is_init());
         self.scan_number();
         let res = self.consume_scan()?;
-        let num = res
-            .parse()
-            .map(Token::Number)
-            .map_err(|_| LexerError::NumErr);
+        let num = if res.contains(['.', 'e', 'E']) {
+            match res.parse::<f64>() {
+                Ok(x) if x.is_finite() => Ok(Token::Float(x)),
+                _ => Err(LexerError::NumErr),
+            }
+        } else {
+            res.parse()
+                .map(Token::Number)
+                .map_err(|_| LexerError::NumErr)
+        };
         Some(num)
     }
 
@@ -196,20 +456,21 @@ impl<'a> Scanner<'a> {
             '&' => Symbol(And),
             '|' => Symbol(Pipe),
             '^' => Symbol(Caret),
-            '_' => Symbol(Underscore),
+            ':' => Symbol(Colon),
+            ',' => Symbol(Comma),
+            '.' => Symbol(Dot),
             _ => return None,
         };
         self.chars.next();
         self.start = self.chars.as_str();
         Some(token)
     }
-}
 
-impl<'a> Iterator for Scanner<'a> {
-    type Item = Result<Token, LexerError>;
+    pub fn trailing_trivia(&self) -> &[Trivia] {
+        &self.trailing
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.skip_whitespace();
+    fn lex_token(&mut self) -> Option<Result<Token, LexerError>> {
         if let Some(t) = self.lex_symbol() {
             return Some(Ok(t));
         }
@@ -219,6 +480,12 @@ impl<'a> Iterator for Scanner<'a> {
         if let Some(t) = self.lex_identifier() {
             return Some(Ok(t));
         }
+        if let Some(t) = self.lex_primitive() {
+            return Some(Ok(t));
+        }
+        if let Some(s) = self.lex_string() {
+            return Some(s);
+        }
         if let Some(m) = self.lex_number() {
             return Some(m);
         }
@@ -228,3 +495,153 @@ impl<'a> Iterator for Scanner<'a> {
         None
     }
 }
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Result<Lexeme, LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let trivia = match self.lex_trivia() {
+            Ok(trivia) => trivia,
+            Err(err) => return Some(Err(err)),
+        };
+        if self.peek().is_none() {
+            self.trailing = trivia;
+            return None;
+        }
+        let start = self.offset();
+        let token = self.lex_token()?;
+        let span = Span {
+            start,
+            end: self.offset(),
+        };
+        Some(token.map(|token| Lexeme {
+            token,
+            span,
+            trivia,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> Vec<Lexeme> {
+        Scanner::new(source).map(Result::unwrap).collect()
+    }
+
+    fn comments(lexeme: &Lexeme) -> Vec<&Trivia> {
+        lexeme.trivia.iter().filter(|t| t.is_comment()).collect()
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let lexemes = lex("/* outer /* inner */ still outer */ x");
+        assert_eq!(lexemes.len(), 1);
+        assert_eq!(lexemes[0].token, Token::Identifier("x".into()));
+        assert_eq!(
+            comments(&lexemes[0]),
+            [&Trivia::BlockComment(
+                "/* outer /* inner */ still outer */".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn unterminated_block_comments_are_errors() {
+        for source in ["x /* never closed", "/* /* closed once */", "/*/"] {
+            let res = Scanner::new(source).collect::<Result<Vec<_>, _>>();
+            assert!(
+                matches!(res, Err(LexerError::UnterminatedComment)),
+                "{source}"
+            );
+        }
+    }
+
+    // Comments are kept, in order, with the token that follows them
+    #[test]
+    fn trivia_is_preserved() {
+        let source = "// leading\nfn /* a */ (main)\n{\n  0 // trailing\n}";
+        let lexemes = lex(source);
+        let with_comments = lexemes.iter().filter(|lexeme| !comments(lexeme).is_empty());
+        let with_comments = with_comments
+            .map(|lexeme| (lexeme.token.clone(), comments(lexeme)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            with_comments,
+            [
+                (
+                    Token::Keyword(Keyword::Fn),
+                    vec![&Trivia::LineComment("// leading".into())]
+                ),
+                (
+                    Token::Paren(Bracket::LParen),
+                    vec![&Trivia::BlockComment("/* a */".into())]
+                ),
+                (
+                    Token::Paren(Bracket::RBrace),
+                    vec![&Trivia::LineComment("// trailing".into())]
+                ),
+            ]
+        );
+    }
+
+    // Every byte of the source is either in a token or in the trivia around it
+    #[test]
+    fn source_is_reproduced() {
+        let source = "  // leading\nfn /* a */ (main)\n{\n  0 // trailing\n}\n// last\n";
+        let mut scanner = Scanner::new(source);
+        let mut text = String::new();
+        for lexeme in scanner.by_ref() {
+            let lexeme = lexeme.unwrap();
+            lexeme
+                .trivia
+                .iter()
+                .for_each(|trivia| text.push_str(trivia.text()));
+            text.push_str(&source[lexeme.span.start..lexeme.span.end]);
+        }
+        let trailing = scanner.trailing_trivia();
+        trailing
+            .iter()
+            .for_each(|trivia| text.push_str(trivia.text()));
+        assert_eq!(text, source);
+        assert_eq!(
+            trailing,
+            [
+                Trivia::Whitespace("\n".into()),
+                Trivia::LineComment("// last".into()),
+                Trivia::Whitespace("\n".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn identifiers_have_underscores_primes_and_paths() {
+        let source = "sum_list _unused x' x'' List.cons Data.List.length _ f_ List.1 x.";
+        let tokens = lex(source).into_iter().map(|lexeme| lexeme.token);
+        let id = |name: &str| Token::Identifier(name.into());
+        assert_eq!(
+            tokens.collect::<Vec<_>>(),
+            [
+                id("sum_list"),
+                id("_unused"),
+                id("x'"),
+                id("x''"),
+                id("List.cons"),
+                id("Data.List.length"),
+                // A lone underscore is the wildcard, not an identifier
+                Token::Symbol(Symbol::Underscore),
+                id("f_"),
+                // A path only continues with the start of an identifier
+                id("List"),
+                Token::Symbol(Symbol::Dot),
+                Token::Number(1),
+                id("x"),
+                Token::Symbol(Symbol::Dot),
+            ]
+        );
+        // Primes only follow the start of an identifier
+        let res = Scanner::new("'x").collect::<Result<Vec<_>, _>>();
+        assert!(matches!(res, Err(LexerError::UnknownToken('\''))));
+    }
+}
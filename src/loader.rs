@@ -0,0 +1,352 @@
+use crate::binary::{self, DecodeError};
+use crate::expr::{Definition, Header};
+use crate::interface::{Interface, InterfaceError};
+use crate::json::{self, JsonError};
+use crate::lexer::{LexerError, Scanner as LexerScanner, Span};
+use crate::module::{self, CheckError, Module, Unit};
+use crate::parser::{ParserError, Scanner as ParserScanner};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, std::io::Error),
+    Lexer(PathBuf, LexerError),
+    Parser(PathBuf, ParserError),
+    // The file of an imported module declares another name
+    NameMismatch(PathBuf, String, String),
+    // Modules importing each other, the first one being imported again by the last one
+    Cycle(Vec<String>),
+    Interface(PathBuf, InterfaceError),
+    Decode(PathBuf, DecodeError),
+    Json(PathBuf, JsonError),
+    Check(CheckError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "Could not read `{}`: {err}", path.display()),
+            LoadError::Lexer(path, err) => write!(f, "{}: {err}", path.display()),
+            LoadError::Parser(path, err) => write!(f, "{}: {err}", path.display()),
+            LoadError::NameMismatch(path, expected, found) => write!(
+                f,
+                "{}: Expected module `{expected}`, found module `{found}`",
+                path.display()
+            ),
+            LoadError::Cycle(modules) => {
+                write!(f, "Modules import each other: {}", modules.join(" -> "))?;
+                write!(f, " -> {}", modules[0])
+            }
+            LoadError::Interface(path, err) => write!(f, "{}: {err}", path.display()),
+            LoadError::Decode(path, err) => write!(f, "{}: {err}", path.display()),
+            LoadError::Json(path, err @ JsonError::Syntax(..)) => {
+                write!(f, "{}:{err}", path.display())
+            }
+            LoadError::Json(path, err) => write!(f, "{}: {err}", path.display()),
+            LoadError::Check(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+pub struct File {
+    pub module: String,
+    pub path: PathBuf,
+    pub source: String,
+}
+
+// A linked program, with the files it was loaded from. Dependencies come before the modules
+// importing them, so the root file is the last one. Programs decoded from their binary encoding
+// have no source files, like those imported from JSON
+pub struct Program {
+    pub module: Module,
+    pub files: Vec<File>,
+}
+
+impl Program {
+    // Position in its source file of a span of the given function
+    pub fn locate(&self, function: &str, span: Span) -> Option<String> {
+        let (root, imported) = self.files.split_last()?;
+        let file = function
+            .rsplit_once('.')
+            .and_then(|(module, _)| imported.iter().find(|file| file.module == module))
+            .unwrap_or(root);
+        let (line, col) = span.line_col(&file.source);
+        Some(format!("{}:{line}:{col}", file.path.display()))
+    }
+}
+
+struct Loader {
+    // Directory of the root file, where imported modules are looked up
+    dir: PathBuf,
+    files: Vec<File>,
+    units: Vec<Unit>,
+    // Modules being loaded, each one importing the next
+    stack: Vec<String>,
+}
+
+// Loads a program from its root file, which has already been read
+pub fn load(path: &Path, source: String) -> Result<Program, LoadError> {
+    let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+    let mut loader = Loader {
+        dir,
+        files: vec![],
+        units: vec![],
+        stack: vec![],
+    };
+    loader.file(path.to_owned(), source, None)?;
+    let module = module::link(loader.units).map_err(LoadError::Check)?;
+    Ok(Program {
+        module,
+        files: loader.files,
+    })
+}
+
+pub fn load_binary(path: &Path) -> Result<Program, LoadError> {
+    let bytes = fs::read(path).map_err(|err| LoadError::Io(path.to_owned(), err))?;
+    let module = binary::decode(&bytes).map_err(|err| LoadError::Decode(path.to_owned(), err))?;
+    Ok(Program {
+        module,
+        files: vec![],
+    })
+}
+
+pub fn load_json(path: &Path) -> Result<Program, LoadError> {
+    let source = fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_owned(), err))?;
+    let module = json::parse(&source)
+        .and_then(|json| json::import(&json))
+        .map_err(|err| LoadError::Json(path.to_owned(), err))?;
+    Ok(Program {
+        module,
+        files: vec![],
+    })
+}
+
+// Checks a single module against the interfaces of the modules it imports, found next to
+// their sources as `.iri` files, and returns its own interface
+pub fn compile(path: &Path, source: String) -> Result<Interface, LoadError> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let (mut header, definitions) = parse(path, &source)?;
+    let name = header.name.clone().unwrap_or_else(|| file_name(path));
+    let mut interfaces = vec![];
+    for import in header.imports.iter() {
+        let path = module_path(dir, import, "iri");
+        let source = fs::read_to_string(&path).map_err(|err| LoadError::Io(path.clone(), err))?;
+        let interface =
+            Interface::parse(&source).map_err(|err| LoadError::Interface(path.clone(), err))?;
+        if interface.module != *import {
+            return Err(LoadError::NameMismatch(
+                path,
+                import.clone(),
+                interface.module,
+            ));
+        }
+        interfaces.push(interface);
+    }
+    let interface = Interface::new(&name, &definitions);
+    header.name = Some(name);
+    let unit = Unit {
+        header,
+        root: false,
+        definitions,
+    };
+    module::check_unit(unit, &interfaces).map_err(LoadError::Check)?;
+    Ok(interface)
+}
+
+// Module `Data.List` is found in `Data/List.ir`
+fn module_path(dir: &Path, module: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(module.replace('.', "/"));
+    path.set_extension(extension);
+    path
+}
+
+fn file_name(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default();
+    stem.to_string_lossy().into()
+}
+
+fn parse(path: &Path, source: &str) -> Result<(Header, Vec<Definition>), LoadError> {
+    let tokens = LexerScanner::new(source)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| LoadError::Lexer(path.to_owned(), err))?;
+    let mut parser = ParserScanner::new(tokens.into_iter());
+    let header = parser
+        .parse_header()
+        .map_err(|err| LoadError::Parser(path.to_owned(), err))?;
+    let definitions = parser
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| LoadError::Parser(path.to_owned(), err))?;
+    Ok((header, definitions))
+}
+
+impl Loader {
+    fn import(&mut self, name: &str) -> Result<(), LoadError> {
+        if let Some(i) = self.stack.iter().position(|module| module == name) {
+            return Err(LoadError::Cycle(self.stack[i..].to_vec()));
+        }
+        if self.files.iter().any(|file| file.module == name) {
+            return Ok(());
+        }
+        let path = module_path(&self.dir, name, "ir");
+        let source = fs::read_to_string(&path).map_err(|err| LoadError::Io(path.clone(), err))?;
+        self.file(path, source, Some(name))
+    }
+
+    // Files that do not declare a module name are named after the import, or after the file
+    // itself for the root file
+    fn file(
+        &mut self,
+        path: PathBuf,
+        source: String,
+        import: Option<&str>,
+    ) -> Result<(), LoadError> {
+        let (mut header, definitions) = parse(&path, &source)?;
+        let name = match (import, &header.name) {
+            (Some(import), Some(name)) if import != name => {
+                return Err(LoadError::NameMismatch(path, import.into(), name.clone()));
+            }
+            (Some(import), _) => import.to_owned(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => file_name(&path),
+        };
+        header.name = Some(name.clone());
+        self.stack.push(name.clone());
+        for import in header.imports.iter() {
+            self.import(import)?;
+        }
+        self.stack.pop();
+        self.files.push(File {
+            module: name,
+            path,
+            source,
+        });
+        self.units.push(Unit {
+            header,
+            root: import.is_none(),
+            definitions,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{State, Value};
+    use crate::{infer, shape, typecheck};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn load_file(path: &str) -> Result<Program, LoadError> {
+        let source = fs::read_to_string(path).unwrap();
+        load(Path::new(path), source)
+    }
+
+    #[test]
+    fn imports_are_resolved() {
+        let program = load_file("corpus/modules/main.ir").unwrap();
+        assert!(program.module.get("List.cons").is_some());
+        assert!(program.module.get("Data.Maybe.just").is_some());
+        let Ok(Value::Num(sum)) = State::new().run(&program.module) else {
+            panic!("Expected a number")
+        };
+        assert_eq!(sum, 55);
+    }
+
+    #[test]
+    fn externs_are_bound() {
+        let program = load_file("corpus/extern.ir").unwrap();
+        let printed = Rc::new(RefCell::new(vec![]));
+        let mut state = State::new();
+        let log = printed.clone();
+        state.bind("print", move |args| {
+            log.borrow_mut().extend_from_slice(args);
+            Ok(args[0])
+        });
+        state.bind("add", |args| match args {
+            [Value::Num(x), Value::Num(y)] => Ok(Value::Num(x + y)),
+            _ => panic!("Expected two numbers"),
+        });
+        let Ok(Value::Num(sum)) = state.run(&program.module) else {
+            panic!("Expected a number")
+        };
+        assert_eq!(sum, 3);
+        assert!(matches!(printed.borrow()[..], [Value::Num(1)]));
+    }
+
+    // Every program of the corpus passes the checks run before interpreting it. `names.ir` only
+    // exercises the lexer, and calls functions that it does not define
+    #[test]
+    fn corpus_is_well_typed() {
+        let files = fs::read_dir("corpus")
+            .unwrap()
+            .map(|entry| entry.unwrap().path());
+        let mut paths = files
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ir"))
+            .filter(|path| !path.ends_with("names.ir"))
+            .collect::<Vec<_>>();
+        paths.push("corpus/modules/main.ir".into());
+        for path in paths {
+            let source = fs::read_to_string(&path).unwrap();
+            let program = load(&path, source).unwrap();
+            let name = path.display();
+            assert!(shape::check(&program.module).is_ok(), "{name}");
+            assert!(typecheck::check(&program.module).is_ok(), "{name}");
+            assert!(infer::infer(&program.module).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn externs_cannot_be_defined() {
+        let source = "extern fn (f x);\nfn (f x) {\n  x\n}\n";
+        match load(Path::new("corpus/inline.ir"), source.into()) {
+            Err(LoadError::Check(CheckError::ExternDefined(func))) => assert_eq!(func, "f"),
+            _ => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        match load_file("corpus/modules/cycle.ir") {
+            Err(LoadError::Cycle(modules)) => assert_eq!(modules, ["Cycle", "Loop"]),
+            _ => panic!("Expected a cycle"),
+        }
+    }
+
+    #[test]
+    fn interfaces_are_written_back() {
+        for path in ["corpus/modules/List.ir", "corpus/modules/Data/Maybe.ir"] {
+            let source = fs::read_to_string(path).unwrap();
+            let interface = compile(Path::new(path), source).unwrap();
+            let expected = fs::read_to_string(Path::new(path).with_extension("iri")).unwrap();
+            assert_eq!(interface.to_string(), expected);
+            assert_eq!(Interface::parse(&expected).unwrap(), interface);
+        }
+    }
+
+    #[test]
+    fn modules_are_checked_against_interfaces() {
+        let source = fs::read_to_string("corpus/modules/main.ir").unwrap();
+        assert!(compile(Path::new("corpus/modules/main.ir"), source).is_ok());
+        let source = "import List;\nfn (main) {\n  (List.range 1 2)\n}\n";
+        match compile(Path::new("corpus/modules/inline.ir"), source.into()) {
+            Err(LoadError::Check(CheckError::WrongArity(func, 1, 2))) => {
+                assert_eq!(func, "List.range")
+            }
+            _ => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    fn qualified_names_must_be_imported() {
+        let source = "import List;\nfn (main) {\n  (Data.Maybe.id 0)\n}\n";
+        match load(Path::new("corpus/modules/inline.ir"), source.into()) {
+            Err(LoadError::Check(CheckError::NotImported(module, _))) => {
+                assert_eq!(module, "Data.Maybe")
+            }
+            _ => panic!("Expected an error"),
+        }
+    }
+}
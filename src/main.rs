@@ -1,14 +1,30 @@
+mod bignum;
+mod binary;
+mod callgraph;
+mod cst;
 mod expr;
+mod format;
+#[cfg(test)]
+mod generate;
+mod infer;
+mod interface;
 mod interpreter;
+mod json;
 mod lexer;
+mod loader;
 mod module;
 mod parser;
+mod pass;
 mod pretty;
+mod shape;
+mod typecheck;
 
-use interpreter::State;
-use lexer::Scanner as LexerScanner;
-use module::Module;
-use parser::Scanner as ParserScanner;
+use callgraph::CallGraph;
+use cst::CstError;
+use interpreter::{Halt, RuntimeError, State, Value};
+use pass::{OptLevel, Pipeline};
+use std::io::Write;
+use std::path::Path;
 
 static SOURCE: &str = "
 fn (id x) {
@@ -19,13 +35,13 @@ fn (flip f x y) {
   (apply f y x)
 }
 
-fn (polynomial x) {
+fn (polynomial x: Int) -> Int {
   let x2 = (* x x);
   let x3 = (* x x2);
   (+ x2 x3)
 }
 
-fn (not x) {
+fn (not x: Int) -> Int {
   match x {
     0 => {
       1
@@ -39,6 +55,8 @@ fn (not x) {
   }
 }
 
+// Lists are Church-encoded: a list takes the result for the empty list
+// and a closure receiving the head and the tail
 fn (nil n c) {
   n
 }
@@ -47,7 +65,7 @@ fn (cons x xs n c) {
   (apply c x xs)
 }
 
-fn (buildList n) {
+fn (buildList n: Int) {
   match n {
     0 => {
       (papp nil)
@@ -60,7 +78,9 @@ fn (buildList n) {
   }
 }
 
+/* `sumList` and `sumListAux` are mutually recursive /* nested */ */
 fn (sumList xs) {
+  // the tail is summed by `sumListAux`
   let sum = (papp sumListAux);
   (apply xs 0 sum)
 }
@@ -76,16 +96,196 @@ fn (main) {
 }
 ";
 
+struct Options {
+    level: OptLevel,
+    dump_passes: bool,
+    infer: bool,
+    dot: bool,
+    format: bool,
+    interface: bool,
+    encode: bool,
+    json: bool,
+    path: Option<String>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        level: OptLevel::O0,
+        dump_passes: false,
+        infer: false,
+        dot: false,
+        format: false,
+        interface: false,
+        encode: false,
+        json: false,
+        path: None,
+    };
+    for arg in std::env::args().skip(1) {
+        if let Some(level) = OptLevel::from_flag(&arg) {
+            options.level = level;
+        } else if arg == "--dump-passes" {
+            options.dump_passes = true;
+        } else if arg == "--infer" {
+            options.infer = true;
+        } else if arg == "--dot" {
+            options.dot = true;
+        } else if arg == "--format" {
+            options.format = true;
+        } else if arg == "--interface" {
+            options.interface = true;
+        } else if arg == "--encode" {
+            options.encode = true;
+        } else if arg == "--json" {
+            options.json = true;
+        } else if arg.starts_with('-') {
+            exit_with(format!("Unknown option `{arg}`"));
+        } else {
+            options.path = Some(arg);
+        }
+    }
+    options
+}
+
+fn exit_with(msg: impl std::fmt::Display) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1)
+}
+
+// Functions of the host, which programs can call by declaring them with `extern`
+fn bind_natives(state: &mut State) {
+    // Prints its arguments on a line, and returns 0
+    state.bind("print", |args| {
+        let args = args.iter().map(|arg| match arg {
+            Value::Num(n) => n.to_string(),
+            Value::Float(x) => format!("{x:?}"),
+            // Natives cannot see the heap, so closures, strings and other cells look the same
+            Value::Ptr(_) => "<pointer>".into(),
+        });
+        println!("{}", args.collect::<Vec<_>>().join(" "));
+        Ok(Value::Num(0))
+    });
+    // Adds two integers
+    state.bind("add", |args| match args {
+        [Value::Num(x), Value::Num(y)] => x
+            .checked_add(*y)
+            .map(Value::Num)
+            .ok_or(RuntimeError::Overflow),
+        _ => Err(RuntimeError::Native("`add` expects two integers".into())),
+    });
+}
+
+fn read_source(path: Option<&str>) -> String {
+    match path {
+        Some(path) => std::fs::read_to_string(path)
+            .unwrap_or_else(|err| exit_with(format!("Could not read `{path}`: {err}"))),
+        None => SOURCE.to_string(),
+    }
+}
+
+fn format_source(source: &str) {
+    let formatted = format::format(source).unwrap_or_else(|err| match err {
+        CstError::Unbalanced(span) => {
+            let (line, col) = span.line_col(source);
+            exit_with(format!("{line}:{col}: {err}"))
+        }
+        err => exit_with(err),
+    });
+    print!("{formatted}");
+}
+
+fn write_interface(path: Option<&str>, source: String) {
+    let Some(path) = path.map(Path::new) else {
+        exit_with("`--interface` expects a file");
+    };
+    let interface = loader::compile(path, source).unwrap_or_else(|err| exit_with(err));
+    let output = path.with_extension("iri");
+    std::fs::write(&output, interface.to_string())
+        .unwrap_or_else(|err| exit_with(format!("Could not write `{}`: {err}", output.display())));
+}
+
 fn main() {
-    let lexer_scanner = LexerScanner::new(SOURCE);
-    let tokens = lexer_scanner.map(|m| m.unwrap());
-    let parser_scanner = ParserScanner::new(tokens);
-    let definitions = parser_scanner.map(|m| m.unwrap());
-    let module = Module::new(definitions);
+    let options = parse_args();
+    let path = options.path.as_deref();
+    let program = match path {
+        // Modules encoded by `--encode` or exported by `--json`
+        Some(path) if path.ends_with(".irb") => {
+            loader::load_binary(Path::new(path)).unwrap_or_else(|err| exit_with(err))
+        }
+        Some(path) if path.ends_with(".json") => {
+            loader::load_json(Path::new(path)).unwrap_or_else(|err| exit_with(err))
+        }
+        _ => {
+            let source = read_source(path);
+            if options.format {
+                return format_source(&source);
+            }
+            if options.interface {
+                return write_interface(path, source);
+            }
+            // The sample program can import modules from the working directory
+            let path = Path::new(path.unwrap_or("sample.ir"));
+            loader::load(path, source).unwrap_or_else(|err| exit_with(err))
+        }
+    };
+    let module = &program.module;
+    if options.encode {
+        let bytes = binary::encode(module);
+        std::io::stdout()
+            .write_all(&bytes)
+            .unwrap_or_else(|err| exit_with(err));
+        return;
+    }
+    if options.json {
+        let json = json::export(module).unwrap_or_else(|err| exit_with(err));
+        return println!("{json}");
+    }
+    if let Err(errors) = shape::check(module) {
+        errors.iter().for_each(|err| eprintln!("{err}"));
+        std::process::exit(1);
+    }
+    if let Err(errors) = typecheck::check(module) {
+        errors.iter().for_each(|err| eprintln!("{err}"));
+        std::process::exit(1);
+    }
+    if options.dot {
+        print!("{}", CallGraph::new(module).to_dot());
+    }
+    if options.infer {
+        match infer::infer(module) {
+            Ok(signatures) => signatures
+                .iter()
+                .for_each(|sig| println!("{:?}", sig.pretty())),
+            Err(errors) => {
+                for err in errors {
+                    match program.locate(&err.function, err.span) {
+                        Some(position) => eprintln!("{position}: {err}"),
+                        None => eprintln!("{err}"),
+                    }
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut pipeline = Pipeline::with_level(options.level);
+    pipeline.dump(options.dump_passes);
+    let module = pipeline
+        .run(program.module)
+        .unwrap_or_else(|err| exit_with(err));
+    let mut externs = module.externs().values().collect::<Vec<_>>();
+    externs.sort_by(|a, b| a.name.cmp(&b.name));
+    externs
+        .iter()
+        .for_each(|decl| println!("extern {:?}\n", decl.pretty()));
     let top = module.toplevel().iter();
     top.for_each(|(_, def)| println!("{:?}\n", def.pretty()));
 
     let mut state = State::new();
-    let val = state.run(&module);
-    println!("main: {:?}", val);
+    bind_natives(&mut state);
+    match state.run(&module) {
+        Ok(val) => println!("main: {:?}", val),
+        Err(Halt::Exit(code)) => std::process::exit(code as i32),
+        Err(Halt::Error(err)) => exit_with(format!("Runtime error: {err}")),
+        Err(Halt::Throw(val)) => exit_with(format!("Uncaught exception: {val:?}")),
+    }
 }
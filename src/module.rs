@@ -1,17 +1,37 @@
-use crate::expr::{Atom, Definition, Expression};
+use crate::expr::{Atom, Declaration, Definition, Expression, Header, Type};
+use crate::interface::Interface;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 static ARGS_MAX_SIZE: usize = 8;
 
 pub struct Module {
     toplevel: HashMap<String, Definition>,
+    // Functions provided by the host, which have no definition
+    externs: HashMap<String, Declaration>,
 }
 
 impl Module {
+    #[allow(dead_code)]
     pub fn new(iter: impl Iterator<Item = Definition>) -> Self {
+        let module = Self::new_unchecked(iter);
+        check(&module).unwrap_or_else(|err| panic!("{err}"));
+        module
+    }
+
+    // Builds a module without checking it. Used by transformations whose
+    // output is verified afterwards, such as the passes in `pass`
+    pub fn new_unchecked(iter: impl Iterator<Item = Definition>) -> Self {
         let toplevel = iter.map(|def| (def.name.clone(), def)).collect();
-        check(&toplevel);
-        Self { toplevel }
+        Self {
+            toplevel,
+            externs: HashMap::new(),
+        }
+    }
+
+    pub fn with_externs(mut self, externs: impl Iterator<Item = Declaration>) -> Self {
+        self.externs = externs.map(|decl| (decl.name.clone(), decl)).collect();
+        self
     }
 
     pub fn toplevel(&self) -> &HashMap<String, Definition> {
@@ -21,114 +41,428 @@ impl Module {
     pub fn get(&self, name: &str) -> Option<&Definition> {
         self.toplevel.get(name)
     }
+
+    pub fn externs(&self) -> &HashMap<String, Declaration> {
+        &self.externs
+    }
+
+    // Number of parameters of a defined or extern function
+    pub fn arity(&self, name: &str) -> Option<usize> {
+        self.signature(name).map(|(params, _)| params.len())
+    }
+
+    // Parameter and return type annotations of a defined or extern function
+    pub fn signature(&self, name: &str) -> Option<(&[Option<Type>], &Option<Type>)> {
+        match (self.toplevel.get(name), self.externs.get(name)) {
+            (Some(def), _) => Some((&def.param_types, &def.ret_type)),
+            (_, Some(decl)) => Some((&decl.param_types, &decl.ret_type)),
+            (None, None) => None,
+        }
+    }
+
+    pub fn into_parts(
+        self,
+    ) -> (
+        impl Iterator<Item = Definition>,
+        impl Iterator<Item = Declaration>,
+    ) {
+        (self.toplevel.into_values(), self.externs.into_values())
+    }
+}
+
+// The definitions of a source file, with its header
+pub struct Unit {
+    pub header: Header,
+    // Whether this is the file given to the runtime, whose definitions are not qualified
+    pub root: bool,
+    pub definitions: Vec<Definition>,
+}
+
+// Merges the modules of a program into one. Definitions of imported modules are qualified by
+// the name of their module, as in `List.map`, and references are resolved accordingly
+pub fn link(units: Vec<Unit>) -> Result<Module, CheckError> {
+    let mut toplevel = HashMap::new();
+    let mut externs = HashMap::<String, Declaration>::new();
+    for unit in units {
+        // Extern functions are not qualified, as they name functions of the host
+        for decl in unit.header.externs.iter() {
+            match externs.get(&decl.name) {
+                Some(other) if other != decl => {
+                    return Err(CheckError::ExternMismatch(decl.name.clone()))
+                }
+                _ => externs.insert(decl.name.clone(), decl.clone()),
+            };
+        }
+        for def in resolve_unit(unit)? {
+            toplevel.insert(def.name.clone(), def);
+        }
+    }
+    let module = Module { toplevel, externs };
+    check(&module)?;
+    Ok(module)
+}
+
+fn resolve_unit(unit: Unit) -> Result<Vec<Definition>, CheckError> {
+    let module = unit.header.name.as_deref().filter(|_| !unit.root);
+    let locals = unit.definitions.iter().map(|def| def.name.clone());
+    let locals = locals.collect::<HashSet<_>>();
+    for decl in unit.header.externs.iter() {
+        if decl.name.contains('.') {
+            return Err(CheckError::QualifiedDefinition(decl.name.clone()));
+        }
+        if locals.contains(&decl.name) {
+            return Err(CheckError::ExternDefined(decl.name.clone()));
+        }
+    }
+    let mut defs = vec![];
+    for mut def in unit.definitions {
+        if def.name.contains('.') {
+            return Err(CheckError::QualifiedDefinition(def.name));
+        }
+        resolve(&mut def.body, module, &unit.header, &locals)?;
+        def.name = qualify(module, &def.name);
+        defs.push(def);
+    }
+    Ok(defs)
+}
+
+fn qualify(module: Option<&str>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{module}.{name}"),
+        None => name.to_owned(),
+    }
+}
+
+// Functions of the same module can be referred to unqualified, and functions of other modules
+// must be qualified by the name of a module that is imported
+fn resolve_name(
+    name: &str,
+    module: Option<&str>,
+    header: &Header,
+    locals: &HashSet<String>,
+) -> Result<String, CheckError> {
+    if header.externs.iter().any(|decl| decl.name == name) {
+        return Ok(name.to_owned());
+    }
+    if locals.contains(name) {
+        return Ok(qualify(module, name));
+    }
+    let imports = &header.imports;
+    match name.rsplit_once('.') {
+        Some((prefix, _)) if module == Some(prefix) || imports.iter().any(|i| i == prefix) => {
+            Ok(name.to_owned())
+        }
+        Some((prefix, _)) => Err(CheckError::NotImported(prefix.to_owned(), name.to_owned())),
+        None => Err(CheckError::UnboundFunction(name.to_owned())),
+    }
+}
+
+fn resolve(
+    expr: &mut Expression,
+    module: Option<&str>,
+    header: &Header,
+    locals: &HashSet<String>,
+) -> Result<(), CheckError> {
+    match expr {
+        Expression::Call(func, _) | Expression::Papp(func, _) | Expression::Thunk(func, _) => {
+            *func = resolve_name(func, module, header, locals)?;
+        }
+        Expression::Let(_, val, body) => {
+            resolve(val, module, header, locals)?;
+            resolve(body, module, header, locals)?;
+        }
+        Expression::Match(_, cases, default) => {
+            for (_, exp) in cases.iter_mut() {
+                resolve(exp, module, header, locals)?;
+            }
+            if let Some(exp) = default {
+                resolve(exp, module, header, locals)?;
+            }
+        }
+        Expression::Catch(body, _, handler) | Expression::Handle(body, _, _, handler) => {
+            resolve(body, module, header, locals)?;
+            resolve(handler, module, header, locals)?;
+        }
+        Expression::Unit(_)
+        | Expression::Apply(..)
+        | Expression::Operate(..)
+        | Expression::Prim(..)
+        | Expression::Str(_)
+        | Expression::Store(_)
+        | Expression::Fetch(_)
+        | Expression::Update(..)
+        | Expression::Force(_)
+        | Expression::Throw(_)
+        | Expression::Perform(_)
+        | Expression::Resume(..) => {}
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum CheckError {
+    NameMismatch(String, String),
+    TooManyParams(String),
+    TooManyArgs,
+    UnboundVariable(String),
+    Redefined(String),
+    UnboundFunction(String),
+    WrongArity(String, usize, usize),
+    PappExceedsArity(String),
+    RepeatedPattern(usize),
+    NotImported(String, String),
+    QualifiedDefinition(String),
+    // A function is both defined and declared extern
+    ExternDefined(String),
+    // Modules declare the same extern function differently
+    ExternMismatch(String),
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::NameMismatch(key, name) => {
+                write!(f, "Function `{name}` is registered as `{key}`")
+            }
+            CheckError::TooManyParams(name) => {
+                write!(
+                    f,
+                    "Function `{name}` has more than {ARGS_MAX_SIZE} arguments"
+                )
+            }
+            CheckError::TooManyArgs => {
+                write!(f, "Application has more than {ARGS_MAX_SIZE} arguments")
+            }
+            CheckError::UnboundVariable(x) => write!(f, "Unbound variable `{x}`"),
+            CheckError::Redefined(x) => write!(
+                f,
+                "Variable `{x}` has already been defined. Functions are supposed to be in SSA"
+            ),
+            CheckError::UnboundFunction(func) => write!(f, "Unbound function `{func}`"),
+            CheckError::WrongArity(func, expected, found) => write!(
+                f,
+                "Wrong number of arguments to `{func}`: expected {expected}, found {found}"
+            ),
+            CheckError::PappExceedsArity(func) => {
+                write!(f, "Partial application of `{func}` exceeds function arity")
+            }
+            CheckError::RepeatedPattern(pat) => write!(f, "Repeated pattern `{pat}` in match"),
+            CheckError::NotImported(module, func) => {
+                write!(f, "Module `{module}` of `{func}` is not imported")
+            }
+            CheckError::QualifiedDefinition(func) => {
+                write!(
+                    f,
+                    "Function `{func}` cannot be defined with a qualified name"
+                )
+            }
+            CheckError::ExternDefined(func) => {
+                write!(f, "Function `{func}` is both defined and declared extern")
+            }
+            CheckError::ExternMismatch(func) => {
+                write!(
+                    f,
+                    "Extern function `{func}` is declared differently by modules"
+                )
+            }
+        }
+    }
 }
 
 // Expressions must be SSA, applications and function arguments cannot be greater than size `ARGS_MAX_SIZE`
 
-fn check(top: &HashMap<String, Definition>) {
-    top.iter().for_each(|(name, def)| check_def(name, def, top));
+pub fn check(module: &Module) -> Result<(), CheckError> {
+    let externs = module.externs.iter();
+    let externs = externs.map(|(name, decl)| (name.clone(), decl.params.len()));
+    let defs = module.toplevel.iter();
+    let defs = defs.map(|(name, def)| (name.clone(), def.params.len()));
+    let arities = externs.chain(defs).collect();
+    for (name, decl) in module.externs.iter() {
+        if module.toplevel.contains_key(name) {
+            return Err(CheckError::ExternDefined(name.clone()));
+        }
+        check_extern(name, decl)?;
+    }
+    module
+        .toplevel
+        .iter()
+        .try_for_each(|(name, def)| check_def(name, def, &arities))
+}
+
+// Checks a module on its own, knowing the functions of the modules it imports from their
+// interfaces only. Returns the definitions with their names resolved
+pub fn check_unit(unit: Unit, interfaces: &[Interface]) -> Result<Vec<Definition>, CheckError> {
+    let externs = unit.header.externs.iter();
+    let mut arities = externs
+        .map(|decl| (decl.name.clone(), decl.params.len()))
+        .collect::<HashMap<_, _>>();
+    for decl in unit.header.externs.iter() {
+        check_extern(&decl.name, decl)?;
+    }
+    let defs = resolve_unit(unit)?;
+    for interface in interfaces {
+        for decl in interface.declarations.iter() {
+            let name = qualify(Some(&interface.module), &decl.name);
+            arities.insert(name, decl.params.len());
+        }
+    }
+    arities.extend(defs.iter().map(|def| (def.name.clone(), def.params.len())));
+    defs.iter()
+        .try_for_each(|def| check_def(&def.name, def, &arities))?;
+    Ok(defs)
 }
 
-fn check_def(name: &str, def: &Definition, top: &HashMap<String, Definition>) {
-    assert_eq!(name, def.name.as_str());
-    assert!(
-        def.params.len() <= ARGS_MAX_SIZE,
-        "Function `{name}` has more than {ARGS_MAX_SIZE} arguments"
-    );
-    let vars = &mut HashSet::new();
-    for arg in def.params.iter() {
-        insert_unique(arg, vars);
+fn check_extern(name: &str, decl: &Declaration) -> Result<(), CheckError> {
+    if name != decl.name {
+        return Err(CheckError::NameMismatch(name.into(), decl.name.clone()));
+    }
+    if decl.params.len() > ARGS_MAX_SIZE {
+        return Err(CheckError::TooManyParams(name.into()));
     }
-    check_expr(&def.body, vars, top);
+    insert_params(&decl.params).map(|_| ())
 }
 
-fn is_bound(x: &String, vars: &HashSet<String>) {
-    assert!(vars.contains(x), "Unbound variable `{x}`");
+fn insert_params(params: &[String]) -> Result<HashSet<String>, CheckError> {
+    let mut vars = HashSet::new();
+    for param in params {
+        insert_unique(param, &mut vars)?;
+    }
+    Ok(vars)
 }
 
-fn insert_unique(x: &String, vars: &mut HashSet<String>) {
-    assert!(
-        vars.insert(x.clone()),
-        "Variable `{x}` has already been defined. Functions are supposed to be in SSA"
-    );
+fn check_def(
+    name: &str,
+    def: &Definition,
+    arities: &HashMap<String, usize>,
+) -> Result<(), CheckError> {
+    if name != def.name {
+        return Err(CheckError::NameMismatch(name.into(), def.name.clone()));
+    }
+    if def.params.len() > ARGS_MAX_SIZE {
+        return Err(CheckError::TooManyParams(name.into()));
+    }
+    let mut vars = insert_params(&def.params)?;
+    check_expr(&def.body, &mut vars, arities)
 }
 
-fn check_expr(expr: &Expression, vars: &mut HashSet<String>, top: &HashMap<String, Definition>) {
+fn is_bound(x: &str, vars: &HashSet<String>) -> Result<(), CheckError> {
+    if !vars.contains(x) {
+        return Err(CheckError::UnboundVariable(x.to_owned()));
+    }
+    Ok(())
+}
+
+fn is_atom_bound(atom: &Atom, vars: &HashSet<String>) -> Result<(), CheckError> {
+    match atom {
+        Atom::Var(var) => is_bound(var, vars),
+        Atom::Lit(_) | Atom::Float(_) => Ok(()),
+    }
+}
+
+fn insert_unique(x: &str, vars: &mut HashSet<String>) -> Result<(), CheckError> {
+    if !vars.insert(x.to_owned()) {
+        return Err(CheckError::Redefined(x.to_owned()));
+    }
+    Ok(())
+}
+
+fn get_arity(func: &str, arities: &HashMap<String, usize>) -> Result<usize, CheckError> {
+    arities
+        .get(func)
+        .copied()
+        .ok_or_else(|| CheckError::UnboundFunction(func.to_owned()))
+}
+
+fn check_expr(
+    expr: &Expression,
+    vars: &mut HashSet<String>,
+    arities: &HashMap<String, usize>,
+) -> Result<(), CheckError> {
     match expr {
-        Expression::Unit(Atom::Var(x)) => is_bound(x, vars),
-        Expression::Unit(Atom::Lit(_)) => {}
+        Expression::Unit(atom) => is_atom_bound(atom, vars),
         Expression::Let(name, val, body) => {
-            check_expr(val, vars, top);
-            insert_unique(name, vars);
-            check_expr(body, vars, top);
+            check_expr(val, vars, arities)?;
+            insert_unique(name, vars)?;
+            check_expr(body, vars, arities)
         }
         Expression::Apply(closure, args) => {
-            assert!(
-                // the closure is also part of the argument of apply so
-                // `args` should be strictly less than `ARGS_MAX_SIZE`
-                args.len() < ARGS_MAX_SIZE,
-                "Application has more than {ARGS_MAX_SIZE} arguments"
-            );
-            is_bound(closure, vars);
-            for arg in args {
-                if let Atom::Var(var) = arg {
-                    is_bound(var, vars);
-                }
+            // the closure is also part of the argument of apply so
+            // `args` should be strictly less than `ARGS_MAX_SIZE`
+            if args.len() >= ARGS_MAX_SIZE {
+                return Err(CheckError::TooManyArgs);
             }
+            is_bound(closure, vars)?;
+            args.iter().try_for_each(|arg| is_atom_bound(arg, vars))
         }
-        Expression::Call(func, args) => {
-            assert!(
-                args.len() <= ARGS_MAX_SIZE,
-                "Application has more than {ARGS_MAX_SIZE} arguments"
-            );
-            let func = top
-                .get(func)
-                .unwrap_or_else(|| panic!("Unbound function `{func}`"));
-            assert_eq!(func.params.len(), args.len(), "Wrong number of arguments");
-            for arg in args {
-                if let Atom::Var(var) = arg {
-                    is_bound(var, vars);
-                }
+        // A thunk is evaluated by calling its function with all its arguments
+        Expression::Call(func, args) | Expression::Thunk(func, args) => {
+            if args.len() > ARGS_MAX_SIZE {
+                return Err(CheckError::TooManyArgs);
+            }
+            let arity = get_arity(func, arities)?;
+            if arity != args.len() {
+                return Err(CheckError::WrongArity(func.clone(), arity, args.len()));
             }
+            args.iter().try_for_each(|arg| is_atom_bound(arg, vars))
         }
         Expression::Papp(func, args) => {
-            assert!(
-                args.len() < ARGS_MAX_SIZE,
-                "Partial application has more than {ARGS_MAX_SIZE} arguments"
-            );
-            let func = top
-                .get(func)
-                .unwrap_or_else(|| panic!("Unbound function `{func}`"));
-            assert!(
-                func.params.len() >= args.len(),
-                "Partial application exceeds function arity"
-            );
-            for arg in args {
-                if let Atom::Var(var) = arg {
-                    is_bound(var, vars);
-                }
+            if args.len() >= ARGS_MAX_SIZE {
+                return Err(CheckError::TooManyArgs);
             }
+            if get_arity(func, arities)? < args.len() {
+                return Err(CheckError::PappExceedsArity(func.clone()));
+            }
+            args.iter().try_for_each(|arg| is_atom_bound(arg, vars))
         }
         Expression::Match(atom, matches, default) => {
-            if let Atom::Var(var) = atom {
-                is_bound(var, vars)
-            }
+            is_atom_bound(atom, vars)?;
             let mut unique_pat = HashSet::new();
             for (pat, exp) in matches {
-                assert!(unique_pat.insert(pat), "Repeated pattern in match");
-                check_expr(exp, vars, top);
+                if !unique_pat.insert(pat) {
+                    return Err(CheckError::RepeatedPattern(*pat));
+                }
+                check_expr(exp, vars, arities)?;
             }
             if let Some(exp) = default {
-                check_expr(exp, vars, top)
+                check_expr(exp, vars, arities)?;
             }
+            Ok(())
         }
         Expression::Operate(_, x, y) => {
-            if let Atom::Var(var) = x {
-                is_bound(var, vars);
-            }
-            if let Atom::Var(var) = y {
-                is_bound(var, vars);
+            is_atom_bound(x, vars)?;
+            is_atom_bound(y, vars)
+        }
+        Expression::Prim(prim, args) => {
+            if prim.arity() != args.len() {
+                let name = format!("#{}", prim.name());
+                return Err(CheckError::WrongArity(name, prim.arity(), args.len()));
             }
+            args.iter().try_for_each(|arg| is_atom_bound(arg, vars))
+        }
+        Expression::Str(_) => Ok(()),
+        Expression::Store(x) => is_atom_bound(x, vars),
+        Expression::Fetch(p) => is_bound(p, vars),
+        Expression::Update(p, x) => {
+            is_bound(p, vars)?;
+            is_atom_bound(x, vars)
+        }
+        Expression::Force(t) => is_bound(t, vars),
+        Expression::Throw(x) => is_atom_bound(x, vars),
+        Expression::Catch(body, x, handler) => {
+            check_expr(body, vars, arities)?;
+            insert_unique(x, vars)?;
+            check_expr(handler, vars, arities)
+        }
+        Expression::Perform(x) => is_atom_bound(x, vars),
+        Expression::Handle(body, x, k, handler) => {
+            check_expr(body, vars, arities)?;
+            insert_unique(x, vars)?;
+            insert_unique(k, vars)?;
+            check_expr(handler, vars, arities)
+        }
+        Expression::Resume(k, x) => {
+            is_bound(k, vars)?;
+            is_atom_bound(x, vars)
         }
     }
 }
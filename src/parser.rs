@@ -1,34 +1,57 @@
-use crate::expr::{Atom, Definition, Expression, Operation};
-use crate::lexer::{Bracket, Keyword, Symbol, Token};
+use crate::expr::{Atom, Declaration, Definition, Expression, Header, Operation, Primitive, Type};
+use crate::lexer::{Bracket, Keyword, Lexeme, Span, Symbol, Token};
+use std::fmt;
 use std::iter::Peekable;
 
 pub struct Scanner<Iter: Iterator> {
     iter: Peekable<Iter>,
+    // Span of the last consumed token
+    last: Span,
+    // Spans of the expressions of the current definition, in the order they start
+    spans: Vec<Span>,
 }
 
+// A parameter with its optional type annotation
+type Param = (String, Option<Type>);
+
 #[derive(Debug)]
 pub enum ParserError {
     Expected(String),
     UnknownOperation(String),
+    UnknownPrimitive(String),
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParserError::Expected(msg) => write!(f, "Expected {msg}"),
+            ParserError::UnknownOperation(op) => write!(f, "Unknown operation `{op}`"),
+            ParserError::UnknownPrimitive(name) => write!(f, "Unknown primitive `#{name}`"),
+        }
+    }
 }
 
 fn expected<S: Into<String>>(msg: S) -> ParserError {
     ParserError::Expected(msg.into())
 }
 
-impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
+impl<Iter: Iterator<Item = Lexeme>> Scanner<Iter> {
     pub fn new(iter: Iter) -> Self {
         Self {
             iter: iter.peekable(),
+            last: Span::default(),
+            spans: vec![],
         }
     }
 
     fn consume_token(&mut self) -> Option<Token> {
-        self.iter.next()
+        let lexeme = self.iter.next()?;
+        self.last = lexeme.span;
+        Some(lexeme.token)
     }
 
     fn peek(&mut self) -> Option<&Token> {
-        self.iter.peek()
+        self.iter.peek().map(|lexeme| &lexeme.token)
     }
 
     fn expect_token(&mut self, t: &Token) -> Result<(), ParserError> {
@@ -57,6 +80,7 @@ impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
         let x = match self.peek()? {
             Token::Identifier(v) => Atom::Var(v.clone()),
             Token::Number(n) => Atom::Lit(*n),
+            Token::Float(x) => Atom::Float(*x),
             _ => return None,
         };
         self.consume_token();
@@ -83,41 +107,192 @@ impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
             "^" => Some(Ok(Xor)),
             ">>" => Some(Ok(Sr)),
             "<<" => Some(Ok(Sl)),
+            "+." => Some(Ok(FAdd)),
+            "-." => Some(Ok(FSub)),
+            "*." => Some(Ok(FMul)),
+            "/." => Some(Ok(FDiv)),
+            "==." => Some(Ok(FEq)),
+            "<." => Some(Ok(FLt)),
+            "<=." => Some(Ok(FLe)),
+            ">." => Some(Ok(FGt)),
+            ">=." => Some(Ok(FGe)),
             _ => Some(Err(ParserError::UnknownOperation(string))),
         }
     }
 
+    // Optional `module` declaration followed by imports and extern functions, before the
+    // definitions
+    pub fn parse_header(&mut self) -> Result<Header, ParserError> {
+        let mut header = Header::default();
+        if let Some(Token::Keyword(Keyword::Module)) = self.peek() {
+            self.consume_token();
+            header.name = Some(self.parse_module_name()?);
+        }
+        while let Some(Token::Keyword(Keyword::Import)) = self.peek() {
+            self.consume_token();
+            let name = self.parse_module_name()?;
+            header.imports.push(name);
+        }
+        while let Some(Token::Keyword(Keyword::Extern)) = self.peek() {
+            self.consume_token();
+            let decl = self.parse_declaration().ok_or(expected("`fn`"))?;
+            header.externs.push(decl?);
+        }
+        Ok(header)
+    }
+
+    fn parse_module_name(&mut self) -> Result<String, ParserError> {
+        let Some(Token::Identifier(name)) = self.consume_token() else {
+            return Err(expected("a module name"));
+        };
+        self.expect_token(&Token::Symbol(Symbol::Semicolon))?;
+        Ok(name)
+    }
+
     fn parse_def(&mut self) -> Option<Result<Definition, ParserError>> {
         if Token::Keyword(Keyword::Fn) != *self.peek()? {
             return None;
         }
+        let trivia = self.iter.peek()?.trivia.iter();
+        let comments = trivia.filter(|trivia| trivia.is_comment());
+        let comments = comments.map(|trivia| trivia.text().to_owned()).collect();
         self.consume_token();
 
-        Some(self.parse_def_inner())
+        Some(self.parse_def_inner(comments))
     }
 
-    fn parse_def_inner(&mut self) -> Result<Definition, ParserError> {
-        let (name, params) = self.parse_function_header()?;
+    fn parse_def_inner(&mut self, comments: Vec<String>) -> Result<Definition, ParserError> {
+        let start = self.last;
+        let decl = self.parse_signature()?;
         self.expect_token(&Token::Paren(Bracket::LBrace))?;
+        self.spans.clear();
         let body = self.parse_expr()?;
         self.expect_token(&Token::Paren(Bracket::RBrace))?;
-        Ok(Definition { name, params, body })
+        Ok(Definition {
+            name: decl.name,
+            params: decl.params,
+            param_types: decl.param_types,
+            ret_type: decl.ret_type,
+            body,
+            span: start.merge(self.last),
+            spans: std::mem::take(&mut self.spans),
+            comments,
+        })
+    }
+
+    // Declaration of a function without its body, ended by a semicolon
+    pub fn parse_declaration(&mut self) -> Option<Result<Declaration, ParserError>> {
+        if Token::Keyword(Keyword::Fn) != *self.peek()? {
+            return None;
+        }
+        self.consume_token();
+        let decl = self.parse_signature().and_then(|decl| {
+            self.expect_token(&Token::Symbol(Symbol::Semicolon))?;
+            Ok(decl)
+        });
+        Some(decl)
     }
 
+    fn parse_signature(&mut self) -> Result<Declaration, ParserError> {
+        let (name, params) = self.parse_function_header()?;
+        let (params, param_types) = params.into_iter().unzip();
+        let ret_type = match self.peek() {
+            Some(Token::Symbol(Symbol::Minus)) => {
+                self.expect_arrow("->")?;
+                Some(self.parse_type()?)
+            }
+            _ => None,
+        };
+        Ok(Declaration {
+            name,
+            params,
+            param_types,
+            ret_type,
+        })
+    }
+
+    fn expect_arrow(&mut self, arrow: &str) -> Result<(), ParserError> {
+        let symbols = self.parse_symbol().ok_or(expected(arrow))?;
+        let string: String = symbols.into_iter().map(|s| s.to_char()).collect();
+        if string.as_str() != arrow {
+            return Err(expected(arrow));
+        }
+        Ok(())
+    }
+
+    fn parse_type(&mut self) -> Result<Type, ParserError> {
+        let Some(Token::Identifier(name)) = self.consume_token() else {
+            return Err(expected("a type"));
+        };
+        let args = match self.peek() {
+            Some(Token::Paren(Bracket::LParen)) => self.parse_type_args()?,
+            _ => vec![],
+        };
+        match name.as_str() {
+            "Int" if args.is_empty() => Ok(Type::Int),
+            "Int" => Err(expected("`Int` with no arguments")),
+            "Fn" => {
+                self.expect_arrow("->")?;
+                let ret = self.parse_type()?;
+                Ok(Type::Fn(args, ret.into()))
+            }
+            "Rec" => match <[Type; 2]>::try_from(args) {
+                Ok([Type::Var(var), body]) => Ok(Type::Rec(var, body.into())),
+                _ => Err(expected("`Rec` with a type variable and a type")),
+            },
+            _ if name.starts_with(char::is_lowercase) && args.is_empty() => Ok(Type::Var(name)),
+            _ if name.starts_with(char::is_lowercase) => {
+                Err(expected("a type variable with no arguments"))
+            }
+            _ => Ok(Type::Con(name, args)),
+        }
+    }
+
+    fn parse_type_args(&mut self) -> Result<Vec<Type>, ParserError> {
+        self.expect_token(&Token::Paren(Bracket::LParen))?;
+        let mut args = vec![];
+        if let Some(Token::Paren(Bracket::RParen)) = self.peek() {
+            self.consume_token();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_type()?);
+            match self.consume_token() {
+                Some(Token::Symbol(Symbol::Comma)) => {}
+                Some(Token::Paren(Bracket::RParen)) => return Ok(args),
+                _ => return Err(expected("`,` or `)`")),
+            }
+        }
+    }
+
+    // The span of an expression is reserved before parsing the ones it holds
     fn parse_expr(&mut self) -> Result<Expression, ParserError> {
+        let index = self.spans.len();
+        let start = self.iter.peek().map_or(self.last, |lexeme| lexeme.span);
+        self.spans.push(start);
+        let expr = self.parse_expr_inner()?;
+        self.spans[index] = start.merge(self.last);
+        Ok(expr)
+    }
+
+    fn parse_expr_inner(&mut self) -> Result<Expression, ParserError> {
         let expr = match self.peek().ok_or(expected("an expression"))? {
             Token::Keyword(Keyword::Let) => return self.parse_let(),
             Token::Keyword(Keyword::Match) => return self.parse_match(),
+            Token::Keyword(Keyword::Catch) => return self.parse_catch(),
+            Token::Keyword(Keyword::Handle) => return self.parse_handle(),
             Token::Paren(Bracket::LParen) => return self.parse_apply(),
             Token::Identifier(var) => Expression::Unit(Atom::Var(var.clone())),
             Token::Number(num) => Expression::Unit(Atom::Lit(*num)),
+            Token::Float(x) => Expression::Unit(Atom::Float(*x)),
+            Token::String(bytes) => Expression::Str(bytes.clone()),
             _ => return Err(expected("an expression")),
         };
         self.consume_token();
         Ok(expr)
     }
 
-    fn parse_function_header(&mut self) -> Result<(String, Vec<String>), ParserError> {
+    fn parse_function_header(&mut self) -> Result<(String, Vec<Param>), ParserError> {
         self.expect_token(&Token::Paren(Bracket::LParen))?;
         let c = self.consume_token().ok_or(expected("function name"))?;
         let Token::Identifier(name) = c else {
@@ -125,8 +300,16 @@ impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
         };
         let mut args = vec![];
         while let Some(Token::Identifier(arg)) = self.peek() {
-            args.push(arg.clone());
+            let arg = arg.clone();
             self.consume_token();
+            let typ = match self.peek() {
+                Some(Token::Symbol(Symbol::Colon)) => {
+                    self.consume_token();
+                    Some(self.parse_type()?)
+                }
+                _ => None,
+            };
+            args.push((arg, typ));
         }
         self.expect_token(&Token::Paren(Bracket::RParen))?;
         Ok((name.clone(), args))
@@ -169,6 +352,63 @@ impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
             }
             self.expect_token(&Token::Paren(Bracket::RParen))?;
             Ok(Expression::Papp(func, args))
+        } else if let Some(Token::Primitive(name)) = token {
+            let prim =
+                Primitive::from_name(&name).ok_or(ParserError::UnknownPrimitive(name.clone()))?;
+            let mut args = vec![];
+            while let Some(arg) = self.parse_atom() {
+                args.push(arg);
+            }
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Prim(prim, args))
+        } else if let Some(Token::Keyword(Keyword::Store)) = token {
+            let x = self.parse_atom().ok_or(expected("an atom to store"))?;
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Store(x))
+        } else if let Some(Token::Keyword(Keyword::Fetch)) = token {
+            let Some(Token::Identifier(p)) = self.consume_token() else {
+                return Err(expected("a reference to fetch"));
+            };
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Fetch(p))
+        } else if let Some(Token::Keyword(Keyword::Update)) = token {
+            let Some(Token::Identifier(p)) = self.consume_token() else {
+                return Err(expected("a reference to update"));
+            };
+            let x = self.parse_atom().ok_or(expected("an atom"))?;
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Update(p, x))
+        } else if let Some(Token::Keyword(Keyword::Thunk)) = token {
+            let Some(Token::Identifier(func)) = self.consume_token() else {
+                return Err(expected("a function to suspend"));
+            };
+            let mut args = vec![];
+            while let Some(arg) = self.parse_atom() {
+                args.push(arg);
+            }
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Thunk(func, args))
+        } else if let Some(Token::Keyword(Keyword::Force)) = token {
+            let Some(Token::Identifier(t)) = self.consume_token() else {
+                return Err(expected("a thunk to force"));
+            };
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Force(t))
+        } else if let Some(Token::Keyword(Keyword::Throw)) = token {
+            let x = self.parse_atom().ok_or(expected("an atom to throw"))?;
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Throw(x))
+        } else if let Some(Token::Keyword(Keyword::Perform)) = token {
+            let x = self.parse_atom().ok_or(expected("an atom to perform"))?;
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Perform(x))
+        } else if let Some(Token::Keyword(Keyword::Resume)) = token {
+            let Some(Token::Identifier(k)) = self.consume_token() else {
+                return Err(expected("a continuation to resume"));
+            };
+            let x = self.parse_atom().ok_or(expected("an atom"))?;
+            self.expect_token(&Token::Paren(Bracket::RParen))?;
+            Ok(Expression::Resume(k, x))
         } else {
             Err(expected("a function or operator"))
         }
@@ -186,6 +426,41 @@ impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
         Ok(Expression::Let(name.clone(), val.into(), body.into()))
     }
 
+    fn parse_catch(&mut self) -> Result<Expression, ParserError> {
+        self.expect_token(&Token::Keyword(Keyword::Catch))?;
+        self.expect_token(&Token::Paren(Bracket::LBrace))?;
+        let body = self.parse_expr()?;
+        self.expect_token(&Token::Paren(Bracket::RBrace))?;
+        self.expect_token(&Token::Keyword(Keyword::With))?;
+        let Some(Token::Identifier(x)) = self.consume_token() else {
+            return Err(expected("an identifier"));
+        };
+        self.expect_arrow("=>")?;
+        self.expect_token(&Token::Paren(Bracket::LBrace))?;
+        let handler = self.parse_expr()?;
+        self.expect_token(&Token::Paren(Bracket::RBrace))?;
+        Ok(Expression::Catch(body.into(), x, handler.into()))
+    }
+
+    fn parse_handle(&mut self) -> Result<Expression, ParserError> {
+        self.expect_token(&Token::Keyword(Keyword::Handle))?;
+        self.expect_token(&Token::Paren(Bracket::LBrace))?;
+        let body = self.parse_expr()?;
+        self.expect_token(&Token::Paren(Bracket::RBrace))?;
+        self.expect_token(&Token::Keyword(Keyword::With))?;
+        let Some(Token::Identifier(x)) = self.consume_token() else {
+            return Err(expected("an identifier"));
+        };
+        let Some(Token::Identifier(k)) = self.consume_token() else {
+            return Err(expected("an identifier for the continuation"));
+        };
+        self.expect_arrow("=>")?;
+        self.expect_token(&Token::Paren(Bracket::LBrace))?;
+        let handler = self.parse_expr()?;
+        self.expect_token(&Token::Paren(Bracket::RBrace))?;
+        Ok(Expression::Handle(body.into(), x, k, handler.into()))
+    }
+
     fn parse_match(&mut self) -> Result<Expression, ParserError> {
         self.expect_token(&Token::Keyword(Keyword::Match))?;
         let atom = self.parse_atom().ok_or(expected("an atom"))?;
@@ -222,7 +497,7 @@ impl<Iter: Iterator<Item = Token>> Scanner<Iter> {
     }
 }
 
-impl<Iter: Iterator<Item = Token>> Iterator for Scanner<Iter> {
+impl<Iter: Iterator<Item = Lexeme>> Iterator for Scanner<Iter> {
     type Item = Result<Definition, ParserError>;
 
     fn next(&mut self) -> Option<Self::Item> {
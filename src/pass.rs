@@ -0,0 +1,509 @@
+use crate::callgraph::CallGraph;
+use crate::expr::{Atom, Definition, Expression, Operation};
+use crate::module::{self, CheckError, Module};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// A transformation over a whole module. Passes may assume their input passes
+// `module::check`, and must produce a module that passes it as well
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn run(&self, module: Module) -> Module;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PassError {
+    pub pass: &'static str,
+    pub error: CheckError,
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Pass `{}` produced an invalid module: {}",
+            self.pass, self.error
+        )
+    }
+}
+
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+    dump: bool,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self {
+            passes: vec![],
+            dump: false,
+        }
+    }
+
+    pub fn with_level(level: OptLevel) -> Self {
+        let mut pipeline = Self::new();
+        match level {
+            OptLevel::O0 => {}
+            OptLevel::O1 => {
+                pipeline.add(CopyPropagation);
+                pipeline.add(ConstantFolding);
+            }
+            OptLevel::O2 => {
+                pipeline.add(CopyPropagation);
+                pipeline.add(ConstantFolding);
+                pipeline.add(CopyPropagation);
+                pipeline.add(KnownMatch);
+                pipeline.add(ConstantFolding);
+                pipeline.add(DeadLet);
+                pipeline.add(DeadFunctions);
+            }
+        }
+        pipeline
+    }
+
+    pub fn add(&mut self, pass: impl Pass + 'static) {
+        self.passes.push(Box::new(pass))
+    }
+
+    // Print the module to stderr before the first pass and after every pass
+    pub fn dump(&mut self, dump: bool) {
+        self.dump = dump
+    }
+
+    pub fn run(&self, mut module: Module) -> Result<Module, PassError> {
+        if self.dump {
+            dump_module("input", &module);
+        }
+        for pass in self.passes.iter() {
+            module = pass.run(module);
+            module::check(&module).map_err(|error| PassError {
+                pass: pass.name(),
+                error,
+            })?;
+            if self.dump {
+                dump_module(pass.name(), &module);
+            }
+        }
+        Ok(module)
+    }
+}
+
+// Definitions are sorted by name so that dumps of consecutive passes can be diffed
+fn dump_module(stage: &str, module: &Module) {
+    let mut defs = module.toplevel().values().collect::<Vec<_>>();
+    defs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut externs = module.externs().values().collect::<Vec<_>>();
+    externs.sort_by(|a, b| a.name.cmp(&b.name));
+    eprintln!("==> {stage}");
+    externs
+        .iter()
+        .for_each(|decl| eprintln!("extern {:?}\n", decl.pretty()));
+    defs.iter()
+        .for_each(|def| eprintln!("{:?}\n", def.pretty()));
+}
+
+fn map_bodies(module: Module, f: impl Fn(Expression) -> Expression) -> Module {
+    let (defs, externs) = module.into_parts();
+    let defs = defs.map(|def| Definition {
+        body: f(def.body),
+        spans: vec![],
+        ..def
+    });
+    Module::new_unchecked(defs).with_externs(externs)
+}
+
+fn map_expr(expr: Expression, f: &impl Fn(Expression) -> Expression) -> Expression {
+    match expr {
+        Expression::Let(x, val, body) => Expression::Let(x, f(*val).into(), f(*body).into()),
+        Expression::Match(atom, cases, default) => {
+            let cases = cases.into_iter().map(|(n, e)| (n, f(e))).collect();
+            Expression::Match(atom, cases, default.map(|e| f(*e).into()))
+        }
+        Expression::Catch(body, x, handler) => {
+            Expression::Catch(f(*body).into(), x, f(*handler).into())
+        }
+        Expression::Handle(body, x, k, handler) => {
+            Expression::Handle(f(*body).into(), x, k, f(*handler).into())
+        }
+        expr => expr,
+    }
+}
+
+fn occurs(x: &str, expr: &Expression) -> bool {
+    let in_atom = |atom: &Atom| matches!(atom, Atom::Var(y) if y == x);
+    match expr {
+        Expression::Unit(atom) => in_atom(atom),
+        Expression::Let(_, val, body)
+        | Expression::Catch(val, _, body)
+        | Expression::Handle(val, _, _, body) => occurs(x, val) || occurs(x, body),
+        Expression::Apply(f, args) => f == x || args.iter().any(in_atom),
+        Expression::Call(_, args)
+        | Expression::Papp(_, args)
+        | Expression::Prim(_, args)
+        | Expression::Thunk(_, args) => args.iter().any(in_atom),
+        Expression::Match(atom, cases, default) => {
+            in_atom(atom)
+                || cases.iter().any(|(_, e)| occurs(x, e))
+                || default.as_ref().is_some_and(|e| occurs(x, e))
+        }
+        Expression::Operate(_, a, b) => in_atom(a) || in_atom(b),
+        Expression::Str(_) => false,
+        Expression::Store(atom) => in_atom(atom),
+        Expression::Fetch(p) => p == x,
+        Expression::Update(p, atom) => p == x || in_atom(atom),
+        Expression::Force(t) => t == x,
+        Expression::Throw(atom) | Expression::Perform(atom) => in_atom(atom),
+        Expression::Resume(k, atom) => k == x || in_atom(atom),
+    }
+}
+
+// Whether `x` is the closure of an `apply`, the reference of a `fetch` or an `update`, the
+// thunk of a `force`, or the continuation of a `resume`
+fn is_head(x: &str, expr: &Expression) -> bool {
+    match expr {
+        Expression::Apply(f, _)
+        | Expression::Fetch(f)
+        | Expression::Update(f, _)
+        | Expression::Force(f)
+        | Expression::Resume(f, _) => f == x,
+        Expression::Let(_, val, body)
+        | Expression::Catch(val, _, body)
+        | Expression::Handle(val, _, _, body) => is_head(x, val) || is_head(x, body),
+        Expression::Match(_, cases, default) => {
+            cases.iter().any(|(_, e)| is_head(x, e))
+                || default.as_ref().is_some_and(|e| is_head(x, e))
+        }
+        _ => false,
+    }
+}
+
+// Replaces variables bound to atoms by the atoms themselves
+pub struct CopyPropagation;
+
+impl Pass for CopyPropagation {
+    fn name(&self) -> &'static str {
+        "copy-propagation"
+    }
+
+    fn run(&self, module: Module) -> Module {
+        map_bodies(module, |body| propagate(body, &mut HashMap::new()))
+    }
+}
+
+fn propagate(expr: Expression, env: &mut HashMap<String, Atom>) -> Expression {
+    let subst = |atom: Atom, env: &HashMap<String, Atom>| match atom {
+        Atom::Var(x) => env.get(&x).cloned().unwrap_or(Atom::Var(x)),
+        atom => atom,
+    };
+    let subst_var = |x: String, env: &HashMap<String, Atom>| match env.get(&x) {
+        Some(Atom::Var(y)) => y.clone(),
+        _ => x,
+    };
+    let subst_all = |args: Vec<Atom>, env: &HashMap<String, Atom>| {
+        args.into_iter().map(|arg| subst(arg, env)).collect()
+    };
+    match expr {
+        Expression::Unit(atom) => Expression::Unit(subst(atom, env)),
+        Expression::Let(x, val, body) => match propagate(*val, env) {
+            // Literals cannot be in the head of an `apply`, a `fetch`, an `update`, a `force` or
+            // a `resume`, so their bindings must be kept
+            Expression::Unit(atom) if !matches!(atom, Atom::Var(_)) && is_head(&x, &body) => {
+                let body = propagate(*body, env);
+                Expression::Let(x, Expression::Unit(atom).into(), body.into())
+            }
+            Expression::Unit(atom) => {
+                env.insert(x, atom);
+                propagate(*body, env)
+            }
+            val => Expression::Let(x, val.into(), propagate(*body, env).into()),
+        },
+        Expression::Apply(f, args) => Expression::Apply(subst_var(f, env), subst_all(args, env)),
+        Expression::Call(f, args) => Expression::Call(f, subst_all(args, env)),
+        Expression::Papp(f, args) => Expression::Papp(f, subst_all(args, env)),
+        Expression::Match(atom, cases, default) => {
+            let atom = subst(atom, env);
+            let cases = cases
+                .into_iter()
+                .map(|(n, e)| (n, propagate(e, env)))
+                .collect();
+            let default = default.map(|e| propagate(*e, env).into());
+            Expression::Match(atom, cases, default)
+        }
+        Expression::Operate(op, x, y) => Expression::Operate(op, subst(x, env), subst(y, env)),
+        Expression::Prim(prim, args) => Expression::Prim(prim, subst_all(args, env)),
+        Expression::Str(bytes) => Expression::Str(bytes),
+        Expression::Store(x) => Expression::Store(subst(x, env)),
+        Expression::Fetch(p) => Expression::Fetch(subst_var(p, env)),
+        Expression::Update(p, x) => Expression::Update(subst_var(p, env), subst(x, env)),
+        Expression::Thunk(f, args) => Expression::Thunk(f, subst_all(args, env)),
+        Expression::Force(t) => Expression::Force(subst_var(t, env)),
+        Expression::Throw(x) => Expression::Throw(subst(x, env)),
+        Expression::Catch(body, x, handler) => {
+            let body = propagate(*body, env);
+            Expression::Catch(body.into(), x, propagate(*handler, env).into())
+        }
+        Expression::Perform(x) => Expression::Perform(subst(x, env)),
+        Expression::Handle(body, x, k, handler) => {
+            let body = propagate(*body, env);
+            Expression::Handle(body.into(), x, k, propagate(*handler, env).into())
+        }
+        Expression::Resume(k, x) => Expression::Resume(subst_var(k, env), subst(x, env)),
+    }
+}
+
+// Evaluates operations on literals. Operations that would fail at runtime are left untouched
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&self, module: Module) -> Module {
+        map_bodies(module, fold)
+    }
+}
+
+fn fold(expr: Expression) -> Expression {
+    match expr {
+        Expression::Operate(op, Atom::Lit(x), Atom::Lit(y)) => match fold_operation(op, x, y) {
+            Some(n) => Expression::Unit(Atom::Lit(n)),
+            None => Expression::Operate(op, Atom::Lit(x), Atom::Lit(y)),
+        },
+        expr => map_expr(expr, &fold),
+    }
+}
+
+fn fold_operation(op: Operation, x: usize, y: usize) -> Option<usize> {
+    let shift = u32::try_from(y).ok();
+    match op {
+        Operation::Add => x.checked_add(y),
+        Operation::Sub => x.checked_sub(y),
+        Operation::Mul => x.checked_mul(y),
+        Operation::Div => x.checked_div(y),
+        Operation::Mod => x.checked_rem(y),
+        Operation::Eq => Some((x == y) as usize),
+        Operation::Lt => Some((x < y) as usize),
+        Operation::Le => Some((x <= y) as usize),
+        Operation::Gt => Some((x > y) as usize),
+        Operation::Ge => Some((x >= y) as usize),
+        Operation::And => Some(x & y),
+        Operation::Or => Some(x | y),
+        Operation::Xor => Some(x ^ y),
+        Operation::Sr => shift.and_then(|s| x.checked_shr(s)),
+        Operation::Sl => shift.and_then(|s| x.checked_shl(s)),
+        // Floating-point operations fail on integers
+        _ => None,
+    }
+}
+
+// Replaces matches on literals by the selected branch
+pub struct KnownMatch;
+
+impl Pass for KnownMatch {
+    fn name(&self) -> &'static str {
+        "known-match"
+    }
+
+    fn run(&self, module: Module) -> Module {
+        map_bodies(module, select)
+    }
+}
+
+fn select(expr: Expression) -> Expression {
+    match expr {
+        Expression::Match(Atom::Lit(n), cases, default) => {
+            let has_case = cases.iter().any(|(m, _)| *m == n);
+            if !has_case && default.is_none() {
+                // The match fails at runtime, so it is left as it is
+                return Expression::Match(Atom::Lit(n), cases, default);
+            }
+            let branch = cases
+                .into_iter()
+                .find_map(|(m, branch)| if m == n { Some(branch) } else { None })
+                .or(default.map(|e| *e))
+                .unwrap();
+            select(branch)
+        }
+        expr => map_expr(expr, &select),
+    }
+}
+
+// Removes `let` bindings of unused variables whose value has no effect
+pub struct DeadLet;
+
+impl Pass for DeadLet {
+    fn name(&self) -> &'static str {
+        "dead-let"
+    }
+
+    fn run(&self, module: Module) -> Module {
+        map_bodies(module, eliminate)
+    }
+}
+
+fn eliminate(expr: Expression) -> Expression {
+    match expr {
+        Expression::Let(x, val, body) => {
+            let body = eliminate(*body);
+            if !occurs(&x, &body) && is_pure(&val) {
+                return body;
+            }
+            Expression::Let(x, eliminate(*val).into(), body.into())
+        }
+        expr => map_expr(expr, &eliminate),
+    }
+}
+
+// Calls, applications and forced thunks might not terminate, operations and fetches might fail,
+// and primitives, updates, exceptions and effect handlers have effects, so they are never
+// considered pure. Only comparisons and bitwise operations of integer literals cannot fail
+fn is_pure(expr: &Expression) -> bool {
+    match expr {
+        Expression::Unit(_)
+        | Expression::Papp(..)
+        | Expression::Str(_)
+        | Expression::Store(_)
+        | Expression::Thunk(..) => true,
+        Expression::Let(_, val, body) => is_pure(val) && is_pure(body),
+        Expression::Match(..)
+        | Expression::Apply(..)
+        | Expression::Call(..)
+        | Expression::Prim(..)
+        | Expression::Fetch(_)
+        | Expression::Update(..)
+        | Expression::Force(_)
+        | Expression::Throw(_)
+        | Expression::Catch(..)
+        | Expression::Perform(_)
+        | Expression::Handle(..)
+        | Expression::Resume(..) => false,
+        // Any operation fails when a variable holds a float or a closure, and arithmetic can
+        // overflow even on literals
+        Expression::Operate(op, x, y) => {
+            matches!(
+                op,
+                Operation::Eq
+                    | Operation::Lt
+                    | Operation::Le
+                    | Operation::Gt
+                    | Operation::Ge
+                    | Operation::And
+                    | Operation::Or
+                    | Operation::Xor
+            ) && matches!((x, y), (Atom::Lit(_), Atom::Lit(_)))
+        }
+    }
+}
+
+// Removes the definitions that cannot be reached from `main`
+pub struct DeadFunctions;
+
+impl Pass for DeadFunctions {
+    fn name(&self) -> &'static str {
+        "dead-functions"
+    }
+
+    fn run(&self, module: Module) -> Module {
+        if module.get("main").is_none() {
+            return module;
+        }
+        let live = CallGraph::new(&module)
+            .reachable("main")
+            .into_iter()
+            .map(String::from)
+            .collect::<HashSet<_>>();
+        let (defs, externs) = module.into_parts();
+        let defs = defs.filter(|def| live.contains(&def.name));
+        Module::new_unchecked(defs).with_externs(externs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{self, Rng};
+    use crate::interpreter::{Halt, RuntimeError, State, Value};
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    fn parse(source: &str) -> Module {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        Module::new(ParserScanner::new(tokens).map(|m| m.unwrap()))
+    }
+
+    fn single(pass: impl Pass + 'static) -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        pipeline.add(pass);
+        pipeline
+    }
+
+    fn run(module: &Module) -> usize {
+        match State::new().run(module) {
+            Ok(Value::Num(n)) => n,
+            _ => panic!("Expected a number"),
+        }
+    }
+
+    #[test]
+    fn passes_preserve_results() {
+        let pipelines = [
+            single(CopyPropagation),
+            single(ConstantFolding),
+            single(KnownMatch),
+            single(DeadLet),
+            single(DeadFunctions),
+            Pipeline::with_level(OptLevel::O1),
+            Pipeline::with_level(OptLevel::O2),
+        ];
+        for seed in 0..200 {
+            let module = || Module::new(generate::module(&mut Rng::new(seed)).into_iter());
+            let expected = run(&module());
+            for (i, pipeline) in pipelines.iter().enumerate() {
+                let optimised = pipeline.run(module()).unwrap();
+                assert_eq!(run(&optimised), expected, "seed {seed}, pipeline {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn failing_operations_are_kept() {
+        let source = "
+            fn (g f) {
+              let u = (== f 1);
+              let v = (< 1 2);
+              0
+            }
+
+            fn (main) {
+              (g 1.5)
+            }";
+        let optimised = single(DeadLet).run(parse(source)).unwrap();
+        // Only the comparison of literals is removed
+        let Expression::Let(x, _, body) = &optimised.toplevel()["g"].body else {
+            panic!("Expected a binding")
+        };
+        assert_eq!(x, "u");
+        assert!(matches!(**body, Expression::Unit(_)));
+        let err = RuntimeError::Operand(Operation::Eq, Value::Float(1.5));
+        assert_eq!(State::new().run(&optimised), Err(Halt::Error(err)));
+    }
+}
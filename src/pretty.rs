@@ -1,4 +1,5 @@
-use crate::expr::{Atom, Definition, Expression, Operation};
+use crate::expr::{Atom, Declaration, Definition, Expression, Operation, Type};
+use crate::infer::Signature;
 use crate::lexer::{Keyword, Token};
 use std::fmt;
 
@@ -19,8 +20,25 @@ impl Definition {
     }
 }
 
+impl Declaration {
+    pub fn pretty(&self) -> Pretty<&Self> {
+        Pretty { inner: self }
+    }
+}
+
 impl Operation {
-    #[allow(dead_code)]
+    pub fn pretty(&self) -> Pretty<&Self> {
+        Pretty { inner: self }
+    }
+}
+
+impl Type {
+    pub fn pretty(&self) -> Pretty<&Self> {
+        Pretty { inner: self }
+    }
+}
+
+impl Signature {
     pub fn pretty(&self) -> Pretty<&Self> {
         Pretty { inner: self }
     }
@@ -54,6 +72,30 @@ fn op_to_str(x: &Operation) -> String {
         Operation::Xor => "^".to_string(),
         Operation::Sr => ">>".to_string(),
         Operation::Sl => "<<".to_string(),
+        Operation::FAdd => "+.".to_string(),
+        Operation::FSub => "-.".to_string(),
+        Operation::FMul => "*.".to_string(),
+        Operation::FDiv => "/.".to_string(),
+        Operation::FEq => "==.".to_string(),
+        Operation::FLt => "<.".to_string(),
+        Operation::FLe => "<=.".to_string(),
+        Operation::FGt => ">.".to_string(),
+        Operation::FGe => ">=.".to_string(),
+    }
+}
+
+fn type_to_str(x: &Type) -> String {
+    let args_to_str = |args: &[Type]| {
+        let args = args.iter().map(type_to_str).collect::<Vec<_>>();
+        format!("({})", args.join(", "))
+    };
+    match x {
+        Type::Int => "Int".to_string(),
+        Type::Fn(args, ret) => format!("Fn{} -> {}", args_to_str(args), type_to_str(ret)),
+        Type::Con(name, args) if args.is_empty() => name.to_string(),
+        Type::Con(name, args) => format!("{}{}", name, args_to_str(args)),
+        Type::Var(name) => name.to_string(),
+        Type::Rec(name, body) => format!("Rec({}, {})", name, type_to_str(body)),
     }
 }
 
@@ -61,9 +103,30 @@ fn atom_to_str(x: &Atom) -> String {
     match x {
         Atom::Var(x) => x.to_string(),
         Atom::Lit(x) => x.to_string(),
+        // Always printed with a fractional part or an exponent
+        Atom::Float(x) => format!("{x:?}"),
     }
 }
 
+// Escapes every byte that is not printable ASCII, so that the literal parses back to the same bytes
+fn str_to_str(bytes: &[u8]) -> String {
+    let mut s = String::from('"');
+    for byte in bytes {
+        match byte {
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'\r' => s.push_str("\\r"),
+            0 => s.push_str("\\0"),
+            b'\\' => s.push_str("\\\\"),
+            b'"' => s.push_str("\\\""),
+            b' '..=b'~' => s.push(*byte as char),
+            _ => s.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    s.push('"');
+    s
+}
+
 fn fmt_expr(
     f: &mut fmt::Formatter<'_>,
     expr: &Expression,
@@ -123,17 +186,108 @@ fn fmt_expr(
             atom_to_str(x),
             atom_to_str(y)
         ),
+        Expression::Prim(prim, xs) => {
+            let xs = xs.iter().map(atom_to_str).collect::<Vec<_>>();
+            fmt_app(f, &format!("#{}", prim.name()), &xs)
+        }
+        Expression::Str(bytes) => write!(f, "{}", str_to_str(bytes)),
+        Expression::Store(x) => fmt_app(f, &"store".into(), &[atom_to_str(x)]),
+        Expression::Fetch(p) => fmt_app(f, &"fetch".into(), std::slice::from_ref(p)),
+        Expression::Update(p, x) => fmt_app(f, &"update".into(), &[p.clone(), atom_to_str(x)]),
+        Expression::Thunk(func, xs) => {
+            let mut args = Vec::with_capacity(xs.len() + 1);
+            args.push(func.clone());
+            xs.iter().map(atom_to_str).for_each(|s| args.push(s));
+            fmt_app(f, &"thunk".into(), &args)
+        }
+        Expression::Force(t) => fmt_app(f, &"force".into(), std::slice::from_ref(t)),
+        Expression::Throw(x) => fmt_app(f, &"throw".into(), &[atom_to_str(x)]),
+        Expression::Catch(body, x, handler) => {
+            write!(f, "catch {{")?;
+            fmt_expr(f, body, ident + 1, true)?;
+            fmt_newline_ident(f, ident)?;
+            write!(f, "}} with {x} => {{")?;
+            fmt_expr(f, handler, ident + 1, true)?;
+            fmt_newline_ident(f, ident)?;
+            write!(f, "}}")
+        }
+        Expression::Perform(x) => fmt_app(f, &"perform".into(), &[atom_to_str(x)]),
+        Expression::Handle(body, x, k, handler) => {
+            write!(f, "handle {{")?;
+            fmt_expr(f, body, ident + 1, true)?;
+            fmt_newline_ident(f, ident)?;
+            write!(f, "}} with {x} {k} => {{")?;
+            fmt_expr(f, handler, ident + 1, true)?;
+            fmt_newline_ident(f, ident)?;
+            write!(f, "}}")
+        }
+        Expression::Resume(k, x) => fmt_app(f, &"resume".into(), &[k.clone(), atom_to_str(x)]),
     }
 }
 
 fn fmt_def(f: &mut fmt::Formatter<'_>, def: &Definition) -> fmt::Result {
-    write!(f, "fn ")?;
-    fmt_app(f, &def.name, &def.params)?;
+    for comment in def.comments.iter() {
+        writeln!(f, "{comment}")?;
+    }
+    fmt_header(
+        f,
+        &def.name,
+        &def.params,
+        &def.param_types,
+        def.ret_type.as_ref(),
+    )?;
     write!(f, " {{")?;
     fmt_expr(f, &def.body, 1, true)?;
     write!(f, "\n}}")
 }
 
+fn fmt_header(
+    f: &mut fmt::Formatter<'_>,
+    name: &String,
+    params: &[String],
+    types: &[Option<Type>],
+    ret: Option<&Type>,
+) -> fmt::Result {
+    let params = params
+        .iter()
+        .zip(types.iter())
+        .map(|(param, typ)| match typ {
+            Some(typ) => format!("{}: {}", param, type_to_str(typ)),
+            None => param.clone(),
+        })
+        .collect::<Vec<_>>();
+    write!(f, "fn ")?;
+    fmt_app(f, name, &params)?;
+    if let Some(typ) = ret {
+        write!(f, " -> {}", type_to_str(typ))?;
+    }
+    Ok(())
+}
+
+// Signatures are printed as annotated headers, splitting the parameters out of the type
+fn fmt_signature(f: &mut fmt::Formatter<'_>, sig: &Signature) -> fmt::Result {
+    let arity = sig.params.len();
+    match &sig.typ {
+        _ if arity == 0 => fmt_header(f, &sig.name, &[], &[], Some(&sig.typ)),
+        Type::Fn(params, ret) if params.len() >= arity => {
+            let types = params[..arity]
+                .iter()
+                .cloned()
+                .map(Some)
+                .collect::<Vec<_>>();
+            let ret = match &params[arity..] {
+                [] => *ret.clone(),
+                rest => Type::Fn(rest.to_vec(), ret.clone()),
+            };
+            fmt_header(f, &sig.name, &sig.params, &types, Some(&ret))
+        }
+        typ => {
+            fmt_header(f, &sig.name, &sig.params, &vec![None; arity], None)?;
+            write!(f, " : {}", type_to_str(typ))
+        }
+    }
+}
+
 fn fmt_app(f: &mut fmt::Formatter<'_>, head: &String, args: &[String]) -> fmt::Result {
     write!(f, "({}", head)?;
     for arg in args {
@@ -160,18 +314,95 @@ impl fmt::Debug for Pretty<&Operation> {
     }
 }
 
+impl fmt::Debug for Pretty<&Type> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", type_to_str(self.inner))
+    }
+}
+
+impl fmt::Debug for Pretty<&Declaration> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decl = self.inner;
+        let ret = decl.ret_type.as_ref();
+        fmt_header(f, &decl.name, &decl.params, &decl.param_types, ret)?;
+        write!(f, ";")
+    }
+}
+
+impl fmt::Debug for Pretty<&Signature> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_signature(f, self.inner)
+    }
+}
+
 impl fmt::Debug for Pretty<&Token> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.inner {
             Token::Keyword(Keyword::Fn) => write!(f, "fn"),
-            Token::Keyword(Keyword::Let) => write!(f, "fn"),
+            Token::Keyword(Keyword::Let) => write!(f, "let"),
             Token::Keyword(Keyword::Match) => write!(f, "match"),
             Token::Keyword(Keyword::Apply) => write!(f, "apply"),
             Token::Keyword(Keyword::Papp) => write!(f, "papp"),
+            Token::Keyword(Keyword::Module) => write!(f, "module"),
+            Token::Keyword(Keyword::Import) => write!(f, "import"),
+            Token::Keyword(Keyword::Extern) => write!(f, "extern"),
+            Token::Keyword(Keyword::Store) => write!(f, "store"),
+            Token::Keyword(Keyword::Fetch) => write!(f, "fetch"),
+            Token::Keyword(Keyword::Update) => write!(f, "update"),
+            Token::Keyword(Keyword::Thunk) => write!(f, "thunk"),
+            Token::Keyword(Keyword::Force) => write!(f, "force"),
+            Token::Keyword(Keyword::Throw) => write!(f, "throw"),
+            Token::Keyword(Keyword::Catch) => write!(f, "catch"),
+            Token::Keyword(Keyword::With) => write!(f, "with"),
+            Token::Keyword(Keyword::Perform) => write!(f, "perform"),
+            Token::Keyword(Keyword::Handle) => write!(f, "handle"),
+            Token::Keyword(Keyword::Resume) => write!(f, "resume"),
             Token::Identifier(name) => write!(f, "{}", name),
             Token::Symbol(c) => write!(f, "{}", c.to_char()),
             Token::Number(num) => write!(f, "{}", num),
+            Token::Float(x) => write!(f, "{:?}", x),
+            Token::Primitive(name) => write!(f, "#{}", name),
+            Token::String(bytes) => write!(f, "{}", str_to_str(bytes)),
             Token::Paren(c) => write!(f, "{}", c.to_char()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{Definition, Expression};
+    use crate::generate::{self, Rng};
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    fn parse(source: &str) -> Vec<Definition> {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        ParserScanner::new(tokens).map(|m| m.unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_pretty_is_identity() {
+        for seed in 0..500 {
+            let defs = generate::module(&mut Rng::new(seed));
+            let source = defs
+                .iter()
+                .map(|def| format!("{:?}", def.pretty()))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            assert_eq!(parse(&source), defs, "seed {seed}:\n{source}");
+        }
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let source = format!(
+            "fn (main) {{\n  {:?}\n}}",
+            Expression::Str(bytes.clone()).pretty()
+        );
+        assert_eq!(parse(&source)[0].body, Expression::Str(bytes));
+        let source = "fn (main) {\n  \"\\t\\\"\\x41\u{e9}\"\n}";
+        let expected = Expression::Str(vec![b'\t', b'"', b'A', 0xc3, 0xa9]);
+        assert_eq!(parse(source)[0].body, expected);
+    }
+}
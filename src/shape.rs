@@ -0,0 +1,362 @@
+use crate::expr::{Atom, Definition, Expression, Operation, Primitive, Type};
+use crate::module::Module;
+use std::collections::HashMap;
+use std::fmt;
+
+// The shape of a value, ordered from least to most general. `Unknown` is the shape of values
+// that were never seen, such as parameters of functions that are never called
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Unknown,
+    Num,
+    // Closure with a known remaining arity
+    Closure(usize),
+    // Closure whose remaining arity differs between paths
+    AnyClosure,
+    Any,
+}
+
+impl Shape {
+    fn join(self, other: Shape) -> Shape {
+        use Shape::*;
+        match (self, other) {
+            (Unknown, s) | (s, Unknown) => s,
+            (Num, Num) => Num,
+            (Closure(x), Closure(y)) if x == y => Closure(x),
+            (Closure(_) | AnyClosure, Closure(_) | AnyClosure) => AnyClosure,
+            _ => Any,
+        }
+    }
+
+    fn is_closure(self) -> bool {
+        matches!(self, Shape::Closure(_) | Shape::AnyClosure)
+    }
+}
+
+#[derive(Debug)]
+pub enum ShapeErrorKind {
+    // `apply` on a variable that holds a number
+    ApplyNumber(String),
+    // `match` on a variable that holds a closure
+    MatchClosure(String),
+    // Primitive operation on a variable that holds a closure
+    OperateClosure(Operation, String),
+    // Primitive of the runtime given a closure where it expects a number or a string
+    PrimitiveClosure(Primitive, String),
+}
+
+#[derive(Debug)]
+pub struct ShapeError {
+    pub function: String,
+    pub kind: ShapeErrorKind,
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "In function `{}`: ", self.function)?;
+        match &self.kind {
+            ShapeErrorKind::ApplyNumber(x) => {
+                write!(f, "`apply` on `{x}`, which is a number")
+            }
+            ShapeErrorKind::MatchClosure(x) => {
+                write!(f, "`match` on `{x}`, which is a closure")
+            }
+            ShapeErrorKind::OperateClosure(op, x) => write!(
+                f,
+                "operation `{:?}` on `{x}`, which is a closure",
+                op.pretty()
+            ),
+            ShapeErrorKind::PrimitiveClosure(prim, x) => {
+                write!(
+                    f,
+                    "primitive `#{}` on `{x}`, which is a closure",
+                    prim.name()
+                )
+            }
+        }
+    }
+}
+
+// Infers the shape of every variable by propagating shapes from call sites to parameters and
+// from bodies to return values until a fixpoint is reached. Only errors that would certainly
+// happen if the offending expression is evaluated are reported
+pub fn check(module: &Module) -> Result<(), Vec<ShapeError>> {
+    let mut defs = module.toplevel().values().collect::<Vec<_>>();
+    defs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut analysis = Analysis {
+        module,
+        params: defs
+            .iter()
+            .map(|def| (def.name.as_str(), vec![Shape::Unknown; def.params.len()]))
+            .collect(),
+        returns: defs
+            .iter()
+            .map(|def| (def.name.as_str(), Shape::Unknown))
+            .collect(),
+        changed: true,
+        errors: None,
+    };
+    while analysis.changed {
+        analysis.changed = false;
+        defs.iter().for_each(|def| analysis.definition(def));
+    }
+    analysis.errors = Some(vec![]);
+    defs.iter().for_each(|def| analysis.definition(def));
+    match analysis.errors {
+        Some(errors) if !errors.is_empty() => Err(errors),
+        _ => Ok(()),
+    }
+}
+
+struct Analysis<'a> {
+    module: &'a Module,
+    params: HashMap<&'a str, Vec<Shape>>,
+    returns: HashMap<&'a str, Shape>,
+    changed: bool,
+    // Errors are only collected once the fixpoint is reached
+    errors: Option<Vec<ShapeError>>,
+}
+
+impl<'a> Analysis<'a> {
+    fn definition(&mut self, def: &'a Definition) {
+        let mut env = HashMap::new();
+        for (param, shape) in def.params.iter().zip(self.params[def.name.as_str()].iter()) {
+            env.insert(param.as_str(), *shape);
+        }
+        let shape = self.expr(&def.name, &def.body, &mut env);
+        self.flow_return(&def.name, shape);
+    }
+
+    fn flow_return(&mut self, func: &'a str, shape: Shape) {
+        let ret = self.returns.get_mut(func).unwrap();
+        let joined = ret.join(shape);
+        if joined != *ret {
+            *ret = joined;
+            self.changed = true;
+        }
+    }
+
+    fn flow_param(&mut self, func: &str, index: usize, shape: Shape) {
+        let param = &mut self.params.get_mut(func).unwrap()[index];
+        let joined = param.join(shape);
+        if joined != *param {
+            *param = joined;
+            self.changed = true;
+        }
+    }
+
+    fn report(&mut self, function: &str, kind: ShapeErrorKind) {
+        if let Some(errors) = &mut self.errors {
+            let function = function.to_owned();
+            errors.push(ShapeError { function, kind })
+        }
+    }
+
+    fn atom(&self, atom: &Atom, env: &HashMap<&'a str, Shape>) -> Shape {
+        match atom {
+            Atom::Var(x) => env[x.as_str()],
+            Atom::Lit(_) | Atom::Float(_) => Shape::Num,
+        }
+    }
+
+    fn expr(
+        &mut self,
+        func: &str,
+        expr: &'a Expression,
+        env: &mut HashMap<&'a str, Shape>,
+    ) -> Shape {
+        match expr {
+            Expression::Unit(atom) => self.atom(atom, env),
+            Expression::Let(x, val, body) => {
+                let shape = self.expr(func, val, env);
+                env.insert(x, shape);
+                self.expr(func, body, env)
+            }
+            Expression::Apply(f, args) => match env[f.as_str()] {
+                Shape::Num => {
+                    self.report(func, ShapeErrorKind::ApplyNumber(f.clone()));
+                    Shape::Unknown
+                }
+                Shape::Closure(arity) if args.len() < arity => Shape::Closure(arity - args.len()),
+                Shape::Unknown => Shape::Unknown,
+                _ => Shape::Any,
+            },
+            // Nothing is known about the parameters and results of extern functions
+            Expression::Call(f, _) if self.module.get(f).is_none() => Shape::Any,
+            // Thunks are neither numbers nor closures, and nothing is known about their results
+            Expression::Thunk(f, args) => {
+                if self.module.get(f).is_some() {
+                    for (i, arg) in args.iter().enumerate() {
+                        let shape = self.atom(arg, env);
+                        self.flow_param(f, i, shape);
+                    }
+                }
+                Shape::Any
+            }
+            Expression::Force(_) => Shape::Any,
+            Expression::Call(f, args) => {
+                for (i, arg) in args.iter().enumerate() {
+                    let shape = self.atom(arg, env);
+                    self.flow_param(f, i, shape);
+                }
+                self.returns[f.as_str()]
+            }
+            Expression::Papp(f, args) if self.module.get(f).is_none() => {
+                Shape::Closure(self.module.arity(f).unwrap() - args.len())
+            }
+            Expression::Papp(f, args) => {
+                let arity = self.module.get(f).unwrap().params.len();
+                for (i, arg) in args.iter().enumerate() {
+                    let shape = self.atom(arg, env);
+                    self.flow_param(f, i, shape);
+                }
+                // The remaining arguments are given by `apply`, about which nothing is known
+                (args.len()..arity).for_each(|i| self.flow_param(f, i, Shape::Any));
+                Shape::Closure(arity - args.len())
+            }
+            Expression::Match(atom, cases, default) => {
+                if let Atom::Var(x) = atom {
+                    if self.atom(atom, env).is_closure() {
+                        self.report(func, ShapeErrorKind::MatchClosure(x.clone()));
+                    }
+                }
+                let mut shape = Shape::Unknown;
+                for (_, branch) in cases {
+                    shape = shape.join(self.expr(func, branch, env));
+                }
+                if let Some(branch) = default {
+                    shape = shape.join(self.expr(func, branch, env));
+                }
+                shape
+            }
+            Expression::Operate(op, x, y) => {
+                for atom in [x, y] {
+                    if let Atom::Var(var) = atom {
+                        if self.atom(atom, env).is_closure() {
+                            let kind = ShapeErrorKind::OperateClosure(*op, var.clone());
+                            self.report(func, kind);
+                        }
+                    }
+                }
+                Shape::Num
+            }
+            Expression::Prim(prim, args) => {
+                let (params, ret) = prim.signature();
+                for (atom, param) in args.iter().zip(params) {
+                    if let Atom::Var(var) = atom {
+                        let expects_closure = matches!(param, Type::Fn(..) | Type::Var(_));
+                        if !expects_closure && self.atom(atom, env).is_closure() {
+                            let kind = ShapeErrorKind::PrimitiveClosure(*prim, var.clone());
+                            self.report(func, kind);
+                        }
+                    }
+                }
+                match (prim, ret) {
+                    // `#exit` never returns
+                    (Primitive::Exit, _) => Shape::Unknown,
+                    (_, Type::Int) => Shape::Num,
+                    _ => Shape::Any,
+                }
+            }
+            // Strings are neither numbers nor closures
+            Expression::Str(_) => Shape::Any,
+            // Nothing is known about the contents of references, which may be updated anywhere
+            Expression::Store(_) | Expression::Fetch(_) => Shape::Any,
+            Expression::Update(..) => Shape::Num,
+            // `throw` never returns, and anything can be thrown
+            Expression::Throw(_) => Shape::Unknown,
+            Expression::Catch(body, x, handler) => {
+                let shape = self.expr(func, body, env);
+                env.insert(x, Shape::Any);
+                shape.join(self.expr(func, handler, env))
+            }
+            // Continuations are neither numbers nor closures
+            Expression::Perform(_) | Expression::Resume(..) => Shape::Any,
+            Expression::Handle(body, x, k, handler) => {
+                let shape = self.expr(func, body, env);
+                env.insert(x, Shape::Any);
+                env.insert(k, Shape::Any);
+                shape.join(self.expr(func, handler, env))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    fn errors(source: &str) -> Vec<String> {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        let module = Module::new(ParserScanner::new(tokens).map(|m| m.unwrap()));
+        match check(&module) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(ShapeError::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn apply_on_a_number() {
+        // The number flows from the call site to the parameter
+        let source = "
+            fn (call f) {
+              (apply f 1)
+            }
+
+            fn (main) {
+              (call 2)
+            }";
+        assert_eq!(
+            errors(source),
+            ["In function `call`: `apply` on `f`, which is a number"]
+        );
+    }
+
+    #[test]
+    fn match_on_a_closure() {
+        let source = "
+            fn (inc x) {
+              (+ x 1)
+            }
+
+            fn (main) {
+              let f = (papp inc);
+              match f {
+                0 => { 1 }
+                _ => { (+ f 1) }
+              }
+            }";
+        assert_eq!(
+            errors(source),
+            [
+                "In function `main`: `match` on `f`, which is a closure",
+                "In function `main`: operation `+` on `f`, which is a closure",
+            ]
+        );
+    }
+
+    #[test]
+    fn values_of_either_shape_are_accepted() {
+        // `x` may be a number or a closure, so neither use certainly fails
+        let source = "
+            fn (inc x) {
+              (+ x 1)
+            }
+
+            fn (use x) {
+              match x {
+                0 => { (apply x 1) }
+                _ => { x }
+              }
+            }
+
+            fn (main) {
+              let f = (papp inc);
+              let a = (use 0);
+              (use f)
+            }";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+}
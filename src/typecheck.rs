@@ -0,0 +1,566 @@
+use crate::expr::{Atom, Definition, Expression, Type};
+use crate::module::Module;
+use std::collections::HashMap;
+use std::fmt;
+
+// Types are optional. `None` stands for a value of unknown type, such as a parameter with no
+// annotation, and is compatible with any type. Annotated definitions are thus fully checked,
+// while untyped definitions are only checked where types are known, e.g. when calling typed
+// functions with literals
+type MaybeType = Option<Type>;
+
+#[derive(Debug)]
+pub enum TypeErrorKind {
+    Mismatch(Type, Type),
+    NotAFunction(String, Type),
+    TooManyArguments(String),
+    NotAReference(String, Type),
+    NotAThunk(String, Type),
+}
+
+#[derive(Debug)]
+pub struct TypeError {
+    pub function: String,
+    pub kind: TypeErrorKind,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "In function `{}`: ", self.function)?;
+        match &self.kind {
+            TypeErrorKind::Mismatch(expected, found) => write!(
+                f,
+                "expected type `{:?}`, found `{:?}`",
+                expected.pretty(),
+                found.pretty()
+            ),
+            TypeErrorKind::NotAFunction(x, typ) => {
+                write!(
+                    f,
+                    "`{x}` has type `{:?}` and cannot be applied",
+                    typ.pretty()
+                )
+            }
+            TypeErrorKind::TooManyArguments(x) => {
+                write!(f, "`{x}` is applied to too many arguments")
+            }
+            TypeErrorKind::NotAReference(x, typ) => {
+                write!(
+                    f,
+                    "`{x}` has type `{:?}` and is not a reference",
+                    typ.pretty()
+                )
+            }
+            TypeErrorKind::NotAThunk(x, typ) => {
+                write!(f, "`{x}` has type `{:?}` and is not a thunk", typ.pretty())
+            }
+        }
+    }
+}
+
+pub fn check(module: &Module) -> Result<(), Vec<TypeError>> {
+    let mut defs = module.toplevel().values().collect::<Vec<_>>();
+    defs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut errors = vec![];
+    for def in defs {
+        let mut checker = Checker {
+            module,
+            function: &def.name,
+            env: HashMap::new(),
+            errors: &mut errors,
+        };
+        checker.definition(def);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Type variables in the signatures of functions and primitives stand for any type, and are
+// replaced at each use by unknowns, named with a `?` so that they are distinct from the type
+// variables of the definition being checked. Those only equal themselves
+fn instantiate(typ: &Type, bound: &mut Vec<String>) -> Type {
+    match typ {
+        Type::Var(name) if !bound.contains(name) => Type::Var(format!("?{name}")),
+        Type::Int | Type::Var(_) => typ.clone(),
+        Type::Fn(params, ret) => {
+            let params = params.iter().map(|param| instantiate(param, bound));
+            Type::Fn(params.collect(), instantiate(ret, bound).into())
+        }
+        Type::Con(name, args) => {
+            let args = args.iter().map(|arg| instantiate(arg, bound));
+            Type::Con(name.clone(), args.collect())
+        }
+        Type::Rec(name, body) => {
+            bound.push(name.clone());
+            let body = instantiate(body, bound);
+            bound.pop();
+            Type::Rec(name.clone(), body.into())
+        }
+    }
+}
+
+fn is_unknown(name: &str) -> bool {
+    name.starts_with('?')
+}
+
+// Replaces the type variable `name` by `by`, except where a `Rec` binds it again
+fn substitute(typ: &Type, name: &str, by: &Type) -> Type {
+    match typ {
+        Type::Var(var) if var == name => by.clone(),
+        Type::Int | Type::Var(_) => typ.clone(),
+        Type::Fn(params, ret) => {
+            let params = params.iter().map(|param| substitute(param, name, by));
+            Type::Fn(params.collect(), substitute(ret, name, by).into())
+        }
+        Type::Con(con, args) => {
+            let args = args.iter().map(|arg| substitute(arg, name, by));
+            Type::Con(con.clone(), args.collect())
+        }
+        Type::Rec(var, _) if var == name => typ.clone(),
+        Type::Rec(var, body) => Type::Rec(var.clone(), substitute(body, name, by).into()),
+    }
+}
+
+// `Rec(a, T)` is the same type as `T` where `a` is replaced by `Rec(a, T)`. Types such as
+// `Rec(a, a)` unfold to themselves, and are left as they are
+fn unfold(typ: &Type) -> Type {
+    let mut seen = vec![];
+    let mut typ = typ.clone();
+    while let Type::Rec(name, body) = &typ {
+        if seen.contains(&typ) {
+            break;
+        }
+        let next = substitute(body, name, &typ);
+        seen.push(std::mem::replace(&mut typ, next));
+    }
+    typ
+}
+
+// Unknowns of one use of a signature, with the pairs of types assumed to be equal while
+// comparing them, which makes comparisons of recursive types terminate
+#[derive(Default)]
+struct Instance {
+    bindings: HashMap<String, Type>,
+    assumed: Vec<(Type, Type)>,
+}
+
+impl Instance {
+    fn walk(&self, typ: &Type) -> Type {
+        match typ {
+            Type::Var(name) if self.bindings.contains_key(name) => self.walk(&self.bindings[name]),
+            _ => typ.clone(),
+        }
+    }
+
+    // The type with the unknowns that were found, or `None` if some remain unknown
+    fn resolve(&self, typ: &Type) -> MaybeType {
+        Some(match self.walk(typ) {
+            Type::Var(name) if is_unknown(&name) => return None,
+            typ @ (Type::Int | Type::Var(_)) => typ,
+            Type::Fn(params, ret) => {
+                let params = params.iter().map(|param| self.resolve(param));
+                Type::Fn(params.collect::<Option<_>>()?, self.resolve(&ret)?.into())
+            }
+            Type::Con(name, args) => {
+                let args = args.iter().map(|arg| self.resolve(arg));
+                Type::Con(name, args.collect::<Option<_>>()?)
+            }
+            Type::Rec(name, body) => Type::Rec(name, self.resolve(&body)?.into()),
+        })
+    }
+
+    // The type for error messages, where unknowns are shown as the type variables of the
+    // signature
+    fn show(&self, typ: &Type) -> Type {
+        match self.walk(typ) {
+            Type::Var(name) => Type::Var(name.trim_start_matches('?').to_owned()),
+            Type::Int => Type::Int,
+            Type::Fn(params, ret) => {
+                let params = params.iter().map(|param| self.show(param));
+                Type::Fn(params.collect(), self.show(&ret).into())
+            }
+            Type::Con(name, args) => {
+                Type::Con(name, args.iter().map(|arg| self.show(arg)).collect())
+            }
+            Type::Rec(name, body) => Type::Rec(name, self.show(&body).into()),
+        }
+    }
+
+    fn occurs(&self, name: &str, typ: &Type) -> bool {
+        match self.walk(typ) {
+            Type::Var(var) => var == name,
+            Type::Int => false,
+            Type::Fn(params, ret) => {
+                params.iter().any(|param| self.occurs(name, param)) || self.occurs(name, &ret)
+            }
+            Type::Con(_, args) => args.iter().any(|arg| self.occurs(name, arg)),
+            Type::Rec(_, body) => self.occurs(name, &body),
+        }
+    }
+
+    fn unify(&mut self, x: &Type, y: &Type) -> bool {
+        let (x, y) = (self.walk(x), self.walk(y));
+        if x == y || self.assumed.contains(&(x.clone(), y.clone())) {
+            return true;
+        }
+        match (&x, &y) {
+            (Type::Var(name), typ) | (typ, Type::Var(name)) if is_unknown(name) => {
+                if self.occurs(name, typ) {
+                    return false;
+                }
+                self.bindings.insert(name.clone(), typ.clone());
+                true
+            }
+            (Type::Rec(..), _) | (_, Type::Rec(..)) => {
+                self.assumed.push((x.clone(), y.clone()));
+                self.unify(&unfold(&x), &unfold(&y))
+            }
+            (Type::Fn(xs, x), Type::Fn(ys, y)) if xs.len() == ys.len() => {
+                xs.iter().zip(ys).all(|(x, y)| self.unify(x, y)) && self.unify(x, y)
+            }
+            (Type::Con(n, xs), Type::Con(m, ys)) if n == m && xs.len() == ys.len() => {
+                xs.iter().zip(ys).all(|(x, y)| self.unify(x, y))
+            }
+            _ => false,
+        }
+    }
+}
+
+struct Checker<'a> {
+    module: &'a Module,
+    function: &'a str,
+    env: HashMap<&'a str, MaybeType>,
+    errors: &'a mut Vec<TypeError>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, kind: TypeErrorKind) {
+        let function = self.function.to_owned();
+        self.errors.push(TypeError { function, kind })
+    }
+
+    fn expect(&mut self, expected: &MaybeType, found: &MaybeType) {
+        self.expect_in(&mut Instance::default(), expected, found)
+    }
+
+    fn expect_in(&mut self, instance: &mut Instance, expected: &MaybeType, found: &MaybeType) {
+        if let (Some(expected), Some(found)) = (expected, found) {
+            if !instance.unify(expected, found) {
+                let expected = instance.show(expected);
+                self.report(TypeErrorKind::Mismatch(expected, found.clone()))
+            }
+        }
+    }
+
+    // Checks the arguments of a use of a signature, and gives the types of its remaining
+    // parameters and of its result, which are unknown where they depend on unknown arguments
+    fn instance(
+        &mut self,
+        params: &[MaybeType],
+        ret: &MaybeType,
+        args: &[Atom],
+    ) -> (Vec<MaybeType>, MaybeType) {
+        let mut instance = Instance::default();
+        let fresh = |typ: &MaybeType| typ.as_ref().map(|typ| instantiate(typ, &mut vec![]));
+        let params = params.iter().map(fresh).collect::<Vec<_>>();
+        for (param, arg) in params.iter().zip(args.iter()) {
+            let typ = self.atom(arg);
+            self.expect_in(&mut instance, param, &typ);
+        }
+        let resolve = |typ: &MaybeType| instance.resolve(typ.as_ref()?);
+        let rest = params[args.len().min(params.len())..].iter().map(resolve);
+        (rest.collect(), resolve(&fresh(ret)))
+    }
+
+    fn definition(&mut self, def: &'a Definition) {
+        for (param, typ) in def.params.iter().zip(def.param_types.iter()) {
+            self.env.insert(param, typ.clone());
+        }
+        let typ = self.expr(&def.body);
+        self.expect(&def.ret_type, &typ);
+    }
+
+    fn atom(&self, atom: &Atom) -> MaybeType {
+        match atom {
+            Atom::Var(x) => self.env[x.as_str()].clone(),
+            Atom::Lit(_) => Some(Type::Int),
+            Atom::Float(_) => Some(Type::float()),
+        }
+    }
+
+    fn args(&mut self, params: &[MaybeType], args: &[Atom]) {
+        for (param, arg) in params.iter().zip(args.iter()) {
+            let typ = self.atom(arg);
+            self.expect(param, &typ);
+        }
+    }
+
+    // Over-application applies the result to the remaining arguments
+    fn apply(&mut self, f: &str, typ: MaybeType, args: &[Atom], over: bool) -> MaybeType {
+        match unfold(&typ?) {
+            Type::Fn(params, ret) => {
+                let params = params.into_iter().map(Some).collect::<Vec<_>>();
+                self.args(&params, args);
+                if args.len() < params.len() {
+                    let rest = params.into_iter().skip(args.len()).flatten();
+                    return Some(Type::Fn(rest.collect(), ret));
+                }
+                match &args[params.len()..] {
+                    [] => Some(*ret),
+                    rest => self.apply(f, Some(*ret), rest, true),
+                }
+            }
+            _ if over => {
+                self.report(TypeErrorKind::TooManyArguments(f.to_owned()));
+                None
+            }
+            other => {
+                self.report(TypeErrorKind::NotAFunction(f.to_owned(), other));
+                None
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expression) -> MaybeType {
+        match expr {
+            Expression::Unit(atom) => self.atom(atom),
+            Expression::Let(x, val, body) => {
+                let typ = self.expr(val);
+                self.env.insert(x, typ);
+                self.expr(body)
+            }
+            Expression::Apply(f, args) => {
+                let typ = self.env[f.as_str()].clone();
+                self.apply(f, typ, args, false)
+            }
+            Expression::Call(f, args) => {
+                let (param_types, ret_type) = self.module.signature(f).unwrap();
+                self.instance(param_types, ret_type, args).1
+            }
+            Expression::Papp(f, args) => {
+                let (param_types, ret_type) = self.module.signature(f).unwrap();
+                let (rest, ret) = self.instance(param_types, ret_type, args);
+                let rest = rest.into_iter().collect::<Option<Vec<_>>>()?;
+                Some(Type::Fn(rest, ret?.into()))
+            }
+            Expression::Match(atom, cases, default) => {
+                let typ = self.atom(atom);
+                self.expect(&Some(Type::Int), &typ);
+                let branches = cases.iter().map(|(_, e)| e).chain(default.as_deref());
+                let mut result = None;
+                for branch in branches {
+                    let typ = self.expr(branch);
+                    self.expect(&result, &typ);
+                    result = result.or(typ);
+                }
+                result
+            }
+            Expression::Operate(op, x, y) => {
+                let (operand, result) = op.signature();
+                for atom in [x, y] {
+                    let typ = self.atom(atom);
+                    self.expect(&Some(operand.clone()), &typ);
+                }
+                Some(result)
+            }
+            Expression::Prim(prim, args) => {
+                let (params, ret) = prim.signature();
+                let params = params.into_iter().map(Some).collect::<Vec<_>>();
+                self.instance(&params, &Some(ret), args).1
+            }
+            Expression::Str(_) => Some(Type::str()),
+            Expression::Store(x) => Some(Type::reference(self.atom(x)?)),
+            Expression::Fetch(p) => match unfold(&self.env[p.as_str()].clone()?) {
+                Type::Con(name, mut args) if name == "Ref" && args.len() == 1 => args.pop(),
+                typ => {
+                    self.report(TypeErrorKind::NotAReference(p.clone(), typ));
+                    None
+                }
+            },
+            Expression::Thunk(f, args) => {
+                let (param_types, ret_type) = self.module.signature(f).unwrap();
+                let ret = self.instance(param_types, ret_type, args).1;
+                Some(Type::lazy(ret?))
+            }
+            Expression::Force(t) => match unfold(&self.env[t.as_str()].clone()?) {
+                Type::Con(name, mut args) if name == "Lazy" && args.len() == 1 => args.pop(),
+                typ => {
+                    self.report(TypeErrorKind::NotAThunk(t.clone(), typ));
+                    None
+                }
+            },
+            Expression::Update(p, x) => {
+                match self.env[p.as_str()].as_ref().map(unfold) {
+                    Some(Type::Con(name, args)) if name == "Ref" && args.len() == 1 => {
+                        let typ = self.atom(x);
+                        self.expect(&Some(args[0].clone()), &typ);
+                    }
+                    Some(typ) => self.report(TypeErrorKind::NotAReference(p.clone(), typ)),
+                    None => {}
+                }
+                Some(Type::Int)
+            }
+            // The type of thrown values is unknown, like the result of `throw`
+            Expression::Throw(_) => None,
+            Expression::Catch(body, x, handler) => {
+                let typ = self.expr(body);
+                self.env.insert(x, None);
+                let handled = self.expr(handler);
+                self.expect(&typ, &handled);
+                typ.or(handled)
+            }
+            Expression::Perform(_) | Expression::Resume(..) => None,
+            Expression::Handle(body, x, k, handler) => {
+                let typ = self.expr(body);
+                self.env.insert(x, None);
+                self.env.insert(k, None);
+                let handled = self.expr(handler);
+                self.expect(&typ, &handled);
+                typ.or(handled)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Scanner as LexerScanner;
+    use crate::parser::Scanner as ParserScanner;
+
+    fn errors(source: &str) -> Vec<String> {
+        let tokens = LexerScanner::new(source).map(|m| m.unwrap());
+        let module = Module::new(ParserScanner::new(tokens).map(|m| m.unwrap()));
+        match check(&module) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(TypeError::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn well_typed_programs_are_accepted() {
+        // Definitions without annotations are only checked where types are known
+        let source = "
+            fn (twice f: Fn(Int) -> Int x: Int) -> Int {
+              let y = (apply f x);
+              (apply f y)
+            }
+
+            fn (inc x: Int) -> Int {
+              (+ x 1)
+            }
+
+            fn (counter n: Int) -> Ref(Int) {
+              (store n)
+            }
+
+            fn (untyped x) {
+              (apply x 1 2)
+            }
+
+            fn (main) -> Int {
+              let f = (papp inc);
+              let r = (counter 0);
+              let n = (fetch r);
+              let t = (thunk inc n);
+              let m = (force t);
+              (twice f m)
+            }";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn ill_typed_programs_are_rejected() {
+        let source = "
+            fn (inc x: Int) -> Int {
+              (+ x 1)
+            }
+
+            fn (main) -> Int {
+              let n = 1;
+              let a = (apply n 2);
+              let f = (papp inc);
+              let b = (apply f 1 2);
+              let c = (fetch n);
+              let d = (force n);
+              let e = (inc 1.5);
+              (inc f)
+            }";
+        assert_eq!(
+            errors(source),
+            [
+                "In function `main`: `n` has type `Int` and cannot be applied",
+                "In function `main`: `f` is applied to too many arguments",
+                "In function `main`: `n` has type `Int` and is not a reference",
+                "In function `main`: `n` has type `Int` and is not a thunk",
+                "In function `main`: expected type `Int`, found `Float`",
+                "In function `main`: expected type `Int`, found `Fn(Int) -> Int`",
+            ]
+        );
+    }
+
+    #[test]
+    fn inferred_signatures_are_accepted() {
+        // As printed by `--infer` for the same definitions without annotations
+        let source = "
+            fn (stream n: Int) -> Rec(a, Fn() -> a) {
+              let next = (+ n 1);
+              (papp stream next)
+            }
+
+            fn (id x: a) -> a {
+              x
+            }
+
+            // The same type as the result of `stream`, up to unfolding
+            fn (evens) -> Rec(b, Fn() -> Fn() -> b) {
+              (stream 0)
+            }
+
+            fn (main) -> Int {
+              let s = (stream 0);
+              let t = (apply s);
+              let f = (id 1.5);
+              (id 1)
+            }";
+        assert_eq!(errors(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn type_variables_are_instantiated_per_use() {
+        let source = "
+            fn (id x: a) -> a {
+              x
+            }
+
+            fn (const x: a y: b) -> a {
+              y
+            }
+
+            fn (main) -> Float {
+              (id 1)
+            }";
+        assert_eq!(
+            errors(source),
+            [
+                "In function `const`: expected type `a`, found `b`",
+                "In function `main`: expected type `Float`, found `Int`",
+            ]
+        );
+        let source = "
+            fn (same x: a y: a) -> a {
+              x
+            }
+
+            fn (main) -> Int {
+              (same 1 2.5)
+            }";
+        assert_eq!(
+            errors(source),
+            ["In function `main`: expected type `Int`, found `Float`"]
+        );
+    }
+}